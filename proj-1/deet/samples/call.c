@@ -0,0 +1,23 @@
+#include <stdio.h>
+
+int counter = 0;
+
+int add(int a, int b) {
+    return a + b;
+}
+
+double average(int a, int b) {
+    return (a + b) / 2.0;
+}
+
+void bump(void) {
+    counter++;
+}
+
+int main() {
+    bump();
+    int sum = add(1, 2);
+    double mean = average(1, 2);
+    printf("%d %f\n", sum, mean);
+    return 0;
+}
@@ -0,0 +1,19 @@
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main(int argc, char *argv[]) {
+    if (argc > 1) {
+        printf("exec'd with %s\n", argv[1]);
+        return 0;
+    }
+    pid_t pid = fork();
+    if (pid == 0) {
+        printf("child\n");
+        return 0;
+    }
+    waitpid(pid, NULL, 0);
+    execl("/proc/self/exe", argv[0], "again", NULL);
+    perror("execl");
+    return 1;
+}
@@ -0,0 +1,11 @@
+#include <stdio.h>
+
+int main() {
+    volatile char buffer[64];
+    buffer[0] = 1;
+    // mov %eax,-0x34(%rbp), which ends in the same byte as an int3
+    asm volatile(".byte 0x89, 0x45, 0xcc" ::: "memory");
+    buffer[1] = 2;
+    printf("%d\n", buffer[0] + buffer[1]);
+    return 0;
+}
@@ -0,0 +1,17 @@
+#include <stdio.h>
+
+int factorial(int n) {
+    if (n <= 1)
+        return 1;
+    return n * factorial(n - 1);
+}
+
+int main() {
+    int total = 0;
+    for (int i = 0; i < 5; i++) {
+        total += i;
+    }
+    printf("%d\n", total);
+    printf("%d\n", factorial(4));
+    return 0;
+}
@@ -0,0 +1,9 @@
+#include <stdio.h>
+
+void (*handler)(void) = NULL;
+
+int main() {
+    printf("calling a null function pointer\n");
+    handler();
+    return 0;
+}
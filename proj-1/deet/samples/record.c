@@ -0,0 +1,18 @@
+#include <stdlib.h>
+#include <string.h>
+
+int total = 0;
+int *heap;
+
+void fill(void) {
+    memset(heap, 0xff, 2 * sizeof(int));
+    heap[0] = 1;
+    total = heap[0] + 1;
+}
+
+int main() {
+    heap = calloc(2, sizeof(int));
+    fill();
+    free(heap);
+    return 0;
+}
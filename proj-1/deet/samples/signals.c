@@ -0,0 +1,16 @@
+#include <signal.h>
+
+volatile sig_atomic_t received = 0;
+
+void handler(int sig) {
+    received = sig;
+}
+
+void ready(void) {
+}
+
+int main() {
+    signal(SIGUSR1, handler);
+    ready();
+    return received == SIGUSR1 ? 0 : 1;
+}
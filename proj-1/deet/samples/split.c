@@ -0,0 +1,14 @@
+#include <stdio.h>
+
+int calls = 0;
+
+int twice(int n) {
+    calls++;
+    return 2 * n;
+}
+
+int main() {
+    int result = twice(21);
+    printf("%d\n", result);
+    return 0;
+}
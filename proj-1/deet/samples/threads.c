@@ -0,0 +1,30 @@
+#include <pthread.h>
+#include <stdio.h>
+#include <unistd.h>
+
+#define NUM_THREADS 3
+
+int square(int n) {
+    return n * n;
+}
+
+void *worker(void *arg) {
+    long id = (long)arg;
+    for (int i = 0; i < 3; i++) {
+        printf("worker %ld: %d\n", id, i);
+        usleep(1000);
+    }
+    return NULL;
+}
+
+int main() {
+    pthread_t threads[NUM_THREADS];
+    for (long i = 0; i < NUM_THREADS; i++) {
+        pthread_create(&threads[i], NULL, worker, (void *)i);
+    }
+    for (int i = 0; i < NUM_THREADS; i++) {
+        pthread_join(threads[i], NULL);
+    }
+    printf("all workers done\n");
+    return 0;
+}
@@ -0,0 +1,265 @@
+//! Tab completion and inline hints for the command line. The first word completes to a command,
+//! and the rest depends on the command: function names and source files (for `file:line`) where a
+//! location goes, variable names for `print`, and filesystem paths for `run` and other commands
+//! that take a file. A hint shows the rest of the word when only one completion is left.
+
+use crate::debugger_command::{find_command, find_subcommand, COMMANDS};
+use crate::dwarf_data::{DwarfData, Function, Scope};
+use crate::syscalls;
+use nix::sys::signal::Signal;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+
+/// What the word being typed is, going by the words before it.
+enum Argument {
+    Command,
+    Subcommand(&'static [&'static str]),
+    Location,
+    Function,
+    Variable,
+    Path,
+    Syscall,
+    Signal,
+    Nothing,
+}
+
+fn argument_kind(words: &[&str]) -> Argument {
+    if words.is_empty() {
+        return Argument::Command;
+    }
+    // commands are known by any of their abbreviations
+    let spec = match find_command(words[0]) {
+        Ok(spec) => spec,
+        Err(_) => return Argument::Nothing,
+    };
+    if words.len() == 1 && !spec.subcommands.is_empty() {
+        return Argument::Subcommand(spec.subcommands);
+    }
+    let subcommand = words.get(1).and_then(|word| find_subcommand(spec, word)).unwrap_or("");
+    match (spec.name, subcommand) {
+        ("help", _) if words.len() == 1 => Argument::Command,
+        ("run", _) => Argument::Path,
+        ("break", _) | ("tbreak", _) | ("until", _) | ("advance", _) => Argument::Location,
+        ("print", _) | ("return", _) => Argument::Variable,
+        ("call", _) => Argument::Function,
+        ("info", "line") => Argument::Location,
+        ("info", "address") => Argument::Variable,
+        ("catch", "syscall") => Argument::Syscall,
+        ("catch", "signal") => Argument::Signal,
+        ("save", _) | ("set", "cwd") | ("set", "debug-file-directory") | ("set", "inferior-tty") => Argument::Path,
+        _ => Argument::Nothing,
+    }
+}
+
+pub struct DeetHelper {
+    functions: Vec<String>,
+    variables: Vec<String>,
+    // source files, by their full names
+    files: Vec<String>,
+    filenames: FilenameCompleter,
+}
+
+/// The candidates among `names` that start with `word`, sorted and without duplicates, each
+/// followed by `suffix`.
+fn matching<'a>(names: impl Iterator<Item = &'a str>, word: &str, suffix: &str) -> Vec<Pair> {
+    let mut names: Vec<&str> = names.filter(|name| name.starts_with(word)).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| Pair { display: name.to_string(), replacement: format!("{}{}", name, suffix) })
+        .collect()
+}
+
+/// Adds the names of the variables of `scopes`, and of the scopes nested in them, to `names`.
+fn scope_variables<'a>(scopes: &'a [Scope], names: &mut Vec<&'a str>) {
+    for scope in scopes {
+        names.extend(scope.variables.iter().map(|var| var.name.as_str()));
+        scope_variables(&scope.scopes, names);
+    }
+}
+
+/// The names of the parameters and local variables of `func`, including those of its lexical
+/// blocks and of the functions inlined into it.
+fn function_variables(func: &Function) -> Vec<&str> {
+    let mut names: Vec<&str> = func.variables.iter().map(|var| var.name.as_str()).collect();
+    scope_variables(&func.scopes, &mut names);
+    names
+}
+
+impl DeetHelper {
+    /// Completes the names in `debug_data`.
+    pub fn new(debug_data: &DwarfData) -> DeetHelper {
+        let functions = debug_data
+            .files()
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            .chain(debug_data.symbols().iter())
+            .map(|func| func.name.clone())
+            .collect();
+        let locals = debug_data.files().iter().flat_map(|file| file.functions.iter()).flat_map(function_variables);
+        let globals = debug_data.global_variables().map(|var| var.name.as_str());
+        let variables = globals.chain(locals).map(String::from).collect();
+        DeetHelper {
+            functions,
+            variables,
+            files: debug_data.files().iter().map(|file| file.name.clone()).collect(),
+            filenames: FilenameCompleter::new(),
+        }
+    }
+
+    /// Where the word before `pos` starts, and its completions.
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<Pair>) {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &before[start..];
+        let words: Vec<&str> = before[..start].split_whitespace().collect();
+        let candidates = match argument_kind(&words) {
+            Argument::Command => matching(COMMANDS.iter().map(|spec| spec.name), word, " "),
+            Argument::Subcommand(names) => matching(names.iter().copied(), word, " "),
+            Argument::Location => {
+                // a file is named by its base name unless a directory is typed too
+                let files = self.files.iter().map(|file| {
+                    if word.contains('/') {
+                        file.as_str()
+                    } else {
+                        file.rsplit('/').next().unwrap()
+                    }
+                });
+                let mut candidates = matching(self.functions.iter().map(|f| f.as_str()), word, "");
+                candidates.extend(matching(files, word, ":"));
+                candidates
+            }
+            Argument::Function => matching(self.functions.iter().map(|f| f.as_str()), word, "("),
+            Argument::Variable => matching(self.variables.iter().map(|v| v.as_str()), word, ""),
+            Argument::Syscall => {
+                let names = (0..1000).filter_map(syscalls::name);
+                matching(names, word, " ")
+            }
+            Argument::Signal => {
+                let names: Vec<&str> = Signal::iterator().map(|signal| signal.as_str()).collect();
+                matching(names.into_iter(), word, " ")
+            }
+            Argument::Path => return self.filenames.complete_path(line, pos).unwrap_or((start, Vec::new())),
+            Argument::Nothing => Vec::new(),
+        };
+        (start, candidates)
+    }
+
+    /// The rest of the word being typed at the end of the line, if there is only one way to
+    /// complete it. Paths are not hinted, to keep the filesystem out of every keystroke.
+    fn hint_text(&self, line: &str, pos: usize) -> Option<String> {
+        if pos < line.len() || line.ends_with(char::is_whitespace) {
+            return None;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let Argument::Path = argument_kind(&words[..words.len().saturating_sub(1)]) {
+            return None;
+        }
+        let (start, candidates) = self.candidates(line, pos);
+        match candidates.as_slice() {
+            [only] => {
+                let rest = only.replacement[pos - start..].trim_end();
+                if rest.is_empty() {
+                    None
+                } else {
+                    Some(rest.to_string())
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Completer for DeetHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for DeetHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        self.hint_text(line, pos)
+    }
+}
+
+impl Highlighter for DeetHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        // dimmed
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+}
+
+impl Validator for DeetHelper {}
+
+impl Helper for DeetHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::{InlinedCall, Location, TypeKind, Variable};
+    use crate::values::test::make_type;
+    use std::collections::HashMap;
+
+    fn replacements(helper: &DeetHelper, line: &str) -> Vec<String> {
+        helper.candidates(line, line.len()).1.into_iter().map(|pair| pair.replacement).collect()
+    }
+
+    #[test]
+    fn test_candidates() {
+        let mut helper = DeetHelper::new(&DwarfData::from_types(HashMap::new()));
+        helper.functions = vec!["main".to_string(), "make_list".to_string(), "free_list".to_string()];
+        helper.variables = vec!["counter".to_string(), "count".to_string()];
+        helper.files = vec!["/src/main.c".to_string(), "/src/list.c".to_string()];
+        assert_eq!(replacements(&helper, "tb"), vec!["tbreak "]);
+        assert_eq!(replacements(&helper, "re"), vec!["record ", "restart ", "return ", "reverse-continue ", "reverse-next ", "reverse-step "]);
+        assert_eq!(replacements(&helper, "info fu"), vec!["functions "]);
+        assert_eq!(replacements(&helper, "break ma"), vec!["main", "make_list", "main.c:"]);
+        assert_eq!(replacements(&helper, "b /src/l"), vec!["/src/list.c:"]);
+        assert_eq!(replacements(&helper, "print cou"), vec!["count", "counter"]);
+        assert_eq!(replacements(&helper, "catch syscall wri"), vec!["write ", "writev "]);
+        assert_eq!(replacements(&helper, "catch signal SIGSE"), vec!["SIGSEGV "]);
+        assert!(replacements(&helper, "continue x").is_empty());
+        assert_eq!(replacements(&helper, "tb ma"), vec!["main", "make_list", "main.c:"]);
+        assert_eq!(replacements(&helper, "help adv"), vec!["advance "]);
+        assert_eq!(replacements(&helper, "call fr"), vec!["free_list("]);
+        assert_eq!(helper.hint_text("brea", 4), Some("k".to_string()));
+        assert_eq!(helper.hint_text("b make", 6), Some("_list".to_string()));
+        assert_eq!(helper.hint_text("b ma", 4), None);
+        assert_eq!(helper.hint_text("break ", 6), None);
+    }
+
+    #[test]
+    fn test_function_variables() {
+        let variable = |name: &str| Variable {
+            name: name.to_string(),
+            entity_type: make_type("int", 4, TypeKind::Base(0x05), false),
+            location: Location::FramePointerOffset(-4),
+            line_number: 0,
+        };
+        let inlined = Scope {
+            variables: vec![variable("square_arg")],
+            inlined: Some(InlinedCall::default()),
+            ..Scope::default()
+        };
+        let block = Scope {
+            variables: vec![variable("i")],
+            scopes: vec![inlined],
+            ..Scope::default()
+        };
+        let func = Function {
+            variables: vec![variable("argc")],
+            scopes: vec![block],
+            ..Function::default()
+        };
+        assert_eq!(function_variables(&func), vec!["argc", "i", "square_arg"]);
+    }
+}
@@ -0,0 +1,556 @@
+//! A Debug Adapter Protocol server (`deet --dap`), so that editors can drive deet.
+//!
+//! Messages are exchanged over stdin/stdout, each one a JSON body preceded by a `Content-Length`
+//! header. The requests editors need for basic debugging are mapped onto the same `Inferior` and
+//! `DwarfData` machinery the command line uses: `setBreakpoints`, `setFunctionBreakpoints`,
+//! `continue`, `next`, `threads`, `stackTrace`, `scopes` and `variables`. Stops and exits are
+//! reported with `stopped`, `exited` and `terminated` events.
+//!
+//! The inferior's output goes to deet's stderr, since stdout carries the protocol.
+
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Variable};
+use crate::inferior::{Frame, Inferior, Status};
+use crate::printers::Printers;
+use crate::values;
+use nix::fcntl::{self, OFlag};
+use nix::sys::signal::Signal;
+use nix::sys::stat::Mode;
+use nix::unistd;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::FromRawFd;
+
+/// `variablesReference` of the globals scope. The locals of frame N use LOCALS_REFERENCE + N.
+const GLOBALS_REFERENCE: u64 = 1;
+const LOCALS_REFERENCE: u64 = 1000;
+
+/// Key under which function breakpoints are kept in `DapServer::requested_breakpoints`.
+const FUNCTION_BREAKPOINTS: &str = "<functions>";
+
+/// The largest message body we accept, so that a bad Content-Length can not make us allocate
+/// without bound
+const MAX_MESSAGE_LENGTH: usize = 16 << 20;
+
+/// Reads one message. Returns None at end of input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if line.to_ascii_lowercase().starts_with("content-length:") {
+            content_length = line["content-length:".len()..].trim().parse().ok();
+        }
+    }
+    let content_length = content_length.unwrap();
+    if content_length > MAX_MESSAGE_LENGTH {
+        let text = format!("message of {} bytes is too long", content_length);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, text));
+    }
+    let mut body = vec![0; content_length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+struct DapServer<W: Write> {
+    output: W,
+    seq: u64,
+    target: Option<String>,
+    debug_data: Option<DwarfData>,
+    // pretty-printers for the types of debug_data
+    printers: Printers,
+    inferior: Option<Inferior>,
+    breakpoints: HashMap<usize, u8>,
+    // breakpoint addresses by source path (or FUNCTION_BREAKPOINTS), since each setBreakpoints
+    // request replaces all earlier breakpoints of its source
+    requested_breakpoints: HashMap<String, Vec<usize>>,
+    stop_on_entry: bool,
+    // frames of the last stackTrace request, which scopes and variables refer to
+    frames: Vec<Frame>,
+}
+
+impl<W: Write> DapServer<W> {
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    fn debug_data(&self) -> Result<&DwarfData, String> {
+        self.debug_data
+            .as_ref()
+            .ok_or_else(|| "No program has been launched".to_string())
+    }
+
+    fn inferior(&mut self) -> Result<&mut Inferior, String> {
+        self.inferior
+            .as_mut()
+            .ok_or_else(|| "The program is not being run".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let target = match args["program"].as_str() {
+            Some(program) => program.to_string(),
+            None => self.target.clone().ok_or("No program to debug")?,
+        };
+        let debug_data = match DwarfData::from_file(&target) {
+            Ok(val) => val,
+            Err(DwarfError::ErrorOpeningFile) => return Err(format!("Could not open file {}", target)),
+            Err(DwarfError::DwarfFormatError(err)) => {
+                eprintln!("Could not debugging symbols from {}: {:?}", target, err);
+                DwarfData::from_symbol_table(&target).or(Err(format!("Could not open file {}", target)))?
+            }
+        };
+        let program_args: Vec<String> = args["args"]
+            .as_array()
+            .map(|a| a.iter().filter_map(|s| s.as_str()).map(|s| s.to_string()).collect())
+            .unwrap_or_default();
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        let inferior = Inferior::new(&target, &program_args, &mut self.breakpoints)
+            .ok_or("Error starting subprocess")?;
+        self.printers = Printers::for_program(&debug_data);
+        self.debug_data = Some(debug_data);
+        self.inferior = Some(inferior);
+        self.target = Some(target);
+        Ok(Value::Null)
+    }
+
+    /// Replaces the breakpoints stored under `key` with `addrs`, patching the running inferior.
+    fn replace_breakpoints(&mut self, key: &str, addrs: Vec<usize>) {
+        let old = self.requested_breakpoints.insert(key.to_string(), addrs.clone()).unwrap_or_default();
+        for addr in old {
+            let still_requested = self.requested_breakpoints.values().any(|v| v.contains(&addr));
+            if still_requested {
+                continue;
+            }
+            if let Some(orig) = self.breakpoints.remove(&addr) {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    let _ = inferior.write_byte(addr, orig);
+                }
+            }
+        }
+        for addr in addrs {
+            if self.breakpoints.contains_key(&addr) {
+                continue;
+            }
+            match self.inferior.as_mut() {
+                Some(inferior) => {
+                    if let Ok(orig) = inferior.write_byte(addr, 0xcc) {
+                        self.breakpoints.insert(addr, orig);
+                    }
+                }
+                // installed by Inferior::new
+                None => {
+                    self.breakpoints.insert(addr, 0);
+                }
+            }
+        }
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"].as_str().ok_or("Missing source path")?.to_string();
+        let debug_data = self.debug_data()?;
+        let mut addrs = Vec::new();
+        let mut results = Vec::new();
+        for bp in args["breakpoints"].as_array().unwrap_or(&Vec::new()) {
+            let line = bp["line"].as_u64().unwrap_or(0) as usize;
+            match debug_data.get_addr_for_line(Some(&path), line) {
+                Some(addr) => {
+                    let actual_line = debug_data.get_line_from_addr(addr).map_or(line, |l| l.number);
+                    addrs.push(addr);
+                    results.push(json!({"verified": true, "line": actual_line}));
+                }
+                None => results.push(json!({"verified": false, "line": line, "message": "Invalid line number"})),
+            }
+        }
+        self.replace_breakpoints(&path, addrs);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let debug_data = self.debug_data()?;
+        let mut addrs = Vec::new();
+        let mut results = Vec::new();
+        for bp in args["breakpoints"].as_array().unwrap_or(&Vec::new()) {
+            let name = bp["name"].as_str().unwrap_or("");
+            match debug_data.get_addr_for_function(None, name) {
+                Some(addr) => {
+                    addrs.push(addr);
+                    let mut result = json!({"verified": true});
+                    if let Some(line) = debug_data.get_line_from_addr(addr) {
+                        result["line"] = json!(line.number);
+                        result["source"] = json!({"path": line.file});
+                    }
+                    results.push(result);
+                }
+                None => results.push(json!({"verified": false, "message": "No such function"})),
+            }
+        }
+        self.replace_breakpoints(FUNCTION_BREAKPOINTS, addrs);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn threads(&mut self) -> Result<Value, String> {
+        let threads: Vec<Value> = match self.inferior.as_ref() {
+            Some(inferior) => inferior
+                .threads()
+                .iter()
+                .map(|t| json!({"id": t.id, "name": format!("Thread {}", t.tid)}))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(json!({ "threads": threads }))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let frames = {
+            let debug_data = self.debug_data.as_ref().ok_or("No program has been launched")?;
+            let inferior = self.inferior.as_ref().ok_or("The program is not being run")?;
+            inferior.backtrace(debug_data).map_err(|err| err.to_string())?
+        };
+        let debug_data = self.debug_data()?;
+        let stack_frames: Vec<Value> = frames
+            .iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let name = debug_data
+                    .get_frame_function(frame.pc, frame.inline_depth)
+                    .unwrap_or_else(|| "??".to_string());
+                let mut stack_frame = json!({"id": id, "name": name, "line": 0, "column": 0});
+                if let Some(line) = debug_data.get_frame_line(frame.pc, frame.inline_depth) {
+                    let file_name = line.file.rsplit('/').next().unwrap_or(&line.file).to_string();
+                    stack_frame["source"] = json!({"name": file_name, "path": line.file});
+                    stack_frame["line"] = json!(line.number);
+                    stack_frame["column"] = json!(1);
+                }
+                stack_frame
+            })
+            .collect();
+        let total = stack_frames.len();
+        self.frames = frames;
+        Ok(json!({"stackFrames": stack_frames, "totalFrames": total}))
+    }
+
+    fn scopes(&mut self, args: &Value) -> Result<Value, String> {
+        let frame_id = args["frameId"].as_u64().ok_or("Missing frameId")?;
+        Ok(json!({"scopes": [
+            {"name": "Locals", "variablesReference": LOCALS_REFERENCE + frame_id, "expensive": false},
+            {"name": "Globals", "variablesReference": GLOBALS_REFERENCE, "expensive": false},
+        ]}))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        let reference = args["variablesReference"].as_u64().ok_or("Missing variablesReference")?;
+        let debug_data = self.debug_data()?;
+        let inferior = self.inferior.as_ref().ok_or("The program is not being run")?;
+        let (vars, frame): (Vec<&Variable>, Option<&Frame>) = if reference == GLOBALS_REFERENCE {
+            (debug_data.global_variables().collect(), None)
+        } else {
+            let frame = reference
+                .checked_sub(LOCALS_REFERENCE)
+                .and_then(|id| self.frames.get(id as usize))
+                .ok_or("Unknown variablesReference")?;
+            let vars = debug_data.get_frame_variables(frame.pc, frame.inline_depth);
+            (vars, Some(frame))
+        };
+        let variables: Vec<Value> = vars
+            .iter()
+            .map(|var| {
+                let value = match inferior.read_variable(debug_data, var, frame) {
+                    Ok(Some(bytes)) => {
+                        values::format_value(debug_data, &self.printers, inferior, &var.entity_type, &bytes)
+                    },
+                    Ok(None) => "<optimized out>".to_string(),
+                    Err(err) => format!("<error: {}>", err),
+                };
+                json!({
+                    "name": var.name,
+                    "value": value,
+                    "type": var.entity_type.name,
+                    "variablesReference": 0,
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// Reports how the inferior stopped or terminated with the matching events.
+    fn report_status(&mut self, status: Status) -> io::Result<()> {
+        match status {
+            // syscall tracing and event stops are never turned on here
+            Status::SyscallEntry(_, rip)
+            | Status::SyscallExit(_, rip)
+            | Status::Forked(_, rip)
+            | Status::Exec(rip)
+            | Status::Exiting(_, rip) => {
+                self.report_status(Status::Stopped(Signal::SIGTRAP, rip))
+            }
+            Status::Stopped(signal, rip) => {
+                let reason = if signal != Signal::SIGTRAP {
+                    "exception"
+                } else if self.breakpoints.contains_key(&(rip - 1)) {
+                    "breakpoint"
+                } else {
+                    "step"
+                };
+                let inferior = self.inferior.as_ref().unwrap();
+                let thread_id = inferior.thread_by_tid(inferior.current_tid()).map_or(1, |t| t.id);
+                self.send_event(
+                    "stopped",
+                    json!({
+                        "reason": reason,
+                        "description": format!("{}", signal),
+                        "threadId": thread_id,
+                        "allThreadsStopped": true,
+                    }),
+                )
+            }
+            Status::Exited(exit_code) => {
+                self.inferior = None;
+                self.send_event("exited", json!({ "exitCode": exit_code }))?;
+                self.send_event("terminated", json!({}))
+            }
+            Status::Signaled(signal) => {
+                self.inferior = None;
+                self.send_event("exited", json!({ "exitCode": 128 + signal as i32 }))?;
+                self.send_event("terminated", json!({}))
+            }
+        }
+    }
+
+    /// Starts the inferior running after the reply to the request that asked for it has gone out.
+    fn resume(&mut self, command: &str) -> io::Result<()> {
+        let status = {
+            let breakpoints = &self.breakpoints;
+            let inferior = match self.inferior.as_mut() {
+                Some(inferior) => inferior,
+                None => return Ok(()),
+            };
+            match command {
+                "next" => inferior.step_over_line(self.debug_data.as_ref().unwrap(), breakpoints),
+                _ => inferior.continue_run(None, breakpoints),
+            }
+        };
+        match status {
+            Ok(status) => self.report_status(status),
+            Err(err) => self.send_event("output", json!({"category": "stderr", "output": format!("{}\n", err)})),
+        }
+    }
+
+    fn handle(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => Ok(Value::Null),
+            "threads" => self.threads(),
+            "continue" => {
+                self.inferior()?;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                self.inferior()?;
+                Ok(Value::Null)
+            }
+            "stackTrace" => self.stack_trace(),
+            "scopes" => self.scopes(args),
+            "variables" => self.variables(args),
+            "disconnect" | "terminate" => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.terminate();
+                }
+                self.inferior = None;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request: {}", command)),
+        }
+    }
+
+    fn run<R: BufRead>(&mut self, input: &mut R) -> io::Result<()> {
+        while let Some(request) = read_message(input)? {
+            if request["type"] != "request" {
+                continue;
+            }
+            let command = request["command"].as_str().unwrap_or("").to_string();
+            let result = self.handle(&command, &request["arguments"]);
+            let mut response = json!({
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+                "success": result.is_ok(),
+            });
+            match result {
+                Ok(Value::Null) => {}
+                Ok(body) => response["body"] = body,
+                Err(message) => response["message"] = json!(message),
+            }
+            let success = response["success"] == true;
+            self.send(response)?;
+            if !success {
+                continue;
+            }
+            match command.as_str() {
+                // ready for breakpoints once there is a program to put them in
+                "launch" => self.send_event("initialized", json!({}))?,
+                "configurationDone" if self.stop_on_entry => self.send_event(
+                    "stopped",
+                    json!({"reason": "entry", "threadId": 1, "allThreadsStopped": true}),
+                )?,
+                "configurationDone" | "continue" | "next" => self.resume(&command)?,
+                "disconnect" | "terminate" => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_io_error(err: nix::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+/// Speaks DAP over stdin/stdout until the client disconnects. `target` is used if the launch
+/// request does not name a program.
+pub fn serve(target: Option<&str>) -> io::Result<()> {
+    // Move the protocol streams to private descriptors and point 0 and 1 at /dev/null and stderr,
+    // so that neither the inferior (which inherits them) nor a stray println! can get in the way.
+    let input_fd = unistd::dup(0).map_err(to_io_error)?;
+    let output_fd = unistd::dup(1).map_err(to_io_error)?;
+    let devnull = fcntl::open("/dev/null", OFlag::O_RDONLY, Mode::empty()).map_err(to_io_error)?;
+    unistd::dup2(devnull, 0).map_err(to_io_error)?;
+    unistd::close(devnull).map_err(to_io_error)?;
+    unistd::dup2(2, 1).map_err(to_io_error)?;
+
+    let mut input = BufReader::new(unsafe { File::from_raw_fd(input_fd) });
+    let output = unsafe { File::from_raw_fd(output_fd) };
+    let mut server = DapServer {
+        output,
+        seq: 0,
+        target: target.map(|t| t.to_string()),
+        debug_data: None,
+        printers: Printers::new(),
+        inferior: None,
+        breakpoints: HashMap::new(),
+        requested_breakpoints: HashMap::new(),
+        stop_on_entry: false,
+        frames: Vec::new(),
+    };
+    server.run(&mut input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn request(seq: u64, command: &str, arguments: Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let message = json!({"seq": seq, "type": "request", "command": command, "arguments": arguments});
+        write_message(&mut buf, &message).unwrap();
+        buf
+    }
+
+    fn read_all(output: &[u8]) -> Vec<Value> {
+        let mut cursor = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut cursor).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_message_framing() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &json!({"a": 1})).unwrap();
+        assert_eq!(buf, b"Content-Length: 7\r\n\r\n{\"a\":1}");
+        let messages = read_all(&buf);
+        assert_eq!(messages, vec![json!({"a": 1})]);
+
+        let mut huge = Cursor::new(b"Content-Length: 99999999999\r\n\r\n{}".to_vec());
+        assert_eq!(read_message(&mut huge).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_breakpoint_session() {
+        let program = "samples/function_calls";
+        let mut input = Vec::new();
+        input.extend(request(1, "initialize", json!({"adapterID": "deet"})));
+        input.extend(request(2, "launch", json!({"program": program})));
+        input.extend(request(3, "setFunctionBreakpoints", json!({"breakpoints": [{"name": "func2"}]})));
+        input.extend(request(4, "configurationDone", json!({})));
+        input.extend(request(5, "stackTrace", json!({"threadId": 1})));
+        input.extend(request(6, "scopes", json!({"frameId": 0})));
+        input.extend(request(7, "variables", json!({"variablesReference": LOCALS_REFERENCE})));
+        input.extend(request(8, "variables", json!({"variablesReference": GLOBALS_REFERENCE})));
+        input.extend(request(9, "continue", json!({"threadId": 1})));
+
+        let mut server = DapServer {
+            output: Vec::new(),
+            seq: 0,
+            target: None,
+            debug_data: None,
+            printers: Printers::new(),
+            inferior: None,
+            breakpoints: HashMap::new(),
+            requested_breakpoints: HashMap::new(),
+            stop_on_entry: false,
+            frames: Vec::new(),
+        };
+        server.run(&mut Cursor::new(input)).unwrap();
+        let messages = read_all(&server.output);
+
+        let find = |kind: &str, name: &str| -> Vec<&Value> {
+            messages
+                .iter()
+                .filter(|m| m["type"] == kind && (m["command"] == name || m["event"] == name))
+                .collect()
+        };
+        assert!(messages.iter().all(|m| m["type"] != "response" || m["success"] == true));
+        assert_eq!(find("event", "initialized").len(), 1);
+        assert_eq!(find("event", "stopped")[0]["body"]["reason"], "breakpoint");
+
+        let frames = &find("response", "stackTrace")[0]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "func2");
+        assert_eq!(frames[1]["name"], "func1");
+        assert_eq!(frames[2]["name"], "main");
+
+        let locals = find("response", "variables")[0]["body"]["variables"].as_array().unwrap();
+        let names: Vec<&str> = locals.iter().map(|v| v["name"].as_str().unwrap()).collect();
+        assert!(names.contains(&"a") && names.contains(&"b") && names.contains(&"sum"));
+        let globals = find("response", "variables")[1]["body"]["variables"].as_array().unwrap();
+        let global = globals.iter().find(|v| v["name"] == "global").unwrap();
+        assert_eq!(global["value"], "5");
+        assert_eq!(global["type"], "int");
+
+        assert_eq!(find("event", "exited")[0]["body"]["exitCode"], 0);
+        assert_eq!(find("event", "terminated").len(), 1);
+    }
+}
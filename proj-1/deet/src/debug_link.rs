@@ -0,0 +1,137 @@
+//! Locating separate debug info files, the way distributions ship them under /usr/lib/debug.
+//!
+//! An executable can point at its debug file in two ways, both tried in the same order as GDB:
+//!
+//! * `.note.gnu.build-id`: the file is `<debug dir>/.build-id/ab/cdef....debug`, where `abcdef...`
+//!   is the build id in hex.
+//! * `.gnu_debuglink`: a file name and the CRC-32 of the debug file. The name is looked up next to
+//!   the executable, in a `.debug` directory next to it, and under `<debug dir>/<executable dir>`.
+//!   Candidates whose CRC does not match are skipped.
+
+use object::Object;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The default place to look for separate debug files.
+pub const DEFAULT_DEBUG_DIR: &str = "/usr/lib/debug";
+
+const NT_GNU_BUILD_ID: u32 = 3;
+
+fn read_u32(data: &[u8], little_endian: bool) -> Option<u32> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(data.get(..4)?);
+    Some(if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Extracts the build id from the contents of an ELF note section.
+fn parse_build_id(mut notes: &[u8], little_endian: bool) -> Option<Vec<u8>> {
+    while notes.len() >= 12 {
+        let namesz = read_u32(notes, little_endian)? as usize;
+        let descsz = read_u32(&notes[4..], little_endian)? as usize;
+        let note_type = read_u32(&notes[8..], little_endian)?;
+        let name_start = 12;
+        let desc_start = name_start + align4(namesz);
+        let next = desc_start + align4(descsz);
+        let name = notes.get(name_start..name_start + namesz)?;
+        let desc = notes.get(desc_start..desc_start + descsz)?;
+        if note_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(desc.to_vec());
+        }
+        notes = notes.get(next..)?;
+    }
+    None
+}
+
+/// Splits the contents of a `.gnu_debuglink` section into the file name and the CRC.
+fn parse_debuglink(data: &[u8], little_endian: bool) -> Option<(String, u32)> {
+    let name_len = data.iter().position(|&b| b == 0)?;
+    let name = String::from_utf8(data[..name_len].to_vec()).ok()?;
+    let crc = read_u32(data.get(align4(name_len + 1)..)?, little_endian)?;
+    Some((name, crc))
+}
+
+/// The CRC-32 used by `.gnu_debuglink` (the same as zlib's).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Returns the path of the separate debug file for the executable at `path`, if one can be found.
+pub fn find_debug_file(path: &str, object: &object::File, debug_dir: &str) -> Option<PathBuf> {
+    let little_endian = object.is_little_endian();
+
+    let build_id = object
+        .section_data_by_name(".note.gnu.build-id")
+        .and_then(|notes| parse_build_id(&notes, little_endian));
+    if let Some(build_id) = build_id.filter(|id| id.len() >= 2) {
+        let hex: Vec<String> = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+        let candidate = Path::new(debug_dir)
+            .join(".build-id")
+            .join(&hex[0])
+            .join(format!("{}.debug", hex[1..].concat()));
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+    }
+
+    let (name, crc) = object
+        .section_data_by_name(".gnu_debuglink")
+        .and_then(|data| parse_debuglink(&data, little_endian))?;
+    let exe_dir = fs::canonicalize(path).ok()?.parent()?.to_path_buf();
+    let candidates = vec![
+        exe_dir.join(&name),
+        exe_dir.join(".debug").join(&name),
+        // debug_dir mirrors the file system: /usr/lib/debug/usr/bin/foo.debug
+        Path::new(debug_dir).join(exe_dir.strip_prefix("/").ok()?).join(&name),
+    ];
+    candidates.into_iter().find(|candidate| {
+        // the executable may link to itself when its debug info was not split off
+        fs::canonicalize(candidate).ok() != fs::canonicalize(path).ok()
+            && fs::read(candidate).map_or(false, |data| crc32(&data) == crc)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_parse_notes() {
+        let mut notes = Vec::new();
+        // an unrelated note first
+        notes.extend_from_slice(&[4, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0]);
+        notes.extend_from_slice(b"GNU\0\x01\x02\x03\x04");
+        notes.extend_from_slice(&[4, 0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0]);
+        notes.extend_from_slice(b"GNU\0\xab\xcd\xef\0");
+        assert_eq!(parse_build_id(&notes, true), Some(vec![0xab, 0xcd, 0xef]));
+
+        let mut debuglink = b"prog.debug\0\0".to_vec();
+        debuglink.extend_from_slice(&0x1234_5678u32.to_le_bytes());
+        assert_eq!(parse_debuglink(&debuglink, true), Some(("prog.debug".to_string(), 0x1234_5678)));
+    }
+}
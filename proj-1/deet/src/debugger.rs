@@ -1,24 +1,79 @@
-use crate::debugger_command::DebuggerCommand;
-use crate::inferior::Inferior;
+use crate::completion::DeetHelper;
+use crate::debugger_command::{self, DebuggerCommand};
+use crate::error::DebuggerError;
+use crate::inferior::{self, CallResult, Frame, Inferior, LaunchOptions};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::inferior::Status;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Line, Location, Type, TypeKind, Variable};
+use crate::dwarf_expr;
+use crate::printers::Printers;
+use crate::record::Recorder;
+use crate::session::{self, SavedBreakpoint};
+use crate::syscalls;
+use crate::tui::{self, Tui};
+use crate::values;
+use crate::output::{self, Output};
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::io::BufRead;
+
+/// What a catchpoint stops the inferior at.
+enum Catchpoint {
+    /// Entries to and returns from the system calls with these numbers, or any if there are none
+    Syscall(Vec<u64>),
+    /// These signals, or any but SIGTRAP and SIGINT (which deet uses itself) if there are none
+    Signal(Vec<Signal>),
+    Fork,
+    Exec,
+    /// The inferior calling exit, before it is gone
+    Exit,
+}
 
 pub struct Debugger {
     target: String,
     history_path: String,
-    readline: Editor<()>,
+    // where the breakpoints are kept between sessions
+    session_path: std::path::PathBuf,
+    readline: Editor<DeetHelper>,
     inferior: Option<Inferior>,
     debug_data: DwarfData,
+    // pretty-printers for the types of debug_data
+    printers: Printers,
     breakpoints : HashMap<usize, u8>,
+    // breakpoints set with `tbreak`, deleted when first hit
+    temporary_breakpoints: HashSet<usize>,
+    // how `run` starts the program, set with `set inferior-tty`, `set env` and `set cwd`
+    launch: LaunchOptions,
+    // set with `catch`; catchpoint N is catchpoints[N - 1]
+    catchpoints: Vec<Catchpoint>,
+    // execution log for reverse debugging, present while `record` is active
+    recorder: Option<Recorder>,
+    // frozen copies of the inferior made by `checkpoint`, by checkpoint number, with the
+    // breakpoints that were in their memory then
+    checkpoints: Vec<(usize, Inferior, HashMap<usize, u8>)>,
+    next_checkpoint_id: usize,
+    // the split-screen interface of `--tui`
+    tui: Option<Tui>,
+    out: Output,
 }
 
 
+fn missing_dwo_warning(path: &str) -> String {
+    format!(
+        "Warning: could not read split debugging information from {}; the source lines and \
+         variables it describes are unavailable.",
+        path
+    )
+}
+
 impl Debugger {
     /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
+    pub fn new(target: &str, out: Output) -> Debugger {
         // TODO (milestone 3): initialize the DwarfData
         let debug_data = match DwarfData::from_file(target) {
             Ok(val) => val,
@@ -28,13 +83,27 @@ impl Debugger {
             }
             Err(DwarfError::DwarfFormatError(err)) => {
                 println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
+                match DwarfData::from_symbol_table(target) {
+                    Ok(val) => val,
+                    Err(_) => std::process::exit(1),
+                }
             }
         };
-        debug_data.print();
+        if !debug_data.has_debug_info() && debug_data.missing_dwo().is_empty() {
+            println!(
+                "Warning: no debugging symbols found in {}; using the ELF symbol table. \
+                 Source lines and variables are unavailable.",
+                target
+            );
+        }
+        for path in debug_data.missing_dwo() {
+            println!("{}", missing_dwo_warning(path));
+        }
 
-        let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<()>::new();
+        let home = std::env::var("HOME").unwrap();
+        let history_path = format!("{}/.deet_history", home);
+        let mut readline = Editor::<DeetHelper>::new();
+        readline.set_helper(Some(DeetHelper::new(&debug_data)));
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
 
@@ -42,129 +111,1505 @@ impl Debugger {
         Debugger {
             target: target.to_string(),
             history_path,
+            session_path: session::session_path(&home, target),
             readline,
             inferior: None,
+            printers: Printers::for_program(&debug_data),
             debug_data: debug_data,
             breakpoints: breakpoints,
+            temporary_breakpoints: HashSet::new(),
+            launch: LaunchOptions::default(),
+            catchpoints: Vec::new(),
+            recorder: None,
+            checkpoints: Vec::new(),
+            next_checkpoint_id: 1,
+            tui: None,
+            out,
         }
     }
 
+    /// Switches to the split-screen interface, which is redrawn before every prompt.
+    pub fn start_tui(&mut self) -> std::io::Result<()> {
+        self.tui = Some(Tui::new()?);
+        Ok(())
+    }
+
     pub fn run(&mut self) {
+        self.load_session();
         loop {
+            self.refresh_tui();
             match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    if self.inferior.is_some() {
-                        // there is already a inferior running
-                        // if it has not exited, kill it first
-                        self.inferior.as_mut().unwrap().kill();
-                        self.inferior = None;
-                    }
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &mut self.breakpoints) {
+                DebuggerCommand::Quit => {
+                    self.kill_inferior();
+                    self.delete_checkpoints();
+                    let path = self.session_path.clone();
+                    if let Err(err) = session::write(&path, &self.saved_breakpoints()) {
+                        self.out.message(&format!("Could not save breakpoints to {}: {}", path.display(), err));
+                    }
+                    self.out.finish();
+                    return;
+                }
+                cmd => {
+                    if let Err(err) = self.execute(cmd) {
+                        self.out.error(&err.to_string());
+                    }
+                }
+            }
+            self.out.finish();
+        }
+    }
+
+    /// Carries out a command other than quit. Anything that goes wrong is returned to be reported,
+    /// and the debugger goes on to the next command.
+    fn execute(&mut self, cmd: DebuggerCommand) -> Result<(), DebuggerError> {
+        match cmd {
+            DebuggerCommand::Run(args, redirections) => {
+                // there may already be an inferior running, which is killed first
+                self.kill_inferior();
+                self.recorder = None;
+                self.delete_checkpoints();
+                let options = LaunchOptions {
+                    stdin: redirections.stdin,
+                    stdout: redirections.stdout,
+                    ..self.launch.clone()
+                };
+                match Inferior::launch(&self.target, &args, &options) {
+                    Ok(mut inferior) => {
+                        for addr in inferior.install_breakpoints(&mut self.breakpoints) {
+                            self.out.message(&format!("Invalid breakpoint address {:#x}", addr));
+                        }
                         // Create the inferior
                         self.inferior = Some(inferior);
                         // TODO (milestone 1): make the inferior run
                         // You may use self.inferior.as_mut().unwrap() to get a mutable reference
                         // to the Inferior object
-                        match self.inferior.as_mut().unwrap().continue_run(None, &self.breakpoints).unwrap() {
-                            Status::Exited(exit_code) => {
-                                println!("Child exited (status {})", exit_code);
-                                self.inferior = None;
-                            }
-                            Status::Signaled(signal) => {
-                                println!("Child exited due to signal {}", signal);
-                                self.inferior = None;
-                            }
-                            Status::Stopped(signal, rip) => {
-                                println!("Child stopped (signal {})", signal);
-                                let _line = self.debug_data.get_line_from_addr(rip);
-                                let _func = self.debug_data.get_function_from_addr(rip);
-                                if _line.is_some() && _func.is_some() {
-                                    println!("Stopped at {} ({})", _func.unwrap(), _line.unwrap());
-                                }
-                            }
-                        }
-                    } else {
-                        println!("Error starting subprocess");
-                    }
-                }
-
-                DebuggerCommand::Continue => {
-                    if self.inferior.is_none() {
-                        println!("Error: you can not use continue when there is no process running!");
-                    } else {
-                        match self.inferior.as_mut().unwrap().continue_run(None, &self.breakpoints).unwrap() {
-                            Status::Exited(exit_code) => {
-                                println!("Child exited (status {})", exit_code);
-                                self.inferior = None;
-                            }
-                            Status::Signaled(signal) => {
-                                println!("Child exited due to signal {}", signal);
-                                self.inferior = None;
-                            }
-                            Status::Stopped(signal, rip) => {
-                                println!("Child stopped (signal {})", signal);
-                                let _line = self.debug_data.get_line_from_addr(rip);
-                                let _func = self.debug_data.get_function_from_addr(rip);
-                                if _line.is_some() && _func.is_some() {
-                                    println!("Stopped at {} ({})", _func.unwrap(), _line.unwrap());
-                                }
-                            }
+                        self.out.running();
+                        let status = self.continue_inferior()?;
+                        self.report_status(status);
+                    }
+                    Err(err) => self.out.error(&format!("Error starting subprocess: {}", err)),
+                }
+            }
+
+            DebuggerCommand::Continue => {
+                if self.inferior.is_none() {
+                    self.out.error("Error: you can not use continue when there is no process running!");
+                } else if self.recorder.is_some() {
+                    self.arm_catchpoints();
+                    self.out.running();
+                    let recorder = self.recorder.as_mut().unwrap();
+                    let status = recorder.continue_run(self.inferior.as_mut().unwrap(), &self.breakpoints)?;
+                    self.report_status(status);
+                } else {
+                    self.out.running();
+                    let status = self.continue_inferior()?;
+                    self.report_status(status);
+                }
+            }
+            DebuggerCommand::Backtrace => {
+                if self.inferior.is_none() {
+                    self.out.error("Error: you can not use backtrace when there is no process running");
+                } else if self.out.is_json() {
+                    let frames = self.inferior.as_ref().unwrap().backtrace(&self.debug_data)?;
+                    let stack: Vec<Value> = frames.iter().map(|frame| self.stack_frame_json(frame)).collect();
+                    self.out.result("stack", json!(stack), "");
+                } else {
+                    self.inferior.as_mut().unwrap().print_backtrace(&self.debug_data)?;
+                }
+            }
+
+            DebuggerCommand::InfoThreads => {
+                if self.inferior.is_none() {
+                    self.out.result("threads", json!([]), "No threads.");
+                } else if self.out.is_json() {
+                    let inferior = self.inferior.as_ref().unwrap();
+                    let threads = inferior
+                        .threads()
+                        .iter()
+                        .map(|thread| {
+                            let rip = ptrace::getregs(thread.tid)?.rip as usize;
+                            Ok(json!({"id": thread.id, "tid": thread.tid.as_raw(), "frame": self.frame_json(rip)}))
+                        })
+                        .collect::<Result<Vec<Value>, nix::Error>>()?;
+                    let current = inferior.thread_by_tid(inferior.current_tid()).map(|thread| thread.id);
+                    self.out.result("threads", json!(threads), "");
+                    self.out.result("current-thread-id", json!(current), "");
+                } else {
+                    self.inferior.as_ref().unwrap().print_threads(&self.debug_data)?;
+                }
+            }
+            DebuggerCommand::Thread(id) => {
+                if self.inferior.is_none() {
+                    self.out.error("Error: you can not switch threads when there is no process running");
+                } else if let Some(thread) = self.inferior.as_mut().unwrap().select_thread(id) {
+                    let rip = ptrace::getregs(thread.tid)?.rip as usize;
+                    let text = format!("[Switching to thread {} (Thread {})]", thread.id, thread.tid);
+                    self.out.result("new-thread-id", json!(thread.id), &text);
+                    self.out.result("frame", self.frame_json(rip), &self.location_text(rip));
+                } else {
+                    self.out.error(&format!("Invalid thread ID: {}", id));
+                }
+            }
+
+            DebuggerCommand::Record => {
+                if self.inferior.is_none() {
+                    self.out.error("The program is not being run.");
+                } else if self.recorder.is_some() {
+                    self.out.error("The process is already being recorded.");
+                } else if self.inferior.as_ref().unwrap().threads().len() > 1 {
+                    self.out.error("Error: recording multi-threaded programs is not supported");
+                } else {
+                    self.out.message("Recording started; continue will now single-step the program");
+                    self.recorder = Some(Recorder::new());
+                }
+            }
+            DebuggerCommand::RecordStop => {
+                if self.recorder.take().is_some() {
+                    self.out.message("Process record is stopped and all execution logs are deleted.");
+                } else {
+                    self.out.error("No recording is currently active.");
+                }
+            }
+            DebuggerCommand::ReverseStep | DebuggerCommand::ReverseNext | DebuggerCommand::ReverseContinue => {
+                if self.inferior.is_none() || self.recorder.is_none() {
+                    self.out.error("Error: reverse execution needs a running process with \"record\" active");
+                    return Ok(());
+                }
+                self.out.running();
+                let inferior = self.inferior.as_mut().unwrap();
+                let recorder = self.recorder.as_mut().unwrap();
+                let (rip, complete) = match cmd {
+                    DebuggerCommand::ReverseContinue => {
+                        recorder.reverse_continue(inferior, &self.breakpoints)?
+                    }
+                    _ => {
+                        let over_calls = matches!(cmd, DebuggerCommand::ReverseNext);
+                        recorder.reverse_step(inferior, &self.debug_data, &self.breakpoints, over_calls)?
+                    }
+                };
+                let reason = if !complete {
+                    self.out.message("No more reverse-execution history.");
+                    "no-history"
+                } else if matches!(cmd, DebuggerCommand::ReverseContinue) {
+                    "breakpoint-hit"
+                } else {
+                    "end-stepping-range"
+                };
+                let fields = json!({"reason": reason, "frame": self.frame_json(rip)});
+                self.out.event("stopped", fields, &self.location_text(rip));
+            }
+
+            DebuggerCommand::Checkpoint => {
+                if self.inferior.is_none() {
+                    self.out.error("The program is not being run.");
+                } else if self.inferior.as_ref().unwrap().threads().len() > 1 {
+                    self.out.error("Error: checkpoints of multi-threaded programs are not supported");
+                } else {
+                    match self.inferior.as_mut().unwrap().fork() {
+                        Ok(checkpoint) => {
+                            let id = self.next_checkpoint_id;
+                            self.next_checkpoint_id += 1;
+                            let text = format!("checkpoint {}: fork returned pid {}.", id, checkpoint.pid());
+                            let fields = json!({"number": id, "pid": checkpoint.pid().as_raw()});
+                            self.out.result("checkpoint", fields, &text);
+                            self.checkpoints.push((id, checkpoint, self.breakpoints.clone()));
                         }
+                        Err(err) => self.out.error(&format!("Error creating checkpoint: {}", err)),
                     }
                 }
-                DebuggerCommand::Quit => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill();
-                        self.inferior = None;
+            }
+            DebuggerCommand::InfoCheckpoints => {
+                let mut text = Vec::new();
+                let mut list = Vec::new();
+                if self.checkpoints.is_empty() {
+                    text.push("No checkpoints.".to_string());
+                }
+                for (id, checkpoint, _) in &self.checkpoints {
+                    let rip = checkpoint.registers()?.rip as usize;
+                    let _line = self.debug_data.get_line_from_addr(rip);
+                    let _func = self.debug_data.get_function_from_addr(rip);
+                    text.push(match (&_line, &_func) {
+                        (Some(line), Some(func)) => {
+                            format!("  {} process {} at {:#x}, {} ({})", id, checkpoint.pid(), rip, func, line)
+                        }
+                        _ => format!("  {} process {} at {:#x}", id, checkpoint.pid(), rip),
+                    });
+                    list.push(json!({"number": id, "pid": checkpoint.pid().as_raw(), "frame": self.frame_json(rip)}));
+                }
+                self.out.result("checkpoints", json!(list), &text.join("\n"));
+            }
+            DebuggerCommand::Restart(id) => {
+                let index = match self.checkpoints.iter().position(|(n, _, _)| *n == id) {
+                    Some(index) => index,
+                    None => {
+                        self.out.error(&format!("Invalid checkpoint number {}", id));
+                        return Ok(());
+                    }
+                };
+                // run a fresh copy, so that the checkpoint can be restarted again later
+                let mut inferior = match self.checkpoints[index].1.fork() {
+                    Ok(inferior) => inferior,
+                    Err(err) => {
+                        self.out.error(&format!("Error restarting checkpoint {}: {}", id, err));
+                        return Ok(());
+                    }
+                };
+                self.kill_inferior();
+                self.recorder = None;
+                // breakpoints deleted since the checkpoint was taken, temporary ones that were hit
+                // included, are still in its memory, and those set since are not yet
+                for (addr, instruction) in &self.checkpoints[index].2 {
+                    if !self.breakpoints.contains_key(addr) {
+                        let _ = inferior.write_byte(*addr, *instruction);
                     }
-                    return;
                 }
-                DebuggerCommand::Backtrace => {
-                    if self.inferior.is_none() {
-                        println!("Error: you can not use backtrace when there is no process running");
-                    } else {
-                        self.inferior.as_mut().unwrap().print_backtrace(&self.debug_data).unwrap();
+                for addr in self.breakpoints.keys() {
+                    if inferior.write_byte(*addr, 0xcc).is_err() {
+                        self.out.message(&format!("Invalid breakpoint address {:#x}", addr));
                     }
                 }
+                let text = format!("Switching to checkpoint {} (process {})", id, inferior.pid());
+                self.out.result("pid", json!(inferior.pid().as_raw()), &text);
+                let rip = inferior.registers()?.rip as usize;
+                self.inferior = Some(inferior);
+                self.out.result("frame", self.frame_json(rip), &self.location_text(rip));
+            }
+
+            DebuggerCommand::InfoFunctions(pattern) => self.info_functions(pattern),
+            DebuggerCommand::InfoVariables(pattern) => self.info_variables(pattern),
+            DebuggerCommand::InfoLine(location) => self.info_line(&location),
+            DebuggerCommand::InfoAddress(name) => self.info_address(&name),
+            DebuggerCommand::InfoSymbol(addr) => self.info_symbol(&addr),
+            DebuggerCommand::Print(name) => self.print_variable(&name),
+            DebuggerCommand::Call(function, args) => self.call_function(&function, &args)?,
+            DebuggerCommand::Finish => self.finish()?,
+            DebuggerCommand::Return(value) => self.force_return(value.as_deref())?,
+            DebuggerCommand::InfoLocals => self.info_locals(),
 
-                DebuggerCommand::Breakpoint(location) => {
-                    let breakpoint_addr;
-                    if location.starts_with("*") {
-                        if let Some(address) = self.parse_address(&location[1..]) {
-                            breakpoint_addr = address;
-                        } else {
-                            println!("Invalid address");
-                            continue;
+            DebuggerCommand::SetDebugFileDirectory(dir) => {
+                // reload, in case the debug info of the target lives there
+                match DwarfData::from_file_with_debug_dir(&self.target, &dir) {
+                    Ok(debug_data) => {
+                        if !debug_data.has_debug_info() {
+                            self.out.message(&format!("No debugging symbols for {} found in {}", self.target, dir));
                         }
-                    } else if let Some(line) = usize::from_str_radix(&location, 10).ok() {
-                        if let Some(address) = self.debug_data.get_addr_for_line(None, line) {
-                            breakpoint_addr = address;
-                        } else {
-                            println!("Invalid line number");
-                            continue;
+                        for path in debug_data.missing_dwo() {
+                            self.out.message(&missing_dwo_warning(path));
                         }
-                    } else if let Some(address) = self.debug_data.get_addr_for_function(None, &location){
-                        breakpoint_addr = address;
-                    } else {
-                        println!("Usage: b|break|breakpoint *address|line|func");
-                        continue;
+                        self.printers = Printers::for_program(&debug_data);
+                        self.readline.set_helper(Some(DeetHelper::new(&debug_data)));
+                        self.debug_data = debug_data;
                     }
+                    Err(err) => self.out.error(&format!("Could not load debugging symbols: {:?}", err)),
+                }
+            }
 
-                    if self.inferior.is_some() {
-                        if let Some(instruction) = self.inferior.as_mut().unwrap().write_byte(breakpoint_addr, 0xcc).ok() {
-                            println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), breakpoint_addr);
-                            self.breakpoints.insert(breakpoint_addr, instruction);
-                        } else {
-                            println!("Invalid breakpoint address {:#x}", breakpoint_addr);
-                        }
-                    } else {
-                        // when the inferior is initiated, these breakpoints will be installed
-                        println!("Set breakpoint {} at {:#x}", self.breakpoints.len(), breakpoint_addr);
-                        self.breakpoints.insert(breakpoint_addr, 0);
+            DebuggerCommand::SetInferiorTty(tty) => self.launch.tty = tty,
+            DebuggerCommand::SetCwd(cwd) => self.launch.cwd = cwd,
+            DebuggerCommand::Layout(layout) => match self.tui.as_mut() {
+                None => self.out.error("The TUI is not active; start deet with --tui."),
+                Some(tui) => {
+                    if !tui.set_layout(&layout) {
+                        self.out.error(&format!("Unrecognized layout: \"{}\". Try \"src\" or \"regs\".", layout));
+                    }
+                }
+            },
+            DebuggerCommand::SetEnv(name, value) => {
+                if name.is_empty() {
+                    self.out.error("Argument required (environment variable and value).");
+                } else {
+                    self.launch.set_env(&name, value.as_deref());
+                }
+            }
+            DebuggerCommand::UnsetEnv(Some(name)) => self.launch.set_env(&name, None),
+            DebuggerCommand::UnsetEnv(None) => {
+                self.launch.clear_env = true;
+                self.launch.env.clear();
+                self.out.message("Deleted all environment variables for the program.");
+            }
+
+            DebuggerCommand::Breakpoint(location) => {
+                if let Some(addr) = self.resolve_location("b|break|breakpoint", &location) {
+                    self.set_breakpoint(addr, false);
+                }
+            }
+            DebuggerCommand::TemporaryBreakpoint(location) => {
+                if let Some(addr) = self.resolve_location("tb|tbreak", &location) {
+                    self.set_breakpoint(addr, true);
+                }
+            }
+            DebuggerCommand::SaveBreakpoints(path) => {
+                let breakpoints = self.saved_breakpoints();
+                match session::write(std::path::Path::new(&path), &breakpoints) {
+                    Ok(()) => self.out.message(&format!("Saved to file '{}'.", path)),
+                    Err(err) => self.out.error(&format!("{}: {}", path, err)),
+                }
+            }
+            DebuggerCommand::CatchSyscall(names) => {
+                let numbers: Vec<Option<u64>> = names.iter().map(|name| syscalls::number(name)).collect();
+                if let Some(index) = numbers.iter().position(|number| number.is_none()) {
+                    self.out.error(&format!("Unknown syscall name '{}'.", names[index]));
+                    return Ok(());
+                }
+                let numbers: Vec<u64> = numbers.into_iter().flatten().collect();
+                let description = if numbers.is_empty() {
+                    "any syscall".to_string()
+                } else {
+                    let list: Vec<String> = numbers
+                        .iter()
+                        .map(|n| format!("'{}' [{}]", syscalls::name(*n).unwrap_or("unknown"), n))
+                        .collect();
+                    format!("syscall{} {}", if numbers.len() > 1 { "s" } else { "" }, list.join(" "))
+                };
+                self.add_catchpoint(Catchpoint::Syscall(numbers), &description);
+            }
+            DebuggerCommand::CatchSignal(names) => {
+                let signals: Vec<Option<Signal>> = names.iter().map(|name| Debugger::parse_signal(name)).collect();
+                if let Some(index) = signals.iter().position(|signal| signal.is_none()) {
+                    self.out.error(&format!("Unknown signal name '{}'.", names[index]));
+                    return Ok(());
+                }
+                let signals: Vec<Signal> = signals.into_iter().flatten().collect();
+                let description = if signals.is_empty() {
+                    "standard signals".to_string()
+                } else {
+                    let list: Vec<&str> = signals.iter().map(|signal| signal.as_str()).collect();
+                    format!("signal {}", list.join(" "))
+                };
+                self.add_catchpoint(Catchpoint::Signal(signals), &description);
+            }
+            DebuggerCommand::CatchFork => self.add_catchpoint(Catchpoint::Fork, "fork"),
+            DebuggerCommand::CatchExec => self.add_catchpoint(Catchpoint::Exec, "exec"),
+            DebuggerCommand::CatchExit => self.add_catchpoint(Catchpoint::Exit, "exit"),
+            DebuggerCommand::Until(None) => {
+                if self.inferior.is_none() {
+                    self.out.error("The program is not being run.");
+                    return Ok(());
+                }
+                self.out.running();
+                let inferior = self.inferior.as_mut().unwrap();
+                match inferior.step_until_line(&self.debug_data, &self.breakpoints)? {
+                    Status::Stopped(Signal::SIGTRAP, rip) if !self.breakpoints.contains_key(&(rip - 1)) => {
+                        let fields = json!({"reason": "end-stepping-range", "frame": self.frame_json(rip)});
+                        self.out.event("stopped", fields, &self.location_text(rip));
+                    }
+                    status => self.report_status(status),
+                }
+            }
+            DebuggerCommand::Until(Some(location)) => {
+                if let Some(addr) = self.resolve_location("u|until", &location) {
+                    self.run_to(addr, true)?;
+                }
+            }
+            DebuggerCommand::Advance(location) => {
+                if let Some(addr) = self.resolve_location("advance", &location) {
+                    self.run_to(addr, false)?;
+                }
+            }
+            DebuggerCommand::Help(None) => {
+                let width = debugger_command::COMMANDS.iter().map(|spec| spec.name.len()).max().unwrap_or(0);
+                let mut text: Vec<String> = debugger_command::COMMANDS
+                    .iter()
+                    .map(|spec| format!("{:<width$} -- {}", spec.name, spec.help, width = width))
+                    .collect();
+                text.push("Type \"help\" followed by a command name for more. Commands may be abbreviated.".to_string());
+                let names: Vec<&str> = debugger_command::COMMANDS.iter().map(|spec| spec.name).collect();
+                self.out.result("commands", json!(names), &text.join("\n"));
+            }
+            DebuggerCommand::Help(Some(name)) => {
+                let spec = debugger_command::find_command(&name)?;
+                self.out.result("help", json!(spec.help_text()), &spec.help_text());
+            }
+            DebuggerCommand::Quit => unreachable!("quit is handled by run"),
+        }
+        Ok(())
+    }
+
+    /// The address of a `*address`, line or function location, reporting why if there is none.
+    fn resolve_location(&mut self, usage: &str, location: &str) -> Option<usize> {
+        match self.lookup_location(usage, location) {
+            Ok(address) => Some(address),
+            Err(err) => {
+                self.out.error(&err);
+                None
+            }
+        }
+    }
+
+    fn lookup_location(&self, usage: &str, location: &str) -> Result<usize, String> {
+        if location.starts_with("*") {
+            self.parse_address(&location[1..]).ok_or_else(|| "Invalid address".to_string())
+        } else if let Some(line) = usize::from_str_radix(&location, 10).ok() {
+            self.debug_data.get_addr_for_line(None, line).ok_or_else(|| "Invalid line number".to_string())
+        } else if let Some(address) = self.debug_data.get_addr_for_function(None, &location) {
+            Ok(address)
+        } else if let Some(colon) = location.rfind(':') {
+            let (file, place) = (&location[..colon], &location[colon + 1..]);
+            let address = match place.parse::<usize>() {
+                Ok(line) => self.debug_data.get_addr_for_line(Some(file), line),
+                Err(_) => self.debug_data.get_addr_for_function(Some(file), place),
+            };
+            address.ok_or_else(|| format!("No {} in file \"{}\".", place, file))
+        } else {
+            Err(format!("Usage: {} *address|line|file:line|func", usage))
+        }
+    }
+
+    /// How the breakpoints are written down by `save breakpoints` and in the session file: by
+    /// function if one starts there, or else by source line, so that they survive a rebuild. Only
+    /// breakpoints in code without line information are kept by address.
+    fn saved_breakpoints(&self) -> Vec<SavedBreakpoint> {
+        let mut addrs: Vec<usize> = self.breakpoints.keys().copied().collect();
+        addrs.sort();
+        addrs
+            .into_iter()
+            .map(|addr| {
+                let function = self
+                    .debug_data
+                    .get_function_containing(addr)
+                    .map(|func| func.name.clone())
+                    .filter(|name| self.debug_data.get_addr_for_function(None, name) == Some(addr));
+                let line = self
+                    .debug_data
+                    .get_line_from_addr(addr)
+                    .filter(|line| self.debug_data.get_addr_for_line(Some(&line.file), line.number) == Some(addr));
+                let location = match (function, line) {
+                    (Some(name), _) => name,
+                    (None, Some(line)) => line.to_string(),
+                    (None, None) => format!("*{:#x}", addr),
+                };
+                SavedBreakpoint { location, temporary: self.temporary_breakpoints.contains(&addr) }
+            })
+            .collect()
+    }
+
+    /// Sets the breakpoints of the last session with this target again.
+    fn load_session(&mut self) {
+        let text = match std::fs::read_to_string(&self.session_path) {
+            Ok(text) => text,
+            Err(_) => return,
+        };
+        let saved = session::parse(&text);
+        if saved.is_empty() {
+            return;
+        }
+        self.out.message(&format!("Restoring breakpoints from {}", self.session_path.display()));
+        for bp in saved {
+            match self.lookup_location("break", &bp.location) {
+                Ok(addr) => self.set_breakpoint(addr, bp.temporary),
+                Err(_) => self.out.message(&format!("Could not restore the breakpoint at {}", bp.location)),
+            }
+        }
+    }
+
+    /// Sets a breakpoint, installing it right away if the inferior is running. Temporary
+    /// breakpoints are deleted the first time they are hit.
+    fn set_breakpoint(&mut self, addr: usize, temporary: bool) {
+        if self.inferior.is_some() {
+            if let Some(instruction) = self.inferior.as_mut().unwrap().write_byte(addr, 0xcc).ok() {
+                self.report_breakpoint(addr, temporary);
+                self.breakpoints.insert(addr, instruction);
+            } else {
+                self.out.error(&format!("Invalid breakpoint address {:#x}", addr));
+                return;
+            }
+        } else {
+            // when the inferior is initiated, these breakpoints will be installed
+            self.report_breakpoint(addr, temporary);
+            self.breakpoints.insert(addr, 0);
+        }
+        if temporary {
+            self.temporary_breakpoints.insert(addr);
+        }
+    }
+
+    /// Removes the breakpoint at `addr` and puts the original instruction back. If the inferior
+    /// has just hit it, it is moved back onto the instruction so that it runs next.
+    fn delete_breakpoint(&mut self, addr: usize) {
+        let instruction = match self.breakpoints.remove(&addr) {
+            Some(instruction) => instruction,
+            None => return,
+        };
+        self.temporary_breakpoints.remove(&addr);
+        if let Some(inferior) = self.inferior.as_mut() {
+            if let Ok(mut regs) = inferior.registers() {
+                if regs.rip as usize == addr + 1 {
+                    regs.rip -= 1;
+                    let _ = inferior.set_registers(regs);
+                }
+            }
+            let _ = inferior.write_byte(addr, instruction);
+        }
+    }
+
+    fn add_catchpoint(&mut self, catchpoint: Catchpoint, description: &str) {
+        let id = self.catchpoints.len() + 1;
+        let text = format!("Catchpoint {} ({})", id, description);
+        self.out.result("catchpoint", json!({"number": id, "what": description}), &text);
+        self.catchpoints.push(catchpoint);
+    }
+
+    /// A signal given by name, with or without "SIG", or by number.
+    fn parse_signal(name: &str) -> Option<Signal> {
+        if let Ok(number) = name.parse::<libc::c_int>() {
+            return Signal::try_from(number).ok();
+        }
+        let name = name.to_uppercase();
+        if name.starts_with("SIG") {
+            name.parse().ok()
+        } else {
+            format!("SIG{}", name).parse().ok()
+        }
+    }
+
+    /// Continues the inferior until it stops for something the user asked for: syscall stops
+    /// that no catchpoint is waiting for are passed over, and forked children are let go.
+    fn continue_inferior(&mut self) -> Result<Status, nix::Error> {
+        self.arm_catchpoints();
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            let status = inferior.continue_run(None, &self.breakpoints)?;
+            if let Status::Forked(child, _) = status {
+                if let Err(err) = inferior.detach_child(child, &self.breakpoints) {
+                    self.out.message(&format!("Warning: could not detach from child process {}: {}", child, err));
+                }
+            }
+            match status {
+                Status::SyscallEntry(number, _) | Status::SyscallExit(number, _)
+                    if self.syscall_catchpoint(number).is_none() => {}
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Asks the inferior for the stops that the catchpoints need.
+    fn arm_catchpoints(&mut self) {
+        let tracing = self.catchpoint(|c| matches!(c, Catchpoint::Syscall(_))).is_some();
+        let fork = self.catchpoint(|c| matches!(c, Catchpoint::Fork)).is_some();
+        let exec = self.catchpoint(|c| matches!(c, Catchpoint::Exec)).is_some();
+        let exit = self.catchpoint(|c| matches!(c, Catchpoint::Exit)).is_some();
+        let inferior = self.inferior.as_mut().unwrap();
+        inferior.set_syscall_tracing(tracing);
+        if let Err(err) = inferior.set_event_stops(fork, exec, exit) {
+            self.out.message(&format!("Warning: could not set up catchpoints: {}", err));
+        }
+    }
+
+    /// The number of the first catchpoint for system call `number`.
+    fn syscall_catchpoint(&self, number: u64) -> Option<usize> {
+        self.catchpoint(|catchpoint| match catchpoint {
+            Catchpoint::Syscall(numbers) => numbers.is_empty() || numbers.contains(&number),
+            _ => false,
+        })
+    }
+
+    /// The number of the first catchpoint for signal `signal`.
+    fn signal_catchpoint(&self, signal: Signal) -> Option<usize> {
+        self.catchpoint(|catchpoint| match catchpoint {
+            Catchpoint::Signal(signals) if signals.is_empty() => {
+                signal != Signal::SIGTRAP && signal != Signal::SIGINT
+            }
+            Catchpoint::Signal(signals) => signals.contains(&signal),
+            _ => false,
+        })
+    }
+
+    /// The number of the first catchpoint `matches` accepts.
+    fn catchpoint(&self, matches: impl Fn(&Catchpoint) -> bool) -> Option<usize> {
+        Some(self.catchpoints.iter().position(matches)? + 1)
+    }
+
+    /// `until <loc>` and `advance <loc>`: runs until `addr` is reached or the current function
+    /// returns, using temporary breakpoints at both. `until` only stops at `addr` in the current
+    /// frame (or one of its callers), which skips over recursive calls. Also used by `finish`.
+    /// Returns whether it got there.
+    fn run_to(&mut self, addr: usize, in_frame: bool) -> Result<bool, nix::Error> {
+        let frames = match self.inferior.as_ref() {
+            Some(inferior) => inferior.backtrace(&self.debug_data).unwrap_or_default(),
+            None => {
+                self.out.error("The program is not being run.");
+                return Ok(false);
+            }
+        };
+        let start_cfa = frames.first().map(|frame| frame.cfa);
+        let return_addr = frames.iter().find(|frame| frame.level == 1).map(|frame| frame.rip);
+        let mut added = Vec::new();
+        for target in Some(addr).into_iter().chain(return_addr) {
+            if self.breakpoints.contains_key(&target) {
+                continue;
+            }
+            match self.inferior.as_mut().unwrap().write_byte(target, 0xcc) {
+                Ok(instruction) => {
+                    self.breakpoints.insert(target, instruction);
+                    added.push(target);
+                }
+                Err(_) if target == addr => {
+                    self.out.error(&format!("Cannot insert breakpoint at {:#x}", addr));
+                    return Ok(false);
+                }
+                // we will not notice the function returning; that is all
+                Err(_) => {}
+            }
+        }
+        self.out.running();
+        // standing on one of them already, as after `finish` returns into a recursive call, it
+        // would stop us straight away: step off it first
+        let mut on_target = frames.first().map_or(false, |frame| added.contains(&frame.rip));
+        let status = loop {
+            if on_target {
+                on_target = false;
+                match self.inferior.as_mut().unwrap().step_instruction(&self.breakpoints) {
+                    Ok(Status::Stopped(Signal::SIGTRAP, _)) => {}
+                    other => break other,
+                }
+            }
+            let status = match self.continue_inferior() {
+                Ok(status) => status,
+                Err(err) => break Err(err),
+            };
+            let rip = match status {
+                Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                _ => break Ok(status),
+            };
+            // a return from, or for `until` a hit in, a deeper (recursive) call is not the one we
+            // are waiting for
+            let cfa = self.innermost_frame().map_or(0, |frame| frame.cfa);
+            let deeper = cfa < start_cfa.unwrap_or(0);
+            let wanted = if rip - 1 == addr { !in_frame || !deeper } else { !deeper };
+            if added.contains(&(rip - 1)) && !wanted {
+                continue;
+            }
+            break Ok(status);
+        };
+        for target in added {
+            self.delete_breakpoint(target);
+        }
+        let status = status?;
+        let reached = match status {
+            Status::Stopped(Signal::SIGTRAP, rip) => rip - 1 == addr || Some(rip - 1) == return_addr,
+            _ => false,
+        };
+        match status {
+            Status::Stopped(Signal::SIGTRAP, rip) if reached => {
+                if self.breakpoints.contains_key(&(rip - 1)) {
+                    // the user's own breakpoint was there too
+                    self.report_status(status);
+                } else {
+                    let fields = json!({"reason": "location-reached", "frame": self.frame_json(rip - 1)});
+                    self.out.event("stopped", fields, &self.location_text(rip - 1));
+                }
+            }
+            status => self.report_status(status),
+        }
+        Ok(reached)
+    }
+
+    fn report_breakpoint(&mut self, addr: usize, temporary: bool) {
+        let kind = if temporary { "Temporary breakpoint" } else { "Set breakpoint" };
+        let text = format!("{} {} at {:#x}", kind, self.breakpoints.len(), addr);
+        let mut bkpt = self.frame_json(addr);
+        bkpt["number"] = json!(self.breakpoints.len());
+        bkpt["disp"] = json!(if temporary { "del" } else { "keep" });
+        self.out.result("breakpoint", bkpt.clone(), "");
+        self.out.event("breakpoint-set", json!({ "breakpoint": bkpt }), &text);
+    }
+
+    /// Prints why the inferior stopped (or that it is gone, in which case it is dropped).
+    fn report_status(&mut self, status: Status) {
+        match status {
+            Status::Exited(exit_code) => {
+                let text = format!("Child exited (status {})", exit_code);
+                self.out.event("exited", json!({ "exit-code": exit_code }), &text);
+                self.inferior = None;
+                self.recorder = None;
+            }
+            Status::Signaled(signal) => {
+                let text = format!("Child exited due to signal {}", signal);
+                self.out.event("signaled", json!({ "signal": signal.to_string() }), &text);
+                self.inferior = None;
+                self.recorder = None;
+            }
+            Status::Stopped(signal, rip) => {
+                let hit = signal == Signal::SIGTRAP && self.breakpoints.contains_key(&(rip - 1));
+                if let Some(catchpoint) = self.signal_catchpoint(signal).filter(|_| !hit) {
+                    let heading = format!("Catchpoint {} (signal {})", catchpoint, signal);
+                    let fields = json!({"reason": "signal-received", "bkptno": catchpoint, "signal": signal.to_string()});
+                    self.report_catch(heading, rip, fields);
+                    return;
+                }
+                let inferior = self.inferior.as_ref().unwrap();
+                let thread = inferior.thread_by_tid(inferior.current_tid());
+                let mut text = match thread {
+                    Some(thread) if inferior.threads().len() > 1 => {
+                        format!("Thread {} (Thread {}) stopped (signal {})", thread.id, thread.tid, signal)
+                    }
+                    _ => format!("Child stopped (signal {})", signal),
+                };
+                let (pc, location) = self.caller_location(rip);
+                if !location.is_empty() {
+                    text = format!("{}\n{}", text, location);
+                }
+                let reason = if hit { "breakpoint-hit" } else { "signal-received" };
+                let temporary = hit && self.temporary_breakpoints.contains(&(rip - 1));
+                if temporary {
+                    self.delete_breakpoint(rip - 1);
+                }
+                let fields = json!({
+                    "disp": if temporary { "del" } else { "keep" },
+                    "reason": reason,
+                    "signal": signal.to_string(),
+                    "thread-id": thread.map(|thread| thread.id),
+                    "frame": self.frame_json(pc),
+                });
+                self.out.event("stopped", fields, &text);
+            }
+            Status::SyscallEntry(number, rip) | Status::SyscallExit(number, rip) => {
+                let entry = matches!(status, Status::SyscallEntry(..));
+                let inferior = self.inferior.as_ref().unwrap();
+                let regs = match inferior.registers() {
+                    Ok(regs) => regs,
+                    Err(err) => return self.out.error(&DebuggerError::from(err).to_string()),
+                };
+                let (_, args) = syscalls::registers_call(&regs);
+                let result = if entry { None } else { Some(regs.rax as i64) };
+                let call = syscalls::format_call(number, &args, result, inferior);
+                let name = syscalls::name(number).map_or_else(|| number.to_string(), |name| name.to_string());
+                let catchpoint = self.syscall_catchpoint(number).unwrap_or(0);
+                let kind = if entry { "call to" } else { "returned from" };
+                let heading = format!("Catchpoint {} ({} syscall {}), {}", catchpoint, kind, name, call);
+                let fields = json!({
+                    "reason": if entry { "syscall-entry" } else { "syscall-return" },
+                    "bkptno": catchpoint,
+                    "syscall-number": number,
+                    "syscall-name": name,
+                    "call": call,
+                });
+                self.report_catch(heading, rip, fields);
+            }
+            Status::Forked(child, rip) => {
+                let catchpoint = self.catchpoint(|c| matches!(c, Catchpoint::Fork)).unwrap_or(0);
+                let heading = format!("Catchpoint {} (forked process {})", catchpoint, child);
+                let fields = json!({"reason": "fork", "bkptno": catchpoint, "newpid": child.as_raw()});
+                self.report_catch(heading, rip, fields);
+            }
+            Status::Exec(rip) => {
+                let pid = self.inferior.as_ref().unwrap().pid();
+                let path = match std::fs::read_link(format!("/proc/{}/exe", pid)) {
+                    Ok(path) => path.to_string_lossy().to_string(),
+                    Err(_) => "<unknown>".to_string(),
+                };
+                self.out.message(&format!("process {} is executing new program: {}", pid, path));
+                self.follow_exec(&path);
+                let catchpoint = self.catchpoint(|c| matches!(c, Catchpoint::Exec)).unwrap_or(0);
+                let heading = format!("Catchpoint {} (exec'd {})", catchpoint, path);
+                let fields = json!({"reason": "exec", "bkptno": catchpoint, "new-exec": path});
+                self.report_catch(heading, rip, fields);
+            }
+            Status::Exiting(exit_code, rip) => {
+                let catchpoint = self.catchpoint(|c| matches!(c, Catchpoint::Exit)).unwrap_or(0);
+                let heading = format!("Catchpoint {} (exit, status {})", catchpoint, exit_code);
+                let fields = json!({"reason": "exit", "bkptno": catchpoint, "exit-code": exit_code});
+                self.report_catch(heading, rip, fields);
+            }
+        }
+    }
+
+    /// Reports a stop at a catchpoint: its `heading`, and then the line of the program that
+    /// caused it, which for system calls is where the libc wrapper was called from.
+    fn report_catch(&mut self, heading: String, rip: usize, mut fields: Value) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let thread = inferior.thread_by_tid(inferior.current_tid());
+        let (caller, location) = self.caller_location(rip);
+        let mut text = heading;
+        if !location.is_empty() {
+            text = format!("{}\n{}", text, location);
+        }
+        if let Some(thread) = thread {
+            fields["thread-id"] = json!(thread.id);
+        }
+        fields["frame"] = self.frame_json(caller);
+        self.out.event("stopped", fields, &text);
+    }
+
+    /// Where the inferior stopped at `rip`: that line, or if it is somewhere without line
+    /// information (such as inside libc), the line of the program that called into there. Returns
+    /// the address that was described along with the text.
+    fn caller_location(&self, rip: usize) -> (usize, String) {
+        let location = self.location_text(rip);
+        if !location.is_empty() {
+            return (rip, location);
+        }
+        match self.inferior.as_ref().and_then(|inferior| inferior.source_caller(&self.debug_data)) {
+            Some(caller) if caller != rip => (caller, self.location_text_with("Called from", caller)),
+            _ => (rip, location),
+        }
+    }
+
+    /// Redraws the panes of the TUI, if it is on: the source around where the inferior is
+    /// stopped, or around main before it runs.
+    fn refresh_tui(&mut self) {
+        if self.tui.is_none() {
+            return;
+        }
+        let stopped_at = self.innermost_frame().map(|frame| (frame.pc, frame.inline_depth));
+        let (line, current) = match stopped_at {
+            Some((pc, inline_depth)) => (self.debug_data.get_frame_line(pc, inline_depth), true),
+            None => {
+                let main = self.debug_data.get_addr_for_function(None, "main");
+                (main.and_then(|addr| self.debug_data.get_line_from_addr(addr)), false)
+            }
+        };
+        let breakpoints: Vec<Line> = self
+            .breakpoints
+            .keys()
+            .filter_map(|addr| self.debug_data.get_line_from_addr(*addr))
+            .collect();
+        let registers = self.inferior.as_ref().and_then(|inferior| inferior.registers().ok());
+        let view = tui::View {
+            line: line.as_ref(),
+            current,
+            breakpoints: &breakpoints,
+            registers,
+        };
+        if let Err(err) = self.tui.as_mut().unwrap().draw(&view) {
+            self.out.message(&format!("Could not draw the TUI: {}", err));
+            self.tui = None;
+        }
+    }
+
+    /// After the inferior ran execve, switches to the debugging information of its new program.
+    /// The breakpoints were in the old program's code, which is gone, so they are deleted. So is
+    /// the recording and the checkpoints, which can not bring the old program back either.
+    fn follow_exec(&mut self, path: &str) {
+        if self.recorder.take().is_some() {
+            self.out.message("Process record stopped: the program was replaced.");
+        }
+        self.delete_checkpoints();
+        if !self.breakpoints.is_empty() {
+            self.out.message("Deleting the breakpoints of the previous program.");
+            self.breakpoints.clear();
+            self.temporary_breakpoints.clear();
+        }
+        match DwarfData::from_file(path).or_else(|_| DwarfData::from_symbol_table(path)) {
+            Ok(debug_data) => {
+                for dwo in debug_data.missing_dwo() {
+                    self.out.message(&missing_dwo_warning(dwo));
+                }
+                self.printers = Printers::for_program(&debug_data);
+                self.readline.set_helper(Some(DeetHelper::new(&debug_data)));
+                self.debug_data = debug_data;
+                self.target = path.to_string();
+            }
+            Err(err) => self.out.message(&format!("Could not load symbols for {}: {:?}", path, err)),
+        }
+    }
+
+    /// Compiles the optional regex of `info functions` / `info variables`, reporting errors.
+    fn symbol_filter(&mut self, pattern: &Option<String>) -> Option<Option<Regex>> {
+        match pattern.as_ref().map(|p| Regex::new(p)) {
+            None => Some(None),
+            Some(Ok(regex)) => Some(Some(regex)),
+            Some(Err(err)) => {
+                self.out.error(&format!("Invalid regexp: {}", err));
+                None
+            }
+        }
+    }
+
+    fn symbol_heading(kind: &str, pattern: &Option<String>) -> String {
+        match pattern {
+            Some(pattern) => format!("All {} matching regular expression \"{}\":", kind, pattern),
+            None => format!("All defined {}:", kind),
+        }
+    }
+
+    /// `info functions [regex]`: functions of every compilation unit, by file.
+    fn info_functions(&mut self, pattern: Option<String>) {
+        let regex = match self.symbol_filter(&pattern) {
+            Some(regex) => regex,
+            None => return,
+        };
+        let mut text = vec![Debugger::symbol_heading("functions", &pattern)];
+        let mut list = Vec::new();
+        for file in self.debug_data.files() {
+            let matches: Vec<_> = file
+                .functions
+                .iter()
+                // skip declarations of functions defined elsewhere
+                .filter(|func| func.text_length > 0)
+                .filter(|func| regex.as_ref().map_or(true, |re| re.is_match(&func.name)))
+                .collect();
+            if matches.is_empty() {
+                continue;
+            }
+            text.push(format!("\nFile {}:", file.name));
+            for func in matches {
+                text.push(format!("{}:\t{} at {:#x};", func.line_number, func.name, func.address));
+                list.push(json!({
+                    "name": func.name,
+                    "file": file.name,
+                    "line": func.line_number,
+                    "addr": format!("{:#x}", func.address),
+                }));
+            }
+        }
+        let mut heading = false;
+        for sym in self.debug_data.symbols() {
+            let covered = self.debug_data.files().iter().any(|file| {
+                file.functions.iter().any(|func| func.address == sym.address && func.text_length > 0)
+            });
+            if covered || !regex.as_ref().map_or(true, |re| re.is_match(&sym.name)) {
+                continue;
+            }
+            if !heading {
+                text.push("\nNon-debugging symbols:".to_string());
+                heading = true;
+            }
+            text.push(format!("{:#018x}  {}", sym.address, sym.name));
+            list.push(json!({"name": sym.name, "addr": format!("{:#x}", sym.address)}));
+        }
+        self.out.result("functions", json!(list), &text.join("\n"));
+    }
+
+    /// `info variables [regex]`: global variables of every compilation unit, by file.
+    fn info_variables(&mut self, pattern: Option<String>) {
+        let regex = match self.symbol_filter(&pattern) {
+            Some(regex) => regex,
+            None => return,
+        };
+        let mut text = vec![Debugger::symbol_heading("variables", &pattern)];
+        let mut list = Vec::new();
+        for file in self.debug_data.files() {
+            let matches: Vec<_> = file
+                .global_variables
+                .iter()
+                .filter(|var| regex.as_ref().map_or(true, |re| re.is_match(&var.name)))
+                .collect();
+            if matches.is_empty() {
+                continue;
+            }
+            text.push(format!("\nFile {}:", file.name));
+            for var in matches {
+                text.push(format!("{}:\t{} {};", var.line_number, var.entity_type.name, var.name));
+                list.push(json!({
+                    "name": var.name,
+                    "type": var.entity_type.name,
+                    "file": file.name,
+                    "line": var.line_number,
+                }));
+            }
+        }
+        self.out.result("variables", json!(list), &text.join("\n"));
+    }
+
+    /// `info line <line|file:line|func|*addr>`: the code generated for a source line.
+    fn info_line(&mut self, location: &str) {
+        let line = if location.starts_with('*') {
+            self.parse_address(&location[1..]).and_then(|addr| self.debug_data.get_line_from_addr(addr))
+        } else if let Ok(number) = location.parse::<usize>() {
+            let file = self.debug_data.files().get(0).map(|file| file.name.clone());
+            file.map(|file| Line { file, number, address: 0 })
+        } else if let Some(colon) = location.rfind(':') {
+            let file = location[..colon].to_string();
+            location[colon + 1..].parse().ok().map(|number| Line { file, number, address: 0 })
+        } else {
+            self.debug_data
+                .get_addr_for_function(None, location)
+                .and_then(|addr| self.debug_data.get_line_from_addr(addr))
+        };
+        let line = match line {
+            Some(line) => line,
+            None => {
+                self.out.error(&format!("No line number information available for \"{}\"", location));
+                return;
+            }
+        };
+        let ranges = self.debug_data.get_line_ranges(Some(&line.file), line.number);
+        if ranges.is_empty() {
+            let text = format!("Line {} of \"{}\" contains no code.", line.number, line.file);
+            self.out.result("ranges", json!([]), &text);
+            return;
+        }
+        let mut text = Vec::new();
+        let mut list = Vec::new();
+        for (start, end) in ranges {
+            text.push(format!(
+                "Line {} of \"{}\" starts at address {:#x} {} and ends at {:#x} {}.",
+                line.number,
+                line.file,
+                start,
+                self.symbolize(start),
+                end,
+                self.symbolize(end)
+            ));
+            list.push(json!({"start": format!("{:#x}", start), "end": format!("{:#x}", end)}));
+        }
+        self.out.result("file", json!(line.file), "");
+        self.out.result("line", json!(line.number), "");
+        self.out.result("ranges", json!(list), &text.join("\n"));
+    }
+
+    /// `info address <symbol>`: where a function or variable lives.
+    fn info_address(&mut self, name: &str) {
+        // locals of the function we are stopped in take precedence over globals
+        let frame = self.innermost_frame();
+        let local = frame.and_then(|frame| {
+            let vars = self.debug_data.get_frame_variables(frame.pc, frame.inline_depth);
+            vars.into_iter().find(|var| var.name == name)
+        });
+        let global = self.debug_data.global_variables().find(|var| var.name == name);
+        let (text, fields) = if let Some(var) = local.or(global) {
+            match &var.location {
+                Location::Address(addr) => (
+                    format!("Symbol \"{}\" is static storage at address {:#x}.", name, addr),
+                    json!({"kind": "static", "addr": format!("{:#x}", addr)}),
+                ),
+                Location::FramePointerOffset(offset) => (
+                    format!("Symbol \"{}\" is a variable at frame base offset {}.", name, offset),
+                    json!({"kind": "local", "frame-base-offset": offset}),
+                ),
+                Location::Expression(expr) => (
+                    format!("Symbol \"{}\" is {}.", name, Debugger::describe_expression(expr)),
+                    json!({"kind": "computed"}),
+                ),
+                Location::List(entries) => {
+                    let mut text = vec![format!("Symbol \"{}\" is multi-location:", name)];
+                    for (begin, end, expr) in entries {
+                        let description = Debugger::describe_expression(expr);
+                        text.push(format!("  Range {:#x}-{:#x}: {}", begin, end, description));
+                    }
+                    (text.join("\n"), json!({"kind": "multi-location", "ranges": entries.len()}))
+                }
+                Location::Value(_) => (format!("Symbol \"{}\" is constant.", name), json!({"kind": "constant"})),
+                Location::OptimizedOut => (
+                    format!("Symbol \"{}\" is optimized out.", name),
+                    json!({"kind": "optimized-out"}),
+                ),
+            }
+        } else if let Some(addr) = self.debug_data.get_addr_for_function(None, name) {
+            (
+                format!("Symbol \"{}\" is a function at address {:#x}.", name, addr),
+                json!({"kind": "function", "addr": format!("{:#x}", addr)}),
+            )
+        } else {
+            self.out.error(&format!("No symbol \"{}\" in current context.", name));
+            return;
+        };
+        self.out.result("symbol", fields, &text);
+    }
+
+    fn describe_expression(expr: &[u8]) -> String {
+        match dwarf_expr::register_operand(expr) {
+            Some(reg) => match dwarf_expr::REGISTER_NAMES.get(reg as usize) {
+                Some(name) => format!("a variable in ${}", name),
+                None if reg >= dwarf_expr::XMM0 && reg < dwarf_expr::XMM0 + 16 => {
+                    format!("a variable in $xmm{}", reg - dwarf_expr::XMM0)
+                }
+                None => format!("a variable in DWARF register {}", reg),
+            },
+            None if expr.is_empty() => "optimized out".to_string(),
+            None => "a variable with a complex DWARF expression".to_string(),
+        }
+    }
+
+    /// The innermost frame of the current thread, if the inferior is running.
+    fn innermost_frame(&self) -> Option<Frame> {
+        let inferior = self.inferior.as_ref()?;
+        inferior.backtrace(&self.debug_data).ok()?.first().copied()
+    }
+
+    /// The value of a variable for display: "<optimized out>" when the compiler did not keep it
+    /// at this point of the program.
+    fn format_variable(&self, var: &Variable, frame: Option<&Frame>) -> String {
+        let inferior = self.inferior.as_ref().unwrap();
+        match inferior.read_variable(&self.debug_data, var, frame) {
+            Ok(Some(bytes)) => {
+                values::format_value(&self.debug_data, &self.printers, inferior, &var.entity_type, &bytes)
+            },
+            Ok(None) => "<optimized out>".to_string(),
+            Err(err) => format!("<error: {}>", err),
+        }
+    }
+
+    /// The local of the current function called `name`, with its frame, or else the global.
+    fn find_variable(&self, name: &str) -> Option<(&Variable, Option<Frame>)> {
+        let frame = self.innermost_frame();
+        let local = frame.and_then(|frame| {
+            let vars = self.debug_data.get_frame_variables(frame.pc, frame.inline_depth);
+            vars.into_iter().find(|var| var.name == name)
+        });
+        match local {
+            Some(var) => Some((var, frame)),
+            None => Some((self.debug_data.global_variables().find(|var| var.name == name)?, None)),
+        }
+    }
+
+    /// The register value of a function argument: an integer or character literal, or an integer
+    /// or pointer variable.
+    fn argument_value(&self, arg: &str) -> Option<u64> {
+        let (negative, digits) = match arg.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, arg),
+        };
+        let number = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => digits.parse::<u64>().ok(),
+        };
+        if let Some(number) = number {
+            return Some(if negative { number.wrapping_neg() } else { number });
+        }
+        let chars: Vec<char> = arg.chars().collect();
+        if let ['\'', c, '\''] = chars.as_slice() {
+            return Some(*c as u64);
+        }
+        let (var, frame) = self.find_variable(arg)?;
+        let mut ty = &var.entity_type;
+        while let TypeKind::Alias(Some(aliased)) = ty.kind {
+            ty = self.debug_data.get_type(aliased)?;
+        }
+        match ty.kind {
+            TypeKind::Base(_) | TypeKind::Pointer(_) | TypeKind::Enum(_) if !ty.is_float() && ty.size <= 8 => {
+                let inferior = self.inferior.as_ref()?;
+                let bytes = inferior.read_variable(&self.debug_data, var, frame.as_ref()).ok()??;
+                Some(values::read_uint(&bytes))
+            }
+            _ => None,
+        }
+    }
+
+    /// How a value of type `ty` that a function returned with `regs` and `fpregs` is shown.
+    fn returned_value(&self, ty: &Type, regs: &libc::user_regs_struct, fpregs: &libc::user_fpregs_struct) -> String {
+        let inferior = self.inferior.as_ref().unwrap();
+        let xmm = |n: usize| fpregs.xmm_space[n * 4] as u64 | (fpregs.xmm_space[n * 4 + 1] as u64) << 32;
+        let bytes = match values::returned_bytes(&self.debug_data, ty, [regs.rax, regs.rdx], [xmm(0), xmm(1)]) {
+            Some(bytes) => bytes,
+            // the caller passed the address of a buffer for it in %rdi, which comes back in %rax
+            None => match inferior.read_memory(regs.rax as usize, ty.size) {
+                Ok(bytes) => bytes,
+                Err(err) => return format!("<error: {}>", err),
+            },
+        };
+        values::format_value(&self.debug_data, &self.printers, inferior, ty, &bytes)
+    }
+
+    /// `call f(args)` and `print f(args)`: calls a function of the program in the current thread
+    /// and prints what it returns, if anything.
+    fn call_function(&mut self, name: &str, args: &[String]) -> Result<(), DebuggerError> {
+        if self.inferior.is_none() {
+            self.out.error("You can't do that without a process to debug.");
+            return Ok(());
+        }
+        let addr = match self.debug_data.get_addr_for_function(None, name) {
+            Some(addr) => addr,
+            None => {
+                self.out.error(&format!("No symbol \"{}\" in current context.", name));
+                return Ok(());
+            }
+        };
+        if args.len() > inferior::ARGUMENT_REGISTERS {
+            self.out.error(&format!("Too many arguments in function call (at most {}).", inferior::ARGUMENT_REGISTERS));
+            return Ok(());
+        }
+        let mut values = Vec::new();
+        for arg in args {
+            match self.argument_value(arg) {
+                Some(value) => values.push(value),
+                None => {
+                    self.out.error(&format!(
+                        "Cannot pass \"{}\": arguments are integers, characters, or integer or pointer variables.",
+                        arg
+                    ));
+                    return Ok(());
+                }
+            }
+        }
+        // a function without debugging information is taken to return int, as C used to
+        let return_type = match self.debug_data.files().iter().flat_map(|file| file.functions.iter()).find(|func| func.address == addr) {
+            Some(func) => func.return_type.clone(),
+            None => Some(Type { name: "int".to_string(), size: 4, ..Type::default() }),
+        };
+        if return_type.as_ref().map_or(false, |ty| ty.size > 16) {
+            self.out.error(&format!("Cannot call {}: it returns a struct in memory.", name));
+            return Ok(());
+        }
+        let call = format!("{}({})", name, args.join(", "));
+        match self.inferior.as_mut().unwrap().call_function(addr, &values, &self.breakpoints)? {
+            CallResult::Returned(regs, fpregs) => {
+                if let Some(ty) = return_type {
+                    let value = self.returned_value(&ty, &regs, &fpregs);
+                    let fields = json!({"name": call, "type": ty.name, "value": value});
+                    self.out.result("value", fields, &format!("{} = {}", call, value));
+                }
+            }
+            CallResult::Interrupted(status @ Status::Exited(_)) | CallResult::Interrupted(status @ Status::Signaled(_)) => {
+                self.out.message(&format!("The program being debugged exited while in {}, called from deet.", call));
+                self.report_status(status);
+            }
+            CallResult::Interrupted(status) => {
+                let reason = match status {
+                    Status::Stopped(Signal::SIGTRAP, rip) if self.breakpoints.contains_key(&(rip - 1)) => {
+                        "hit a breakpoint".to_string()
                     }
+                    Status::Stopped(signal, _) => format!("received signal {}", signal),
+                    _ => "stopped".to_string(),
+                };
+                self.out.error(&format!(
+                    "The program being debugged {} while in {}, called from deet. The call was abandoned and the program's state restored.",
+                    reason, call
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// `finish`: runs until the current function returns to its caller, and prints what it
+    /// returned.
+    fn finish(&mut self) -> Result<(), DebuggerError> {
+        let frames = match self.inferior.as_ref() {
+            Some(inferior) => inferior.backtrace(&self.debug_data)?,
+            None => {
+                self.out.error("The program is not being run.");
+                return Ok(());
+            }
+        };
+        let (frame, caller) = match (frames.first(), frames.iter().find(|frame| frame.level == 1)) {
+            (Some(frame), Some(caller)) => (*frame, *caller),
+            _ => {
+                self.out.error("\"finish\" not meaningful in the outermost frame.");
+                return Ok(());
+            }
+        };
+        // an inlined call has no return of its own to stop at, nor a register its value is in
+        if !self.debug_data.get_inlined_calls(frame.pc).is_empty() {
+            self.out.error("\"finish\" out of an inlined function is not supported.");
+            return Ok(());
+        }
+        let return_type = self.debug_data.get_function_containing(frame.pc).and_then(|func| func.return_type.clone());
+        self.out.message(&self.location_text_with("Run till exit from", frame.rip));
+        if self.run_to(caller.rip, true)? {
+            if let Some(ty) = return_type {
+                let inferior = self.inferior.as_ref().unwrap();
+                let value = self.returned_value(&ty, &inferior.registers()?, &inferior.fp_registers()?);
+                // the command already has its "running" result, so the value comes with an event
+                let fields = json!({"return-value": value});
+                self.out.event("function-finished", fields, &format!("Value returned is {}", value));
+            }
+        }
+        Ok(())
+    }
+
+    /// `return [value]`: pops the current frame without running the rest of its function, as if
+    /// it had returned `value`. Only %rip, %rsp and %rbp of the caller are restored; registers
+    /// the function saved for it are not.
+    fn force_return(&mut self, value: Option<&str>) -> Result<(), DebuggerError> {
+        if self.recorder.is_some() {
+            self.out.error("Cannot return while recording; use \"record stop\" first.");
+            return Ok(());
+        }
+        let frames = match self.inferior.as_ref() {
+            Some(inferior) => inferior.backtrace(&self.debug_data)?,
+            None => {
+                self.out.error("The program is not being run.");
+                return Ok(());
+            }
+        };
+        let (frame, caller) = match (frames.first(), frames.iter().find(|frame| frame.level == 1)) {
+            (Some(frame), Some(caller)) => (*frame, *caller),
+            _ => {
+                self.out.error("Can not force return from the outermost frame.");
+                return Ok(());
+            }
+        };
+        if !self.debug_data.get_inlined_calls(frame.pc).is_empty() {
+            self.out.error("Can not force return from an inlined function.");
+            return Ok(());
+        }
+        let return_type = self.debug_data.get_function_containing(frame.pc).and_then(|func| func.return_type.clone());
+        let inferior = self.inferior.as_ref().unwrap();
+        // the frames are those of the current thread, which `thread N` may have changed
+        let mut regs = ptrace::getregs(inferior.current_tid())?;
+        if let Some(value) = value {
+            match &return_type {
+                Some(ty) if ty.is_float() => {
+                    let number: f64 = match value.parse() {
+                        Ok(number) => number,
+                        Err(_) => {
+                            self.out.error(&format!("Invalid number \"{}\".", value));
+                            return Ok(());
+                        }
+                    };
+                    let bits = if ty.size == 4 { (number as f32).to_bits() as u64 } else { number.to_bits() };
+                    let mut fpregs = inferior.fp_registers()?;
+                    fpregs.xmm_space[0] = bits as u32;
+                    fpregs.xmm_space[1] = (bits >> 32) as u32;
+                    inferior.set_fp_registers(fpregs)?;
+                }
+                Some(ty) if ty.size > 8 => {
+                    self.out.error("Can only return integers, pointers and floating point numbers.");
+                    return Ok(());
                 }
+                _ => match self.argument_value(value) {
+                    Some(number) => regs.rax = number,
+                    None => {
+                        self.out.error(&format!("Cannot return \"{}\": use an integer, a character or a variable.", value));
+                        return Ok(());
+                    }
+                },
+            }
+        }
+        // as the function's ret would leave them
+        regs.rip = caller.rip as u64;
+        regs.rsp = frame.cfa as u64;
+        regs.rbp = caller.rbp as u64;
+        // keep a system call the thread was stopped in from being restarted
+        regs.orig_rax = u64::MAX;
+        ptrace::setregs(inferior.current_tid(), regs)?;
+        self.out.result("frame", self.frame_json(caller.rip), &self.location_text_with("#0", caller.rip));
+        Ok(())
+    }
+
+    /// `print <name>`: the value of a local of the current function or of a global variable.
+    fn print_variable(&mut self, name: &str) {
+        if self.inferior.is_none() {
+            self.out.error("The program is not being run.");
+            return;
+        }
+        let (var, frame) = match self.find_variable(name) {
+            Some(found) => found,
+            None => {
+                self.out.error(&format!("No symbol \"{}\" in current context.", name));
+                return;
+            }
+        };
+        let value = self.format_variable(var, frame.as_ref());
+        let text = format!("{} = {}", name, value);
+        let fields = json!({"name": name, "type": var.entity_type.name, "value": value});
+        self.out.result("value", fields, &text);
+    }
+
+    /// `info locals`: the variables of the current function.
+    fn info_locals(&mut self) {
+        let frame = match self.innermost_frame() {
+            Some(frame) => frame,
+            None => {
+                self.out.error("No frame selected.");
+                return;
             }
+        };
+        let vars = self.debug_data.get_frame_variables(frame.pc, frame.inline_depth);
+        if vars.is_empty() {
+            self.out.result("locals", json!([]), "No locals.");
+            return;
+        }
+        let mut text = Vec::new();
+        let mut list = Vec::new();
+        for var in vars {
+            let value = self.format_variable(var, Some(&frame));
+            text.push(format!("{} = {}", var.name, value));
+            list.push(json!({"name": var.name, "type": var.entity_type.name, "value": value}));
+        }
+        self.out.result("locals", json!(list), &text.join("\n"));
+    }
+
+    /// `info symbol <addr>`: the function or global variable an address belongs to.
+    fn info_symbol(&mut self, addr: &str) {
+        let address = match self.parse_address(addr) {
+            Some(address) => address,
+            None => {
+                self.out.error("Invalid address");
+                return;
+            }
+        };
+        match self.symbol_for(address) {
+            Some((name, offset)) => {
+                let text = if offset == 0 { name.clone() } else { format!("{} + {}", name, offset) };
+                self.out.result("symbol", json!({"name": name, "offset": offset}), &text);
+            }
+            None => self.out.error(&format!("No symbol matches {}.", addr)),
+        }
+    }
+
+    /// The function (per addr2line) or global variable containing `addr`, and the offset into it.
+    fn symbol_for(&self, addr: usize) -> Option<(String, usize)> {
+        if let Some(name) = self.debug_data.get_function_from_addr(addr) {
+            let start = self.debug_data.get_function_containing(addr).map_or(addr, |func| func.address);
+            return Some((name, addr - start));
+        }
+        let var = self.debug_data.get_global_containing(addr)?;
+        match var.location {
+            Location::Address(start) => Some((var.name.clone(), addr - start)),
+            _ => None,
+        }
+    }
+
+    /// `<func+offset>` for an address, or nothing if it is not in a known symbol.
+    fn symbolize(&self, addr: usize) -> String {
+        match self.symbol_for(addr) {
+            Some((name, 0)) => format!("<{}>", name),
+            Some((name, offset)) => format!("<{}+{}>", name, offset),
+            None => String::new(),
+        }
+    }
+
+    /// "Stopped at func (file:line)", or nothing if we do not know where `rip` is.
+    fn location_text(&self, rip: usize) -> String {
+        self.location_text_with("Stopped at", rip)
+    }
+
+    fn location_text_with(&self, prefix: &str, rip: usize) -> String {
+        let _line = self.debug_data.get_line_from_addr(rip);
+        let _func = self.debug_data.get_function_from_addr(rip);
+        match (_line, _func) {
+            (Some(line), Some(func)) => format!("{} {} ({})", prefix, func, line),
+            // e.g. only the symbol table is available
+            (None, Some(func)) => format!("{} {} (source file not found)", prefix, func),
+            _ => String::new(),
+        }
+    }
+
+    /// Describes a code address for the JSON interpreter.
+    fn frame_json(&self, rip: usize) -> Value {
+        let mut frame = json!({ "addr": format!("{:#x}", rip) });
+        if let Some(func) = self.debug_data.get_function_from_addr(rip) {
+            frame["func"] = json!(func);
         }
+        if let Some(line) = self.debug_data.get_line_from_addr(rip) {
+            frame["file"] = json!(line.file);
+            frame["line"] = json!(line.number);
+        }
+        frame
+    }
+
+    /// Describes a frame of the backtrace for the JSON interpreter.
+    fn stack_frame_json(&self, frame: &Frame) -> Value {
+        let mut json = json!({
+            "addr": format!("{:#x}", frame.rip),
+            "inlined": frame.inline_depth < self.debug_data.get_inlined_calls(frame.pc).len(),
+        });
+        if let Some(func) = self.debug_data.get_frame_function(frame.pc, frame.inline_depth) {
+            json["func"] = json!(func);
+        }
+        if let Some(line) = self.debug_data.get_frame_line(frame.pc, frame.inline_depth) {
+            json["file"] = json!(line.file);
+            json["line"] = json!(line.number);
+        }
+        json
+    }
+
+    /// Kills the running inferior, if there is one, and says so.
+    fn kill_inferior(&mut self) {
+        if let Some(mut inferior) = self.inferior.take() {
+            self.out.message(&format!("Killing running inferior (pid {})", inferior.pid()));
+            inferior.terminate();
+        }
+    }
+
+    /// Kills the processes backing all checkpoints.
+    fn delete_checkpoints(&mut self) {
+        for (_, checkpoint, _) in self.checkpoints.iter_mut() {
+            checkpoint.terminate();
+        }
+        self.checkpoints.clear();
     }
 
     fn parse_address(&self, addr: &str) -> Option<usize> {
@@ -181,6 +1626,9 @@ impl Debugger {
     ///
     /// You don't need to read, understand, or modify this function.
     fn get_next_command(&mut self) -> DebuggerCommand {
+        if self.out.is_json() {
+            return self.get_next_json_command();
+        }
         loop {
             // Print prompt and get next line of user input
             match self.readline.readline("(deet) ") {
@@ -193,7 +1641,8 @@ impl Debugger {
                     return DebuggerCommand::Quit;
                 }
                 Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+                    println!("Error reading the command: {}", err);
+                    return DebuggerCommand::Quit;
                 }
                 Ok(line) => {
                     if line.trim().len() == 0 {
@@ -207,13 +1656,424 @@ impl Debugger {
                         );
                     }
                     let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    match DebuggerCommand::from_tokens(&tokens) {
+                        Ok(cmd) => return cmd,
+                        Err(err) => println!("{}", err),
                     }
                 }
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Like get_next_command, for `--interpreter=json`: commands are read from stdin without a
+    /// prompt or history, and may start with a token that is echoed back in their result.
+    fn get_next_json_command(&mut self) -> DebuggerCommand {
+        let stdin = std::io::stdin();
+        loop {
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    self.out.begin(None);
+                    return DebuggerCommand::Quit;
+                }
+                Ok(_) => {}
+            }
+            let (token, command) = output::split_token(line.trim());
+            let tokens: Vec<&str> = command.split_whitespace().collect();
+            if tokens.is_empty() {
+                continue;
+            }
+            self.out.begin(token);
+            match DebuggerCommand::from_tokens(&tokens) {
+                Ok(cmd) => return cmd,
+                Err(err) => self.out.error(&err.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A debugger whose JSON records go to a file, so that tests can look at what each command
+    /// reported.
+    struct Session {
+        debugger: Debugger,
+        records: std::path::PathBuf,
+        seen: usize,
+    }
+
+    impl Session {
+        fn new(program: &str) -> Session {
+            // Debugger::new exits the whole test binary on a missing program
+            assert!(std::path::Path::new(program).exists(), "{} is missing: run make first", program);
+            let id = SESSIONS.fetch_add(1, Ordering::SeqCst);
+            let records = std::env::temp_dir().join(format!("deet-test-{}-{}", std::process::id(), id));
+            let file = std::fs::File::create(&records).unwrap();
+            Session {
+                debugger: Debugger::new(program, Output::json_to(file)),
+                records,
+                seen: 0,
+            }
+        }
+
+        /// Runs the command on `line`, and returns the records it produced.
+        fn execute(&mut self, line: &str) -> Vec<Value> {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let cmd = DebuggerCommand::from_tokens(&tokens).unwrap_or_else(|err| panic!("{}: {}", line, err));
+            self.debugger.out.begin(None);
+            if let Err(err) = self.debugger.execute(cmd) {
+                self.debugger.out.error(&err.to_string());
+            }
+            self.debugger.out.finish();
+            let text = std::fs::read_to_string(&self.records).unwrap();
+            let records: Vec<Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+            let new = records[self.seen..].to_vec();
+            self.seen = records.len();
+            new
+        }
+
+        /// The result record of the command on `line`.
+        fn result(&mut self, line: &str) -> Value {
+            let records = self.execute(line);
+            records.into_iter().find(|record| record["type"] == "result").unwrap()
+        }
+
+        /// The value `finish` reports.
+        fn finish_value(&mut self) -> Value {
+            let records = self.execute("finish");
+            let finished = records.iter().find(|record| record["class"] == "function-finished");
+            finished.map_or(Value::Null, |record| record["return-value"].clone())
+        }
+    }
+
+    impl Drop for Session {
+        fn drop(&mut self) {
+            if let Some(inferior) = self.debugger.inferior.as_mut() {
+                inferior.terminate();
+            }
+            self.debugger.delete_checkpoints();
+            let _ = std::fs::remove_file(&self.records);
+        }
+    }
+
+    #[test]
+    fn test_exec_drops_recording_and_checkpoints() {
+        let mut session = Session::new("samples/fork_exec");
+        for line in &["break main", "run", "checkpoint", "record", "catch exec"] {
+            session.execute(line);
+        }
+        assert_eq!(session.debugger.checkpoints.len(), 1);
+        // the fork makes the program stop for SIGCHLD on the way
+        for _ in 0..3 {
+            if session.debugger.recorder.is_none() {
+                break;
+            }
+            session.execute("continue");
+        }
+        assert!(session.debugger.recorder.is_none());
+        assert!(session.debugger.checkpoints.is_empty());
+        assert!(session.debugger.breakpoints.is_empty());
+        assert!(session.debugger.target.ends_with("samples/fork_exec"));
+    }
+
+    #[test]
+    fn test_restart_after_temporary_breakpoint() {
+        let mut session = Session::new("samples/loop");
+        session.execute("break main");
+        session.execute("run");
+        session.execute("tbreak 14");
+        session.execute("checkpoint");
+        session.execute("continue");
+        assert_eq!(session.debugger.breakpoints.len(), 1);
+        assert_eq!(session.result("restart 1")["class"], "done");
+        // the checkpoint was taken with the temporary breakpoint still in its memory
+        let records = session.execute("continue");
+        let exited = records.iter().find(|record| record["class"] == "exited");
+        assert_eq!(exited.map(|record| record["exit-code"].clone()), Some(json!(0)), "{:?}", records);
+    }
+
+    #[test]
+    fn test_checkpoint_keeps_pending_signals() {
+        let mut session = Session::new("samples/signals");
+        session.execute("break ready");
+        session.execute("run");
+        // the signal arrives while the checkpoint is being made
+        let pid = session.debugger.inferior.as_ref().unwrap().pid();
+        nix::sys::signal::kill(pid, Signal::SIGUSR1).unwrap();
+        assert_eq!(session.result("checkpoint")["class"], "done");
+        let records = session.execute("continue");
+        let exited = records.iter().find(|record| record["class"] == "exited");
+        assert_eq!(exited.map(|record| record["exit-code"].clone()), Some(json!(0)), "{:?}", records);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut session = Session::new("samples/call");
+        session.execute("break main");
+        session.execute("run");
+        assert_eq!(session.result("call add(2, 40)")["value"]["value"], "42");
+        assert_eq!(session.result("call add(-2, 0x10)")["value"]["value"], "14");
+        assert_eq!(session.result("call add('a', 0)")["value"]["value"], "97");
+        assert_eq!(session.result("call average(3, 4)")["value"]["value"], "3.5");
+        // calls have side effects, and take variables as arguments
+        assert_eq!(session.result("call bump()")["class"], "done");
+        assert_eq!(session.result("call add(counter, 1)")["value"]["value"], "2");
+        assert_eq!(session.result("call add(1.5, 1)")["class"], "error");
+        // the program goes on where it was
+        let records = session.execute("continue");
+        assert!(records.iter().any(|record| record["class"] == "exited"), "{:?}", records);
+    }
+
+    #[test]
+    fn test_call_in_other_thread() {
+        let mut session = Session::new("samples/threads");
+        session.execute("break worker");
+        session.execute("run");
+        session.execute("thread 1");
+        assert_eq!(session.result("call square(7)")["value"]["value"], "49");
+        // the worker that hit the breakpoint goes on from it, once per worker
+        let mut hits = 1;
+        loop {
+            let records = session.execute("continue");
+            if let Some(exited) = records.iter().find(|record| record["class"] == "exited") {
+                assert_eq!(exited["exit-code"], 0);
+                break;
+            }
+            let stopped = records.iter().find(|record| record["class"] == "stopped").unwrap();
+            assert_eq!(stopped["frame"]["func"], "worker", "{:?}", records);
+            hits += 1;
+        }
+        assert_eq!(hits, 3);
+    }
+
+    #[test]
+    fn test_finish() {
+        let mut session = Session::new("samples/call");
+        session.execute("break add");
+        session.execute("break average");
+        session.execute("run");
+        assert_eq!(session.finish_value(), "3");
+        let pc = session.debugger.innermost_frame().unwrap().pc;
+        assert_eq!(session.debugger.debug_data.get_function_containing(pc).unwrap().name, "main");
+        session.execute("continue");
+        assert_eq!(session.finish_value(), "1.5");
+        assert_eq!(session.result("finish")["class"], "error");
+    }
+
+    #[test]
+    fn test_return() {
+        let mut session = Session::new("samples/call");
+        session.execute("break add");
+        session.execute("break average");
+        session.execute("run");
+        assert_eq!(session.result("return 7")["frame"]["func"], "main");
+        session.execute("until");
+        assert_eq!(session.result("print sum")["value"]["value"], "7");
+        session.execute("continue");
+        session.execute("return 2.5");
+        session.execute("until");
+        assert_eq!(session.result("print mean")["value"]["value"], "2.5");
+        assert_eq!(session.result("return")["class"], "error");
+    }
+
+    #[test]
+    fn test_run_to() {
+        let mut session = Session::new("samples/call");
+        session.execute("break main");
+        session.execute("run");
+        let average = session.debugger.debug_data.get_addr_for_function(None, "average").unwrap();
+        assert!(session.debugger.run_to(average, false).unwrap());
+        // bump has already run, and main does not return anywhere we know of
+        let bump = session.debugger.debug_data.get_addr_for_function(None, "bump").unwrap();
+        session.execute("finish");
+        assert!(!session.debugger.run_to(bump, false).unwrap());
+        assert!(session.debugger.inferior.is_none());
+    }
+
+    #[test]
+    fn test_reverse_continue_undoes_heap_writes() {
+        let mut session = Session::new("samples/record");
+        session.execute("break fill");
+        session.execute("break 16");
+        session.execute("run");
+        let heap = session.result("print heap")["value"]["value"].clone();
+        let heap = usize::from_str_radix(heap.as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+        let read_heap = |session: &Session| session.debugger.inferior.as_ref().unwrap().read_memory(heap, 8).unwrap();
+        session.execute("record");
+        // memset runs through the dynamic linker and libc, which write with vector instructions
+        session.execute("continue");
+        assert_eq!(read_heap(&session), [1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(session.result("print total")["value"]["value"], "2");
+        session.execute("reverse-continue");
+        assert_eq!(read_heap(&session), [0; 8]);
+        assert_eq!(session.result("print total")["value"]["value"], "0");
+        let pc = session.debugger.innermost_frame().unwrap().pc;
+        assert_eq!(session.debugger.debug_data.get_function_containing(pc).unwrap().name, "fill");
+        // running forwards again records the same writes
+        session.execute("continue");
+        assert_eq!(read_heap(&session), [1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_threads() {
+        let mut session = Session::new("samples/threads");
+        session.execute("break worker");
+        let records = session.execute("run");
+        let stopped = records.iter().find(|record| record["class"] == "stopped").unwrap();
+        let worker = stopped["thread-id"].clone();
+        assert_eq!(stopped["frame"]["func"], "worker");
+
+        let info = session.result("info threads");
+        assert_eq!(info["current-thread-id"], worker);
+        let threads = info["threads"].as_array().unwrap();
+        assert!(threads.len() > 1, "{}", info);
+        let thread = |id: &Value| threads.iter().find(|thread| &thread["id"] == id).unwrap().clone();
+        assert_eq!(thread(&worker)["frame"]["func"], "worker");
+        assert_eq!(session.result("backtrace")["stack"][0]["func"], "worker");
+
+        // the main thread is waiting in libc, and backtraces follow the selected thread
+        let main = thread(&json!(1));
+        assert_eq!(session.result("thread 1")["new-thread-id"], 1);
+        assert_eq!(session.result("backtrace")["stack"][0]["addr"], main["frame"]["addr"]);
+        assert_eq!(session.result("thread 99")["class"], "error");
+
+        // every worker hits the breakpoint once, each in its own thread
+        let mut hits = vec![worker];
+        loop {
+            let records = session.execute("continue");
+            if let Some(exited) = records.iter().find(|record| record["class"] == "exited") {
+                assert_eq!(exited["exit-code"], 0);
+                break;
+            }
+            let stopped = records.iter().find(|record| record["class"] == "stopped").unwrap();
+            assert_eq!(stopped["frame"]["func"], "worker");
+            assert!(!hits.contains(&stopped["thread-id"]));
+            hits.push(stopped["thread-id"].clone());
+        }
+        assert_eq!(hits.len(), 3);
+        assert_eq!(session.result("info threads")["threads"], json!([]));
+    }
+
+    #[test]
+    fn test_info_symbols() {
+        let mut session = Session::new("samples/function_calls");
+        let functions = session.result("info functions ^func[12]$");
+        let names: Vec<&str> = functions["functions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|func| func["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["func1", "func2"]);
+        assert_eq!(functions["functions"][0]["line"], 16);
+        // functions without debugging information come from the symbol table
+        let functions = session.result("info functions ^_start$");
+        assert_eq!(functions["functions"][0]["file"], Value::Null);
+        assert_eq!(session.result("info functions (")["class"], "error");
+
+        let variables = session.result("info variables glob");
+        assert_eq!(variables["variables"], json!([{
+            "name": "global",
+            "type": "int",
+            "file": variables["variables"][0]["file"],
+            "line": 3,
+        }]));
+
+        let line = session.result("info line func2");
+        assert_eq!(line["line"], 9);
+        let start = &line["ranges"][0]["start"];
+        assert_eq!(session.result("info line 11")["line"], 11);
+        assert_eq!(session.result("info line function_calls.c:13")["line"], 13);
+        assert_eq!(session.result("info line 2")["ranges"], json!([]));
+        assert_eq!(session.result("info line nowhere")["class"], "error");
+
+        let address = session.result("info address func2");
+        assert_eq!(address["symbol"]["kind"], "function");
+        assert_eq!(&address["symbol"]["addr"], start);
+        assert_eq!(session.result("info address global")["symbol"]["kind"], "static");
+        assert_eq!(session.result("info address nothing")["class"], "error");
+
+        let func2 = start.as_str().unwrap();
+        let symbol = session.result(&format!("info symbol {}", func2));
+        assert_eq!(symbol["symbol"], json!({"name": "func2", "offset": 0}));
+        let addr = usize::from_str_radix(&func2[2..], 16).unwrap() + 4;
+        let symbol = session.result(&format!("info symbol {:#x}", addr));
+        assert_eq!(symbol["symbol"], json!({"name": "func2", "offset": 4}));
+        assert_eq!(session.result("info symbol 0x10")["class"], "error");
+    }
+
+    #[test]
+    fn test_until_and_advance() {
+        let mut session = Session::new("samples/loop");
+        assert_eq!(session.result("until 14")["class"], "error");
+        session.execute("break main");
+        session.execute("run");
+        let line = |records: Vec<Value>| {
+            let stopped = records.into_iter().find(|record| record["class"] == "stopped").unwrap();
+            (stopped["reason"].clone(), stopped["frame"]["line"].clone())
+        };
+        assert_eq!(line(session.execute("until")), (json!("end-stepping-range"), json!(10)));
+        // leaves the loop
+        assert_eq!(line(session.execute("until 14")), (json!("location-reached"), json!(14)));
+        assert_eq!(session.result("print total")["value"]["value"], "10");
+        assert_eq!(session.debugger.breakpoints.len(), 1);
+
+        // functions are entered before their prologue, as with break
+        assert_eq!(line(session.execute("advance factorial")).1, 3);
+        assert_eq!(line(session.execute("until 4")).1, 4);
+        assert_eq!(session.result("print n")["value"]["value"], "4");
+        // until stays in this frame, while advance goes down the recursion
+        assert_eq!(line(session.execute("until 6")).1, 6);
+        assert_eq!(session.result("print n")["value"]["value"], "4");
+        assert_eq!(line(session.execute("advance 5")).1, 5);
+        assert_eq!(session.result("print n")["value"]["value"], "1");
+        // a location that is never reached stops when the current function returns
+        assert_eq!(line(session.execute("until 10")), (json!("location-reached"), json!(6)));
+        assert_eq!(session.result("print n")["value"]["value"], "2");
+        assert_eq!(session.debugger.breakpoints.len(), 1);
+    }
+
+    #[test]
+    fn test_tbreak() {
+        let mut session = Session::new("samples/loop");
+        session.execute("tbreak 12");
+        let records = session.execute("run");
+        let stopped = records.iter().find(|record| record["class"] == "stopped").unwrap();
+        assert_eq!((&stopped["disp"], &stopped["frame"]["line"]), (&json!("del"), &json!(12)));
+        // the loop comes back to line 12, but the breakpoint is gone
+        assert!(session.debugger.breakpoints.is_empty());
+        assert!(session.debugger.temporary_breakpoints.is_empty());
+        let records = session.execute("continue");
+        let exited = records.iter().find(|record| record["class"] == "exited").unwrap();
+        assert_eq!(exited["exit-code"], 0);
+    }
+
+    #[test]
+    fn test_frame_after_0xcc_byte() {
+        let mut session = Session::new("samples/frame");
+        session.execute("break 5");
+        session.execute("run");
+        session.execute("until");
+        // the byte before %rip is 0xcc, but no breakpoint
+        assert_eq!(session.result("backtrace")["stack"][0]["line"], 8);
+    }
+
+    #[test]
+    fn test_continue_after_null_call() {
+        let mut session = Session::new("samples/null_call");
+        let records = session.execute("run");
+        let stopped = records.iter().find(|record| record["class"] == "stopped").unwrap();
+        assert_eq!(stopped["signal"], "SIGSEGV");
+        assert_eq!(session.debugger.inferior.as_ref().unwrap().registers().unwrap().rip, 0);
+        assert_eq!(session.result("backtrace")["class"], "done");
+        // the call faults again
+        let records = session.execute("continue");
+        let stopped = records.iter().find(|record| record["class"] == "stopped").unwrap();
+        assert_eq!(stopped["signal"], "SIGSEGV");
+    }
+}
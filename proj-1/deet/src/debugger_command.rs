@@ -1,27 +1,509 @@
+use crate::error::DebuggerError;
+
+/// Where `run` sends the standard streams of the program: `< in`, `> out` or `>> out`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Redirections {
+    pub stdin: Option<String>,
+    /// The file, and whether to append to it
+    pub stdout: Option<(String, bool)>,
+}
+
+impl Redirections {
+    /// Takes the redirections out of the arguments of `run`. The file name may be attached to
+    /// the operator (`<in.txt`) or be the next argument. None if an operator has no file.
+    fn parse(tokens: &[&str]) -> Option<(Vec<String>, Redirections)> {
+        let mut args = Vec::new();
+        let mut redirections = Redirections::default();
+        let mut tokens = tokens.iter();
+        while let Some(token) = tokens.next() {
+            let (operator, attached) = if token.starts_with(">>") {
+                (">>", &token[2..])
+            } else if token.starts_with('<') || token.starts_with('>') {
+                (&token[..1], &token[1..])
+            } else {
+                args.push(token.to_string());
+                continue;
+            };
+            let file = if attached.is_empty() { tokens.next()?.to_string() } else { attached.to_string() };
+            match operator {
+                "<" => redirections.stdin = Some(file),
+                _ => redirections.stdout = Some((file, operator == ">>")),
+            }
+        }
+        Some((args, redirections))
+    }
+}
+
 pub enum DebuggerCommand {
     Quit,
-    Run(Vec<String>),
+    Run(Vec<String>, Redirections),
     Continue,
     Backtrace,
     Breakpoint(String),
+    TemporaryBreakpoint(String),
+    Until(Option<String>),
+    Advance(String),
+    CatchSyscall(Vec<String>),
+    CatchSignal(Vec<String>),
+    CatchFork,
+    CatchExec,
+    CatchExit,
+    InfoThreads,
+    Thread(usize),
+    Record,
+    RecordStop,
+    ReverseStep,
+    ReverseNext,
+    ReverseContinue,
+    Checkpoint,
+    InfoCheckpoints,
+    Restart(usize),
+    InfoFunctions(Option<String>),
+    InfoVariables(Option<String>),
+    InfoLine(String),
+    InfoAddress(String),
+    InfoSymbol(String),
+    SetDebugFileDirectory(String),
+    SetInferiorTty(Option<String>),
+    SetEnv(String, Option<String>),
+    UnsetEnv(Option<String>),
+    SetCwd(Option<String>),
+    Print(String),
+    /// A function and its arguments, unevaluated
+    Call(String, Vec<String>),
+    Finish,
+    Return(Option<String>),
+    InfoLocals,
+    Layout(String),
+    SaveBreakpoints(String),
+    Help(Option<String>),
+}
+
+/// An entry of the command table: a command, the abbreviations it also goes by, and its help.
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// The second words it takes, for commands that are a family of subcommands
+    pub subcommands: &'static [&'static str],
+    /// One line for each way of calling it
+    pub usage: &'static [&'static str],
+    pub help: &'static str,
+}
+
+/// Every command, in alphabetical order. Besides by name and alias, a command can be given by any
+/// prefix of its name that no other command shares.
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "advance",
+        aliases: &[],
+        subcommands: &[],
+        usage: &["advance <location>"],
+        help: "Run until <location> is reached, or the current function returns.",
+    },
+    CommandSpec {
+        name: "backtrace",
+        aliases: &["bt", "back"],
+        subcommands: &[],
+        usage: &["backtrace"],
+        help: "Print the call stack of the current thread.",
+    },
+    CommandSpec {
+        name: "break",
+        aliases: &["b", "breakpoint"],
+        subcommands: &[],
+        usage: &["break <location>"],
+        help: "Set a breakpoint.",
+    },
+    CommandSpec {
+        name: "call",
+        aliases: &[],
+        subcommands: &[],
+        usage: &["call <function>(<argument>, ...)"],
+        help: "Call a function of the program and print what it returns.",
+    },
+    CommandSpec {
+        name: "catch",
+        aliases: &[],
+        subcommands: &["exec", "exit", "fork", "signal", "syscall"],
+        usage: &["catch syscall [name|number]...", "catch signal [signal]...", "catch fork", "catch exec", "catch exit"],
+        help: "Stop when the program makes a system call, receives a signal, forks, calls exec or exits.",
+    },
+    CommandSpec {
+        name: "checkpoint",
+        aliases: &[],
+        subcommands: &[],
+        usage: &["checkpoint"],
+        help: "Take a snapshot of the program to come back to with restart.",
+    },
+    CommandSpec {
+        name: "continue",
+        aliases: &["c", "cont"],
+        subcommands: &[],
+        usage: &["continue"],
+        help: "Resume the program until it stops again.",
+    },
+    CommandSpec {
+        name: "finish",
+        aliases: &["fin"],
+        subcommands: &[],
+        usage: &["finish"],
+        help: "Run until the current function returns, and print the value it returns.",
+    },
+    CommandSpec {
+        name: "help",
+        aliases: &["h"],
+        subcommands: &[],
+        usage: &["help [command]"],
+        help: "List the commands, or describe one.",
+    },
+    CommandSpec {
+        name: "info",
+        aliases: &["i"],
+        subcommands: &["address", "checkpoints", "functions", "line", "locals", "symbol", "threads", "variables"],
+        usage: &[
+            "info threads",
+            "info checkpoints",
+            "info functions [regex]",
+            "info variables [regex]",
+            "info locals",
+            "info line <location>",
+            "info address <name>",
+            "info symbol <address>",
+        ],
+        help: "Show the threads, checkpoints or symbols of the program.",
+    },
+    CommandSpec {
+        name: "layout",
+        aliases: &[],
+        subcommands: &["regs", "src"],
+        usage: &["layout src|regs"],
+        help: "Show the source alone, or below the registers (with --tui).",
+    },
+    CommandSpec {
+        name: "print",
+        aliases: &["p"],
+        subcommands: &[],
+        usage: &["print <variable>", "print <function>(<argument>, ...)"],
+        help: "Print the value of a variable, or what a function call returns.",
+    },
+    CommandSpec {
+        name: "quit",
+        aliases: &["q"],
+        subcommands: &[],
+        usage: &["quit"],
+        help: "Kill the program, remember the breakpoints for next time and exit.",
+    },
+    CommandSpec {
+        name: "record",
+        aliases: &["rec"],
+        subcommands: &["stop"],
+        usage: &["record", "record stop"],
+        help: "Start or stop recording the execution, for the reverse-* commands.",
+    },
+    CommandSpec {
+        name: "restart",
+        aliases: &[],
+        subcommands: &[],
+        usage: &["restart <checkpoint>"],
+        help: "Go back to a checkpoint.",
+    },
+    CommandSpec {
+        name: "return",
+        aliases: &[],
+        subcommands: &[],
+        usage: &["return [value]"],
+        help: "Make the current function return at once, with the value if one is given.",
+    },
+    CommandSpec {
+        name: "reverse-continue",
+        aliases: &["rc"],
+        subcommands: &[],
+        usage: &["reverse-continue"],
+        help: "Run backwards to the previous breakpoint.",
+    },
+    CommandSpec {
+        name: "reverse-next",
+        aliases: &["rn"],
+        subcommands: &[],
+        usage: &["reverse-next"],
+        help: "Run backwards to the previous line, over calls.",
+    },
+    CommandSpec {
+        name: "reverse-step",
+        aliases: &["rs"],
+        subcommands: &[],
+        usage: &["reverse-step"],
+        help: "Run backwards to the previous line, into calls.",
+    },
+    CommandSpec {
+        name: "run",
+        aliases: &["r"],
+        subcommands: &[],
+        usage: &["run [arg]... [<input] [>output|>>output]"],
+        help: "Start the program, killing it first if it is running.",
+    },
+    CommandSpec {
+        name: "save",
+        aliases: &[],
+        subcommands: &["breakpoints"],
+        usage: &["save breakpoints <file>"],
+        help: "Write the breakpoints to a file, as break commands.",
+    },
+    CommandSpec {
+        name: "set",
+        aliases: &[],
+        subcommands: &["cwd", "debug-file-directory", "env", "environment", "inferior-tty"],
+        usage: &["set env <name>=<value>", "set cwd [directory]", "set inferior-tty [tty]", "set debug-file-directory <directory>"],
+        help: "Change how the program is started, or where debugging symbols are looked for.",
+    },
+    CommandSpec {
+        name: "tbreak",
+        aliases: &["tb"],
+        subcommands: &[],
+        usage: &["tbreak <location>"],
+        help: "Set a breakpoint that is deleted when it is first hit.",
+    },
+    CommandSpec {
+        name: "thread",
+        aliases: &["t"],
+        subcommands: &[],
+        usage: &["thread <id>"],
+        help: "Switch to another thread.",
+    },
+    CommandSpec {
+        name: "unset",
+        aliases: &[],
+        subcommands: &["env", "environment"],
+        usage: &["unset env [name]"],
+        help: "Remove a variable, or all of them, from the environment of the program.",
+    },
+    CommandSpec {
+        name: "until",
+        aliases: &["u"],
+        subcommands: &[],
+        usage: &["until [location]"],
+        help: "Run until a later line of the current function, or <location>, is reached.",
+    },
+];
+
+impl CommandSpec {
+    /// "Usage: ..." with every way of calling the command.
+    pub fn usage_text(&self) -> String {
+        format!("Usage: {}", self.usage.join("\n       "))
+    }
+
+    /// The long help of `help <command>`.
+    pub fn help_text(&self) -> String {
+        let mut text = format!("{}\n{}", self.help, self.usage_text());
+        if !self.aliases.is_empty() {
+            text.push_str(&format!("\nAliases: {}", self.aliases.join(", ")));
+        }
+        if self.usage.iter().any(|usage| usage.contains("location")) {
+            text.push_str("\nA location is a line, file:line, function or *address.");
+        }
+        text
+    }
+}
+
+/// The one of `names` that `word` names: an exact match, or else the only name that starts with
+/// it. A name that all the others start with wins too (`env` over `environment`).
+fn find_name<'a>(word: &str, names: impl Iterator<Item = &'a str>) -> Result<&'a str, Vec<&'a str>> {
+    let matches: Vec<&str> = names.filter(|name| name.starts_with(word)).collect();
+    if let Some(exact) = matches.iter().find(|name| **name == word) {
+        return Ok(exact);
+    }
+    match matches.iter().find(|name| matches.iter().all(|other| other.starts_with(*name))) {
+        Some(name) => Ok(name),
+        None => Err(matches),
+    }
+}
+
+/// Splits `name(arg, ...)` into the function name and its arguments.
+fn parse_call(expression: &str) -> Option<(String, Vec<String>)> {
+    let open = expression.find('(')?;
+    let name = expression[..open].trim();
+    let inside = expression[open + 1..].trim_end();
+    if name.is_empty() || name.contains(char::is_whitespace) || !inside.ends_with(')') {
+        return None;
+    }
+    let inside = inside[..inside.len() - 1].trim();
+    let args: Vec<String> = if inside.is_empty() {
+        Vec::new()
+    } else {
+        inside.split(',').map(|arg| arg.trim().to_string()).collect()
+    };
+    if args.iter().any(|arg| arg.is_empty()) {
+        return None;
+    }
+    Some((name.to_string(), args))
+}
+
+/// Looks a command up by name, alias or prefix.
+pub fn find_command(word: &str) -> Result<&'static CommandSpec, DebuggerError> {
+    if let Some(spec) = COMMANDS.iter().find(|spec| spec.aliases.contains(&word)) {
+        return Ok(spec);
+    }
+    match find_name(word, COMMANDS.iter().map(|spec| spec.name)) {
+        Ok(name) => Ok(COMMANDS.iter().find(|spec| spec.name == name).unwrap()),
+        Err(names) if names.is_empty() => Err(DebuggerError::UnknownCommand(word.to_string())),
+        Err(names) => Err(DebuggerError::AmbiguousCommand(word.to_string(), names)),
+    }
+}
+
+/// Looks a subcommand of `spec` up by name or prefix.
+pub fn find_subcommand(spec: &'static CommandSpec, word: &str) -> Option<&'static str> {
+    find_name(word, spec.subcommands.iter().copied()).ok()
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
-        match tokens[0] {
-            "q" | "quit" => Some(DebuggerCommand::Quit),
-            "r" | "run" => {
-                let args = tokens[1..].to_vec();
-                Some(DebuggerCommand::Run(
-                    args.iter().map(|s| s.to_string()).collect(),
-                ))
-            }
-            "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
-            "bt"| "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" | "breakpoint" => Some(DebuggerCommand::Breakpoint(tokens[1].to_string())),
-
-            // Default case:
+    pub fn from_tokens(tokens: &[&str]) -> Result<DebuggerCommand, DebuggerError> {
+        let spec = find_command(tokens[0])?;
+        let usage = || DebuggerError::Usage(spec);
+        let arg = |i: usize| tokens.get(i).map(|s| s.to_string()).ok_or_else(usage);
+        let optional = |i: usize| tokens.get(i).map(|s| s.to_string());
+        let number = |i: usize| tokens.get(i).and_then(|s| s.parse().ok()).ok_or_else(usage);
+        let rest = |i: usize| tokens.iter().skip(i).map(|s| s.to_string()).collect();
+        let subcommand = match tokens.get(1) {
+            Some(word) if !spec.subcommands.is_empty() => find_subcommand(spec, word),
             _ => None,
+        };
+        let command = match (spec.name, subcommand) {
+            ("quit", _) => DebuggerCommand::Quit,
+            ("run", _) => {
+                let (args, redirections) = Redirections::parse(&tokens[1..]).ok_or_else(usage)?;
+                DebuggerCommand::Run(args, redirections)
+            }
+            ("continue", _) => DebuggerCommand::Continue,
+            ("backtrace", _) => DebuggerCommand::Backtrace,
+            ("break", _) => DebuggerCommand::Breakpoint(arg(1)?),
+            ("tbreak", _) => DebuggerCommand::TemporaryBreakpoint(arg(1)?),
+            ("until", _) => DebuggerCommand::Until(optional(1)),
+            ("advance", _) => DebuggerCommand::Advance(arg(1)?),
+            ("catch", Some("syscall")) => DebuggerCommand::CatchSyscall(rest(2)),
+            ("catch", Some("signal")) => DebuggerCommand::CatchSignal(rest(2)),
+            ("catch", Some("fork")) => DebuggerCommand::CatchFork,
+            ("catch", Some("exec")) => DebuggerCommand::CatchExec,
+            ("catch", Some("exit")) => DebuggerCommand::CatchExit,
+            ("info", Some("threads")) => DebuggerCommand::InfoThreads,
+            ("info", Some("checkpoints")) => DebuggerCommand::InfoCheckpoints,
+            ("info", Some("functions")) => DebuggerCommand::InfoFunctions(optional(2)),
+            ("info", Some("variables")) => DebuggerCommand::InfoVariables(optional(2)),
+            ("info", Some("line")) => DebuggerCommand::InfoLine(arg(2)?),
+            ("info", Some("address")) => DebuggerCommand::InfoAddress(arg(2)?),
+            ("info", Some("symbol")) => DebuggerCommand::InfoSymbol(arg(2)?),
+            ("info", Some("locals")) => DebuggerCommand::InfoLocals,
+            ("print", _) => {
+                let expression = tokens.get(1..).filter(|rest| !rest.is_empty()).ok_or_else(usage)?.join(" ");
+                match parse_call(&expression) {
+                    Some((function, args)) => DebuggerCommand::Call(function, args),
+                    None if expression.contains('(') => return Err(usage()),
+                    None => DebuggerCommand::Print(expression),
+                }
+            }
+            ("call", _) => {
+                let (function, args) = parse_call(&tokens[1..].join(" ")).ok_or_else(usage)?;
+                DebuggerCommand::Call(function, args)
+            }
+            ("finish", _) => DebuggerCommand::Finish,
+            ("return", _) if tokens.len() <= 2 => DebuggerCommand::Return(optional(1)),
+            ("thread", _) => DebuggerCommand::Thread(number(1)?),
+            ("record", None) if tokens.len() == 1 => DebuggerCommand::Record,
+            ("record", Some("stop")) => DebuggerCommand::RecordStop,
+            ("reverse-step", _) => DebuggerCommand::ReverseStep,
+            ("reverse-next", _) => DebuggerCommand::ReverseNext,
+            ("reverse-continue", _) => DebuggerCommand::ReverseContinue,
+            ("checkpoint", _) => DebuggerCommand::Checkpoint,
+            ("restart", _) => DebuggerCommand::Restart(number(1)?),
+            ("set", Some("debug-file-directory")) => DebuggerCommand::SetDebugFileDirectory(arg(2)?),
+            ("set", Some("inferior-tty")) => DebuggerCommand::SetInferiorTty(optional(2)),
+            ("set", Some("cwd")) => DebuggerCommand::SetCwd(optional(2)),
+            // `set env NAME=VALUE` or `set env NAME VALUE`; a value may contain spaces
+            ("set", Some("env")) | ("set", Some("environment")) => {
+                let name = arg(2)?;
+                let setting = tokens[2..].join(" ");
+                let (name, value) = match setting.find('=') {
+                    Some(eq) => (setting[..eq].trim().to_string(), setting[eq + 1..].trim().to_string()),
+                    None => (name, tokens[3..].join(" ")),
+                };
+                DebuggerCommand::SetEnv(name, Some(value))
+            }
+            ("save", Some("breakpoints")) => DebuggerCommand::SaveBreakpoints(arg(2)?),
+            ("layout", Some(layout)) => DebuggerCommand::Layout(layout.to_string()),
+            ("unset", Some("env")) | ("unset", Some("environment")) => DebuggerCommand::UnsetEnv(optional(2)),
+            ("help", _) => DebuggerCommand::Help(optional(1)),
+            _ => return Err(usage()),
+        };
+        Ok(command)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redirections() {
+        let (args, redirections) = Redirections::parse(&["a", "<", "in.txt", "b", ">out.txt"]).unwrap();
+        assert_eq!(args, vec!["a", "b"]);
+        assert_eq!(redirections.stdin, Some("in.txt".to_string()));
+        assert_eq!(redirections.stdout, Some(("out.txt".to_string(), false)));
+        let (args, redirections) = Redirections::parse(&[">>", "log"]).unwrap();
+        assert!(args.is_empty());
+        assert_eq!(redirections, Redirections { stdin: None, stdout: Some(("log".to_string(), true)) });
+        assert!(Redirections::parse(&["a", "<"]).is_none());
+    }
+
+    #[test]
+    fn test_find_command() {
+        assert_eq!(find_command("b").unwrap().name, "break");
+        assert_eq!(find_command("brea").unwrap().name, "break");
+        assert_eq!(find_command("adv").unwrap().name, "advance");
+        assert_eq!(find_command("r").unwrap().name, "run");
+        match find_command("rev") {
+            Err(DebuggerError::AmbiguousCommand(_, names)) => {
+                assert_eq!(names, vec!["reverse-continue", "reverse-next", "reverse-step"])
+            }
+            _ => panic!("rev is ambiguous"),
+        }
+        assert!(matches!(find_command("frobnicate"), Err(DebuggerError::UnknownCommand(_))));
+        let set = find_command("set").unwrap();
+        assert_eq!(find_subcommand(set, "env"), Some("env"));
+        assert_eq!(find_subcommand(set, "en"), Some("env"));
+        assert_eq!(find_subcommand(set, "debug"), Some("debug-file-directory"));
+        assert_eq!(find_subcommand(set, "c"), Some("cwd"));
+    }
+
+    #[test]
+    fn test_from_tokens() {
+        assert!(matches!(DebuggerCommand::from_tokens(&["cont"]), Ok(DebuggerCommand::Continue)));
+        assert!(matches!(
+            DebuggerCommand::from_tokens(&["i", "func", "^m"]),
+            Ok(DebuggerCommand::InfoFunctions(Some(_)))
+        ));
+        // missing and malformed arguments are usage errors rather than panics
+        for tokens in &[&["break"][..], &["thread", "x"], &["info"], &["info", "bogus"], &["record", "go"], &["run", "<"]] {
+            match DebuggerCommand::from_tokens(tokens) {
+                Err(DebuggerError::Usage(spec)) => assert_eq!(find_command(tokens[0]).unwrap().name, spec.name),
+                _ => panic!("{:?} should be a usage error", tokens),
+            }
         }
+        match DebuggerCommand::from_tokens(&["print", "add(1,", "-2)"]) {
+            Ok(DebuggerCommand::Call(function, args)) => {
+                assert_eq!(function, "add");
+                assert_eq!(args, vec!["1", "-2"]);
+            }
+            _ => panic!("print add(1, -2) is a call"),
+        }
+        assert!(matches!(DebuggerCommand::from_tokens(&["call", "dump()"]), Ok(DebuggerCommand::Call(_, ref args)) if args.is_empty()));
+        assert!(matches!(DebuggerCommand::from_tokens(&["call", "dump"]), Err(DebuggerError::Usage(_))));
+        assert!(matches!(DebuggerCommand::from_tokens(&["print", "f(1,,2)"]), Err(DebuggerError::Usage(_))));
+        assert!(matches!(DebuggerCommand::from_tokens(&["finish"]), Ok(DebuggerCommand::Finish)));
+        assert!(matches!(DebuggerCommand::from_tokens(&["fin"]), Ok(DebuggerCommand::Finish)));
+        assert!(matches!(DebuggerCommand::from_tokens(&["return"]), Ok(DebuggerCommand::Return(None))));
+        assert!(matches!(DebuggerCommand::from_tokens(&["return", "-1"]), Ok(DebuggerCommand::Return(Some(ref v))) if v == "-1"));
+        assert_eq!(
+            DebuggerError::Usage(find_command("save").unwrap()).to_string(),
+            "Usage: save breakpoints <file>"
+        );
     }
-}
\ No newline at end of file
+}
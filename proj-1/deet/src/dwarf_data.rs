@@ -1,6 +1,10 @@
+use crate::debug_link;
+use crate::dwarf_expr;
 use crate::gimli_wrapper;
 use addr2line::Context;
-use object::Object;
+use gimli::UnwindSection;
+use object::{Object, ObjectSection, SymbolKind};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::{fmt, fs};
 
@@ -12,7 +16,58 @@ pub enum Error {
 
 pub struct DwarfData {
     files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    // None when only the symbol table could be loaded, or when addr2line can not read the DWARF
+    // (e.g. DWARF 5 split units); lookups then fall back to our own tables
+    addr2line: Option<Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>>,
+    // functions from .symtab/.dynsym, sorted by address, for code without debugging information.
+    // They have no line number or variables.
+    symbols: Vec<Function>,
+    // .eh_frame of the executable, to unwind code built without frame pointers
+    cfi: Option<CallFrameInfo>,
+    // every type of every unit; variables refer to the types of their members, elements and
+    // pointees by id
+    types: HashMap<TypeId, Type>,
+    // split DWARF (.dwo) files that could not be read; nothing of their units is loaded
+    missing_dwo: Vec<String>,
+}
+
+struct CallFrameInfo {
+    eh_frame: Vec<u8>,
+    eh_frame_address: u64,
+    text_address: u64,
+    endian: gimli::RunTimeEndian,
+}
+
+/// How to find the caller of the code at some address, from the call frame information.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRule {
+    /// The canonical frame address is the value of this DWARF register plus `cfa_offset`
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    /// Where the caller's %rbp is saved, relative to the canonical frame address. None if %rbp
+    /// still holds it.
+    pub rbp_offset: Option<i64>,
+}
+
+fn endian_of(object: &object::File) -> gimli::RunTimeEndian {
+    if object.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    }
+}
+
+impl CallFrameInfo {
+    fn load(object: &object::File) -> Option<CallFrameInfo> {
+        let address_of = |name| object.section_by_name(name).map(|section| section.address());
+        let eh_frame = object.section_data_by_name(".eh_frame")?;
+        Some(CallFrameInfo {
+            eh_frame: eh_frame.to_vec(),
+            eh_frame_address: address_of(".eh_frame")?,
+            text_address: address_of(".text")?,
+            endian: endian_of(object),
+        })
+    }
 }
 
 impl fmt::Debug for DwarfData {
@@ -29,25 +84,108 @@ impl From<gimli_wrapper::Error> for Error {
 
 impl DwarfData {
     pub fn from_file(path: &str) -> Result<DwarfData, Error> {
+        DwarfData::from_file_with_debug_dir(path, debug_link::DEFAULT_DEBUG_DIR)
+    }
+
+    /// Like from_file, looking for separate debug files (see `debug_link`) under `debug_dir`.
+    pub fn from_file_with_debug_dir(path: &str, debug_dir: &str) -> Result<DwarfData, Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::File::parse(&*mmap)
             .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
-        let endian = if object.is_little_endian() {
-            gimli::RunTimeEndian::Little
-        } else {
-            gimli::RunTimeEndian::Big
-        };
+        let mut symbols = Vec::new();
+        load_symbols(&object, &mut symbols);
+
+        if object.section_data_by_name(".debug_info").is_none() {
+            if let Some(debug_path) = debug_link::find_debug_file(path, &object, debug_dir) {
+                let debug_file = fs::File::open(&debug_path).or(Err(Error::ErrorOpeningFile))?;
+                let debug_mmap = unsafe { memmap::Mmap::map(&debug_file).or(Err(Error::ErrorOpeningFile))? };
+                let debug_object = object::File::parse(&*debug_mmap)
+                    .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+                // the debug file usually keeps the full .symtab that was stripped from the
+                // executable
+                load_symbols(&debug_object, &mut symbols);
+                // .eh_frame is loaded code, so it stays in the executable
+                return DwarfData::load(&debug_object, symbols, CallFrameInfo::load(&object));
+            }
+        }
+        DwarfData::load(&object, symbols, CallFrameInfo::load(&object))
+    }
+
+    fn load(object: &object::File, mut symbols: Vec<Function>, cfi: Option<CallFrameInfo>) -> Result<DwarfData, Error> {
+        symbols.sort_by_key(|sym| sym.address);
+        let (files, types, missing_dwo) = gimli_wrapper::load_file(object, endian_of(object))?;
+        Ok(DwarfData {
+            files,
+            addr2line: Context::new(object).ok(),
+            symbols,
+            cfi,
+            types,
+            missing_dwo,
+        })
+    }
+
+    /// Loads only the ELF symbol table, for files whose DWARF data can not be read.
+    pub fn from_symbol_table(path: &str) -> Result<DwarfData, Error> {
+        let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
+        let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
+        let object = object::File::parse(&*mmap)
+            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        let mut symbols = Vec::new();
+        load_symbols(&object, &mut symbols);
+        symbols.sort_by_key(|sym| sym.address);
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            files: Vec::new(),
+            addr2line: None,
+            symbols,
+            cfi: CallFrameInfo::load(&object),
+            types: HashMap::new(),
+            missing_dwo: Vec::new(),
         })
     }
 
+    /// A DwarfData with only the given types, to test formatting values.
+    #[cfg(test)]
+    pub fn from_types(types: HashMap<TypeId, Type>) -> DwarfData {
+        DwarfData {
+            files: Vec::new(),
+            addr2line: None,
+            symbols: Vec::new(),
+            cfi: None,
+            types,
+            missing_dwo: Vec::new(),
+        }
+    }
+
+    /// The split DWARF (.dwo) files the program refers to that could not be read.
+    pub fn missing_dwo(&self) -> &[String] {
+        &self.missing_dwo
+    }
+
+    /// Whether the file has any DWARF compilation units. Without them, only the ELF symbol table
+    /// is available: no source lines, no variables.
+    pub fn has_debug_info(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    /// Returns the functions of the ELF symbol table.
+    pub fn symbols(&self) -> &[Function] {
+        &self.symbols
+    }
+
+    fn get_symbol_containing(&self, addr: usize) -> Option<&Function> {
+        self.symbols
+            .iter()
+            .find(|sym| addr >= sym.address && addr < sym.address + sym.text_length.max(1))
+    }
+
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
         self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+            f.name == file
+                || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+                // an absolute path (e.g. from an editor) for a file compiled with a relative one
+                || file.ends_with(&format!("/{}", f.name))
         })
     }
 
@@ -66,49 +204,206 @@ impl DwarfData {
         )
     }
 
+    /// Finds a function by name. Besides the full name, Rust and C++ functions can be named
+    /// without their path (`run` for `deet::debugger::Debugger::run`), and C++ ones without their
+    /// parameter list; exact matches win.
     #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
-        match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
+        let functions: Vec<&Function> = match file {
+            Some(filename) => self.get_target_file(filename)?.functions.iter().collect(),
+            None => self.files.iter().flat_map(|file| file.functions.iter()).collect(),
+        };
+        let symbols = self.symbols.iter().filter(|_| file.is_none());
+        let exact = functions.iter().copied().chain(symbols.clone()).find(|func| func.name == func_name);
+        let by_base_name = || {
+            functions
+                .iter()
+                .copied()
+                .chain(symbols.clone())
+                .find(|func| base_name_matches(&func.name, func_name))
+        };
+        Some(exact.or_else(by_base_name)?.address)
+    }
+
+    /// Returns the function whose code contains `addr`.
+    pub fn get_function_containing(&self, addr: usize) -> Option<&Function> {
+        self.files
+            .iter()
+            .flat_map(|f| f.functions.iter())
+            .find(|func| addr >= func.address && addr < func.address + func.text_length)
+            .or_else(|| self.get_symbol_containing(addr))
+    }
+
+    /// Returns the compilation units.
+    pub fn files(&self) -> &[File] {
+        &self.files
+    }
+
+    /// Returns the `[start, end)` address ranges of the code generated for a source line, in
+    /// address order. Contiguous rows of the line table are merged.
+    pub fn get_line_ranges(&self, file: Option<&str>, line_number: usize) -> Vec<(usize, usize)> {
+        let target_file = match file {
+            Some(filename) => self.get_target_file(filename),
+            None => self.files.get(0),
+        };
+        let mut rows: Vec<&Line> = match target_file {
+            Some(target_file) => target_file.lines.iter().collect(),
+            None => return Vec::new(),
+        };
+        rows.sort_by_key(|line| line.address);
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            if row.number != line_number {
+                continue;
+            }
+            // the last row of a sequence runs to the end of its function
+            let end = match rows.get(i + 1) {
+                Some(next) => next.address,
+                None => self
+                    .get_function_containing(row.address)
+                    .map_or(row.address, |func| func.address + func.text_length),
+            };
+            match ranges.last_mut() {
+                Some((_, prev_end)) if *prev_end == row.address => *prev_end = end,
+                _ => ranges.push((row.address, end)),
             }
         }
+        ranges
+    }
+
+    /// Returns how to unwind the frame of the code at `pc`, from `.eh_frame`.
+    pub fn get_frame_rule(&self, pc: usize) -> Option<FrameRule> {
+        let cfi = self.cfi.as_ref()?;
+        let eh_frame = gimli::EhFrame::new(&cfi.eh_frame, cfi.endian);
+        let bases = gimli::BaseAddresses::default()
+            .set_eh_frame(cfi.eh_frame_address)
+            .set_text(cfi.text_address);
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        let row = eh_frame
+            .unwind_info_for_address(&bases, &mut ctx, pc as u64, gimli::EhFrame::cie_from_offset)
+            .ok()?;
+        let (cfa_register, cfa_offset) = match row.cfa() {
+            gimli::CfaRule::RegisterAndOffset { register, offset } => (register.0, *offset),
+            gimli::CfaRule::Expression(_) => return None,
+        };
+        let rbp_offset = match row.register(gimli::Register(dwarf_expr::RBP)) {
+            gimli::RegisterRule::Offset(offset) => Some(offset),
+            _ => None,
+        };
+        Some(FrameRule {
+            cfa_register,
+            cfa_offset,
+            rbp_offset,
+        })
+    }
+
+    /// Returns the global variable stored at `addr`.
+    pub fn get_global_containing(&self, addr: usize) -> Option<&Variable> {
+        self.global_variables().find(|var| match var.location {
+            Location::Address(start) => addr >= start && addr < start + var.entity_type.size.max(1),
+            _ => false,
+        })
+    }
+
+    /// Returns the global variables of every compilation unit.
+    pub fn global_variables(&self) -> impl Iterator<Item = &Variable> {
+        self.files.iter().flat_map(|f| f.global_variables.iter())
     }
 
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
-            .addr2line
-            .find_location(curr_addr.try_into().unwrap())
-            .ok()??;
+        let from_addr2line = || -> Option<Line> {
+            let location = self
+                .addr2line
+                .as_ref()?
+                .find_location(curr_addr.try_into().unwrap())
+                .ok()??;
+            Some(Line {
+                file: location.file?.to_string(),
+                number: location.line?.try_into().unwrap(),
+                address: curr_addr,
+            })
+        };
+        from_addr2line().or_else(|| self.get_line_from_table(curr_addr))
+    }
+
+    /// Like get_line_from_addr, from the line tables loaded by gimli_wrapper: the last row at or
+    /// before `addr` in the function containing it.
+    fn get_line_from_table(&self, addr: usize) -> Option<Line> {
+        let func = self.get_function_containing(addr)?;
+        let line = self
+            .files
+            .iter()
+            .flat_map(|f| f.lines.iter())
+            .filter(|line| line.address <= addr && line.address >= func.address)
+            .max_by_key(|line| line.address)?;
         Some(Line {
-            file: location.file?.to_string(),
-            number: location.line?.try_into().unwrap(),
-            address: curr_addr,
+            address: addr,
+            ..line.clone()
         })
     }
 
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+        let from_dwarf = || -> Option<String> {
+            let frame = self
+                .addr2line
+                .as_ref()?
+                .find_frames(curr_addr.try_into().unwrap())
+                .ok()?
+                .next()
+                .ok()??;
+            Some(frame.function?.demangle().ok()?.to_string())
+        };
+        from_dwarf().or_else(|| Some(self.get_function_containing(curr_addr)?.name.clone()))
+    }
+
+    /// The calls inlined at `pc`, innermost first (see `Function::inlined_calls_at`).
+    pub fn get_inlined_calls(&self, pc: usize) -> Vec<&InlinedCall> {
+        self.get_function_containing(pc)
+            .map_or(Vec::new(), |func| func.inlined_calls_at(pc))
+    }
+
+    /// The name of the function running at `pc`, `inline_depth` inlined calls out from the
+    /// innermost one.
+    pub fn get_frame_function(&self, pc: usize, inline_depth: usize) -> Option<String> {
+        let calls = self.get_inlined_calls(pc);
+        match calls.get(inline_depth) {
+            Some(call) => Some(call.name.clone()),
+            None if calls.is_empty() => self.get_function_from_addr(pc),
+            None => Some(self.get_function_containing(pc)?.name.clone()),
+        }
+    }
+
+    /// The source line a frame is at: the line of `pc` for the innermost function, and the call
+    /// site of the inlined function below it otherwise.
+    pub fn get_frame_line(&self, pc: usize, inline_depth: usize) -> Option<Line> {
+        if inline_depth == 0 {
+            return self.get_line_from_addr(pc);
+        }
+        let calls = self.get_inlined_calls(pc);
+        let call = calls.get(inline_depth - 1)?;
+        Some(Line {
+            file: call.call_file.clone(),
+            number: call.call_line,
+            address: pc,
+        })
+    }
+
+    /// The variables visible in a frame (see `Function::variables_at`).
+    pub fn get_frame_variables(&self, pc: usize, inline_depth: usize) -> Vec<&Variable> {
+        self.get_function_containing(pc)
+            .map_or(Vec::new(), |func| func.variables_at(pc, inline_depth))
+    }
+
+    /// Returns a type that a `Type` refers to (see `TypeKind`).
+    pub fn get_type(&self, id: TypeId) -> Option<&Type> {
+        self.types.get(&id)
+    }
+
+    /// Returns every type, with its id.
+    pub fn types(&self) -> impl Iterator<Item = (TypeId, &Type)> {
+        self.types.iter().map(|(id, ty)| (*id, ty))
     }
 
     #[allow(dead_code)]
@@ -148,17 +443,164 @@ impl DwarfData {
     }
 }
 
+/// Adds the defined functions of the symbol tables that are not in `symbols` yet, preferring
+/// .symtab over .dynsym.
+fn load_symbols(object: &object::File, symbols: &mut Vec<Function>) {
+    // the same function is often in both tables, and in both the executable and its debug file
+    let mut seen: HashSet<(usize, String)> = symbols.iter().map(|sym| (sym.address, sym.name.clone())).collect();
+    for (_, symbol) in object.symbols().chain(object.dynamic_symbols()) {
+        if symbol.kind() != SymbolKind::Text || symbol.is_undefined() || symbol.address() == 0 {
+            continue;
+        }
+        let name = match symbol.name() {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        let name = addr2line::demangle_auto(name.into(), None).into_owned();
+        let address = symbol.address() as usize;
+        if !seen.insert((address, name.clone())) {
+            continue;
+        }
+        symbols.push(Function {
+            name,
+            address,
+            text_length: symbol.size() as usize,
+            ..Function::default()
+        });
+    }
+}
+
+/// Whether `name` is `full_name` without its path or its parameter list, e.g. `bar` for
+/// `foo::bar(int)`.
+fn base_name_matches(full_name: &str, name: &str) -> bool {
+    let without_params = match full_name.find('(') {
+        Some(paren) => &full_name[..paren],
+        None => full_name,
+    };
+    without_params == name || without_params.ends_with(&format!("::{}", name))
+}
+
+/// Identifies a type in `DwarfData`: the number of its unit, in load order, and its offset in
+/// the unit.
+pub type TypeId = usize;
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+    /// Whether the type comes from a Rust compilation unit, whose values are shown the way
+    /// Rust's `Debug` would
+    pub rust: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    /// A `DW_TAG_base_type`, with its `DW_ATE_*` encoding (0 if unknown)
+    Base(u8),
+    /// A pointer or reference; None for `void *`
+    Pointer(Option<TypeId>),
+    /// A struct, class or union. Rust enums are structs with a variant part.
+    Struct {
+        members: Vec<Member>,
+        variants: Option<VariantPart>,
+        /// `DW_TAG_template_type_parameter`s, e.g. the `T` of `Vec<T>`
+        template_params: Vec<TypeId>,
+    },
+    /// `count` elements, None for `int a[]`
+    Array { element: TypeId, count: Option<usize> },
+    /// A C-like enumeration: its enumerators and their values
+    Enum(Vec<(String, i64)>),
+    /// A typedef or a const/volatile qualified type, which has the same values as another type
+    Alias(Option<TypeId>),
+    /// Function types and anything else we can not show values of
+    Other,
+}
+
+impl Default for TypeKind {
+    fn default() -> Self {
+        TypeKind::Base(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// Offset in the containing type, in bytes
+    pub offset: usize,
+    pub type_id: TypeId,
+}
+
+/// The variants of a Rust enum (`DW_TAG_variant_part`). Each variant is a member holding a
+/// struct with the fields of that variant.
+#[derive(Debug, Clone, Default)]
+pub struct VariantPart {
+    /// The member holding the discriminant, None if there is a single variant
+    pub discriminant: Option<Member>,
+    pub variants: Vec<Variant>,
 }
 
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// The discriminant value that selects this variant; None for the default variant, e.g. the
+    /// `Some` of a niche-optimized `Option<&T>`
+    pub discr_value: Option<u64>,
+    pub member: Member,
+}
+
+// DW_ATE_* encodings of base types
+const ATE_BOOLEAN: u8 = 0x02;
+const ATE_FLOAT: u8 = 0x04;
+const ATE_SIGNED: u8 = 0x05;
+const ATE_SIGNED_CHAR: u8 = 0x06;
+const ATE_UNSIGNED: u8 = 0x07;
+const ATE_UNSIGNED_CHAR: u8 = 0x08;
+const ATE_UTF: u8 = 0x10;
+
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
-        Type {
-            name: name,
-            size: size,
+    /// Whether this is a floating point base type.
+    pub fn is_float(&self) -> bool {
+        match self.kind {
+            TypeKind::Base(ATE_FLOAT) => true,
+            TypeKind::Base(0) => self.name == "float" || self.name == "double",
+            _ => false,
+        }
+    }
+
+    /// Formats a value of this (base) type from its little-endian bytes, the way C would print it
+    /// (or Rust, for Rust types).
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        let mut buf = [0u8; 8];
+        let len = bytes.len().min(8);
+        buf[..len].copy_from_slice(&bytes[..len]);
+        let raw = u64::from_le_bytes(buf);
+        // sign-extend from the type's width
+        let shift = 64 - 8 * len.max(1) as u32;
+        let signed = ((raw << shift) as i64) >> shift;
+        let encoding = match self.kind {
+            TypeKind::Base(encoding) => encoding,
+            _ => 0,
+        };
+        match (encoding, self.name.as_str(), self.size) {
+            (ATE_FLOAT, _, 4) | (0, "float", 4) => format!("{}", f32::from_bits(raw as u32)),
+            (ATE_FLOAT, _, 8) | (0, "double", 8) => format!("{}", f64::from_bits(raw)),
+            (ATE_BOOLEAN, _, _) | (0, "_Bool", _) | (0, "bool", _) => format!("{}", raw != 0),
+            // Rust's char is a Unicode scalar value
+            (ATE_UTF, _, 4) => match std::char::from_u32(raw as u32) {
+                Some(c) => format!("{:?}", c),
+                None => format!("{}", raw),
+            },
+            (ATE_SIGNED_CHAR, _, 1) => format!("{} {:?}", signed, raw as u8 as char),
+            (ATE_UNSIGNED_CHAR, _, 1) | (ATE_UTF, _, 1) => format!("{} {:?}", raw, raw as u8 as char),
+            (0, name, 1) if name.contains("char") => {
+                let value = if name.contains("unsigned") { raw as i64 } else { signed };
+                format!("{} {:?}", value, raw as u8 as char)
+            }
+            (ATE_UNSIGNED, _, size) | (ATE_UTF, _, size) if size <= 8 => format!("{}", raw),
+            (0, name, _) if name.contains("unsigned") => format!("{}", raw),
+            (ATE_SIGNED, _, size) if size <= 8 => format!("{}", signed),
+            (0, _, 1) | (0, _, 2) | (0, _, 4) | (0, _, 8) => format!("{}", signed),
+            _ => format!("<{} bytes>", self.size),
         }
     }
 }
@@ -166,14 +608,49 @@ impl Type {
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
+    /// Offset from the function's frame base (`DW_OP_fbreg`)
     FramePointerOffset(isize),
+    /// Any other DWARF expression, evaluated when the variable is read (see `dwarf_expr`)
+    Expression(Vec<u8>),
+    /// A location list: the expression that applies while the pc is in `[begin, end)`. The
+    /// variable is optimized out everywhere else.
+    List(Vec<(usize, usize, Vec<u8>)>),
+    /// `DW_AT_const_value`: the variable has no storage, only this value
+    Value(Vec<u8>),
+    OptimizedOut,
+}
+
+impl Location {
+    /// Returns the expression that locates the variable when the pc is at `pc`, or None if it is
+    /// optimized out there. Only meaningful for `Expression` and `List`.
+    pub fn expression_at(&self, pc: usize) -> Option<&[u8]> {
+        match self {
+            Location::Expression(expr) => Some(expr),
+            Location::List(entries) => entries
+                .iter()
+                .find(|(begin, end, _)| pc >= *begin && pc < *end)
+                .map(|(_, _, expr)| &expr[..]),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expr) => write!(f, "Expression({:02x?})", expr),
+            Location::List(entries) => {
+                write!(f, "List(")?;
+                for (i, (begin, end, expr)) in entries.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{}[{:#x}, {:#x}): {:02x?}", sep, begin, end, expr)?;
+                }
+                write!(f, ")")
+            }
+            Location::Value(bytes) => write!(f, "Value({:02x?})", bytes),
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -200,6 +677,98 @@ pub struct Function {
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    /// The type of the value it returns; None for void functions
+    pub return_type: Option<Type>,
+    /// `DW_AT_frame_base`; None means the canonical frame address, as gcc uses
+    pub frame_base: Option<Location>,
+    /// The calls this function makes that record their arguments, for `DW_OP_entry_value`
+    pub call_sites: Vec<CallSite>,
+    /// Lexical blocks and inlined calls. `variables` only has the parameters and the variables of
+    /// the function's outermost block.
+    pub scopes: Vec<Scope>,
+}
+
+/// A lexical block, or the body of a function inlined into another.
+#[derive(Debug, Default, Clone)]
+pub struct Scope {
+    /// `[start, end)` address ranges of the code in the scope
+    pub ranges: Vec<(usize, usize)>,
+    pub variables: Vec<Variable>,
+    pub scopes: Vec<Scope>,
+    /// Set for an inlined call
+    pub inlined: Option<InlinedCall>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct InlinedCall {
+    /// The inlined function
+    pub name: String,
+    /// Where it is called from
+    pub call_file: String,
+    pub call_line: usize,
+}
+
+impl Scope {
+    pub fn contains(&self, pc: usize) -> bool {
+        self.ranges.iter().any(|&(start, end)| pc >= start && pc < end)
+    }
+}
+
+impl Function {
+    /// The nested scopes containing `pc`, outermost first.
+    fn scopes_at(&self, pc: usize) -> Vec<&Scope> {
+        let mut path = Vec::new();
+        let mut scopes = &self.scopes;
+        while let Some(scope) = scopes.iter().find(|scope| scope.contains(pc)) {
+            path.push(scope);
+            scopes = &scope.scopes;
+        }
+        path
+    }
+
+    /// The calls inlined into this function that are running at `pc`, innermost first.
+    pub fn inlined_calls_at(&self, pc: usize) -> Vec<&InlinedCall> {
+        self.scopes_at(pc)
+            .into_iter()
+            .rev()
+            .filter_map(|scope| scope.inlined.as_ref())
+            .collect()
+    }
+
+    /// The variables visible at `pc`, innermost scope first, leaving out the ones they shadow.
+    /// `inline_depth` selects the function: 0 is the innermost inlined call running at `pc`, and
+    /// `inlined_calls_at(pc).len()` this function itself.
+    pub fn variables_at(&self, pc: usize, inline_depth: usize) -> Vec<&Variable> {
+        let path = self.scopes_at(pc);
+        let inlined: Vec<usize> = (0..path.len()).filter(|&i| path[i].inlined.is_some()).collect();
+        if inline_depth > inlined.len() {
+            return Vec::new();
+        }
+        // the part of the path that belongs to the selected function: from its inlined call
+        // (or the top) down to the next inlined call
+        let outermost = inline_depth == inlined.len();
+        let start = if outermost { 0 } else { inlined[inlined.len() - 1 - inline_depth] };
+        let end = if inline_depth == 0 { path.len() } else { inlined[inlined.len() - inline_depth] };
+        let mut vars: Vec<&Variable> = Vec::new();
+        let scopes = path[start..end].iter().rev().map(|scope| &scope.variables);
+        let own = if outermost { Some(&self.variables) } else { None };
+        for var in scopes.chain(own).flat_map(|vars| vars.iter()) {
+            if !vars.iter().any(|seen| seen.name == var.name) {
+                vars.push(var);
+            }
+        }
+        vars
+    }
+}
+
+/// A call instruction with `DW_TAG_call_site` information.
+#[derive(Debug, Default, Clone)]
+pub struct CallSite {
+    /// The address the callee returns to
+    pub return_address: usize,
+    /// For each argument register, an expression for the value it was given, evaluated in the
+    /// caller's frame
+    pub parameters: Vec<(u16, Vec<u8>)>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -224,3 +793,121 @@ impl fmt::Display for Line {
 }
 
 
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_table() {
+        // built by the Makefile with the compiler's default DWARF version (5 for current gcc)
+        let debug_data =
+            DwarfData::from_file("samples/function_calls").expect("Could not load samples. Have you run make?");
+        let addr = debug_data.get_addr_for_line(None, 11).unwrap();
+        assert_eq!(debug_data.get_line_from_addr(addr).unwrap().number, 11);
+        assert_eq!(debug_data.get_function_from_addr(addr).unwrap(), "func2");
+        assert!(debug_data.global_variables().any(|var| var.name == "global"));
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let debug_data = DwarfData::from_file("samples/function_calls_stripped")
+            .or_else(|_| DwarfData::from_symbol_table("samples/function_calls_stripped"))
+            .unwrap();
+        assert!(!debug_data.has_debug_info());
+        let func2 = debug_data.get_addr_for_function(None, "func2").unwrap();
+        assert_eq!(debug_data.get_function_from_addr(func2 + 1).unwrap(), "func2");
+
+        // loading the same tables again adds nothing
+        let file = fs::File::open("samples/function_calls_stripped").unwrap();
+        let mmap = unsafe { memmap::Mmap::map(&file).unwrap() };
+        let object = object::File::parse(&*mmap).unwrap();
+        let mut symbols = debug_data.symbols().to_vec();
+        load_symbols(&object, &mut symbols);
+        assert_eq!(symbols.len(), debug_data.symbols().len());
+        assert_eq!(symbols.iter().filter(|sym| sym.name == "main").count(), 1);
+    }
+
+    #[test]
+    fn test_split_dwarf() {
+        let debug_data = DwarfData::from_file("samples/split").unwrap();
+        assert!(debug_data.missing_dwo().is_empty());
+        let addr = debug_data.get_addr_for_function(None, "twice").unwrap();
+        assert_eq!(debug_data.get_line_from_addr(addr).unwrap().number, 5);
+        assert!(debug_data.global_variables().any(|var| var.name == "calls"));
+
+        // without its .dwo, only the symbol table is left
+        let debug_data = DwarfData::from_file("samples/split_missing").unwrap();
+        assert_eq!(debug_data.missing_dwo().len(), 1);
+        assert!(debug_data.missing_dwo()[0].ends_with(".dwo"));
+        assert!(!debug_data.global_variables().any(|var| var.name == "calls"));
+        assert!(debug_data.get_addr_for_function(None, "twice").is_some());
+    }
+
+    #[test]
+    fn test_base_name_matches() {
+        assert!(base_name_matches("demo::show", "show"));
+        assert!(base_name_matches("geo::P::norm(int) const", "norm"));
+        assert!(base_name_matches("geo::P::norm(int) const", "P::norm"));
+        assert!(!base_name_matches("demo::shows", "show"));
+        assert!(!base_name_matches("demo::reshow", "show"));
+    }
+
+    #[test]
+    fn test_scopes() {
+        let var = |name: &str, value: u8| Variable {
+            name: name.to_string(),
+            entity_type: Type {
+                name: "int".to_string(),
+                size: 4,
+                ..Type::default()
+            },
+            location: Location::Value(vec![value, 0, 0, 0]),
+            line_number: 0,
+        };
+        // main(x) { { int x; helper(v) inlined at line 7 { int y; } } }
+        let inlined = Scope {
+            ranges: vec![(0x20, 0x30)],
+            variables: vec![var("v", 3), var("y", 4)],
+            inlined: Some(InlinedCall {
+                name: "helper".to_string(),
+                call_file: "main.c".to_string(),
+                call_line: 7,
+            }),
+            ..Scope::default()
+        };
+        let block = Scope {
+            ranges: vec![(0x10, 0x40)],
+            variables: vec![var("x", 2)],
+            scopes: vec![inlined],
+            inlined: None,
+        };
+        let func = Function {
+            name: "main".to_string(),
+            address: 0,
+            text_length: 0x50,
+            variables: vec![var("x", 1)],
+            scopes: vec![block],
+            ..Function::default()
+        };
+        let values = |vars: Vec<&Variable>| -> Vec<(String, u8)> {
+            vars.iter()
+                .map(|var| match &var.location {
+                    Location::Value(bytes) => (var.name.clone(), bytes[0]),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        let named = |pairs: &[(&str, u8)]| -> Vec<(String, u8)> {
+            pairs.iter().map(|&(name, value)| (name.to_string(), value)).collect()
+        };
+        assert_eq!(values(func.variables_at(0x5, 0)), named(&[("x", 1)]));
+        // the block's x shadows the parameter
+        assert_eq!(values(func.variables_at(0x15, 0)), named(&[("x", 2)]));
+        // in the inlined call, its own variables, then those of main where it was called
+        assert_eq!(func.inlined_calls_at(0x25).len(), 1);
+        assert_eq!(values(func.variables_at(0x25, 0)), named(&[("v", 3), ("y", 4)]));
+        assert_eq!(values(func.variables_at(0x25, 1)), named(&[("x", 2)]));
+        assert!(func.variables_at(0x25, 2).is_empty());
+    }
+}
@@ -0,0 +1,438 @@
+//! Evaluation of DWARF location expressions, the small stack machine programs that say where a
+//! variable lives: in memory, in a register, split into pieces, or nowhere at all.
+//!
+//! Optimizing compilers rarely keep a variable at a fixed offset from the frame pointer. A typical
+//! `-O2` parameter is described by a location list, with an expression for each range of code:
+//!
+//!     [0x401130, 0x401138): DW_OP_reg5 (rdi)
+//!     [0x401138, 0x40114c): DW_OP_entry_value(DW_OP_reg5 (rdi)), DW_OP_stack_value
+//!
+//! The evaluator only knows about the expression. Registers, memory and the frame come from a
+//! `Context`, which the inferior implements for one of its stack frames.
+
+use std::fmt;
+
+/// DWARF register numbers of x86-64.
+pub const REGISTER_NAMES: [&str; 17] = [
+    "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+pub const RBP: u16 = 6;
+pub const RSP: u16 = 7;
+pub const RIP: u16 = 16;
+/// %xmm0 to %xmm15 are 17 to 32
+pub const XMM0: u16 = 17;
+
+#[derive(Debug)]
+pub enum Error {
+    /// An operation this evaluator does not implement
+    Unsupported(u8),
+    /// The expression ends in the middle of an operation or pops an empty stack
+    Malformed,
+    /// A register that is not known in this frame, e.g. a caller-saved one in an outer frame
+    Unavailable,
+    /// The value can not be recovered, e.g. an entry value without a matching call site
+    OptimizedOut,
+    Inferior(nix::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unsupported(op) => write!(f, "unsupported DWARF operation {:#x}", op),
+            Error::Malformed => write!(f, "malformed DWARF expression"),
+            Error::Unavailable => write!(f, "value is not available in this frame"),
+            Error::OptimizedOut => write!(f, "optimized out"),
+            Error::Inferior(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Self {
+        Error::Inferior(err)
+    }
+}
+
+/// What an expression needs from the program being debugged.
+pub trait Context {
+    /// The value of one of the general purpose registers (0 to 16).
+    fn register(&self, reg: u16) -> Result<u64, Error>;
+    /// The contents of any register, including the vector registers.
+    fn register_bytes(&self, reg: u16) -> Result<Vec<u8>, Error>;
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, Error>;
+    /// The value of the function's `DW_AT_frame_base`, for `DW_OP_fbreg`.
+    fn frame_base(&self) -> Result<u64, Error>;
+    /// The canonical frame address, for `DW_OP_call_frame_cfa`.
+    fn call_frame_cfa(&self) -> Result<u64, Error>;
+    /// The value `expr` had when the function was entered, for `DW_OP_entry_value`.
+    fn entry_value(&self, expr: &[u8]) -> Result<u64, Error>;
+}
+
+/// Where (part of) a variable is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PieceLocation {
+    Memory(u64),
+    Register(u16),
+    /// `DW_OP_stack_value`: the value itself, which has no storage
+    Value(u64),
+    /// `DW_OP_implicit_value`
+    Bytes(Vec<u8>),
+    OptimizedOut,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub location: PieceLocation,
+    /// Size in bytes, or None when the piece is the whole variable
+    pub size: Option<usize>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(Error::Malformed);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads a little-endian value of `len` bytes, sign-extended when `signed`.
+    fn fixed(&mut self, len: usize, signed: bool) -> Result<u64, Error> {
+        let mut buf = [0u8; 8];
+        buf[..len].copy_from_slice(self.bytes(len)?);
+        let value = u64::from_le_bytes(buf);
+        let shift = 64 - 8 * len as u32;
+        Ok(if signed { (((value << shift) as i64) >> shift) as u64 } else { value })
+    }
+
+    fn uleb128(&mut self) -> Result<u64, Error> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Result<i64, Error> {
+        let mut result = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1 << shift;
+                }
+                return Ok(result);
+            }
+        }
+    }
+}
+
+/// Returns the register of an expression that is just `DW_OP_reg*`, the usual operand of
+/// `DW_OP_entry_value`.
+pub fn register_operand(expr: &[u8]) -> Option<u16> {
+    let mut reader = Reader { data: expr };
+    let reg = match reader.u8().ok()? {
+        op @ 0x50..=0x6f => (op - 0x50) as u16,
+        0x90 => reader.uleb128().ok()? as u16,
+        _ => return None,
+    };
+    if reader.is_empty() {
+        Some(reg)
+    } else {
+        None
+    }
+}
+
+// what the operations since the last piece said about the location
+enum State {
+    // the address on top of the stack, if any
+    Stack,
+    Register(u16),
+    StackValue,
+    Implicit(Vec<u8>),
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, Error> {
+    stack.pop().ok_or(Error::Malformed)
+}
+
+/// Runs a location expression, returning the pieces of the variable in order. An empty expression
+/// yields a single optimized out piece.
+pub fn evaluate(expr: &[u8], context: &dyn Context) -> Result<Vec<Piece>, Error> {
+    let mut reader = Reader { data: expr };
+    let mut stack: Vec<u64> = Vec::new();
+    let mut state = State::Stack;
+    let mut pieces = Vec::new();
+    while !reader.is_empty() {
+        let op = reader.u8()?;
+        match op {
+            // DW_OP_addr
+            0x03 => stack.push(reader.fixed(8, false)?),
+            // DW_OP_deref
+            0x06 => {
+                let addr = pop(&mut stack)?;
+                stack.push(u64::from_le_bytes(to_array(&context.read_memory(addr, 8)?)));
+            }
+            // DW_OP_const1u, const1s, ..., const8s
+            0x08..=0x0f => {
+                let len = 1 << ((op - 0x08) / 2);
+                stack.push(reader.fixed(len, op % 2 == 1)?);
+            }
+            // DW_OP_constu, DW_OP_consts
+            0x10 => stack.push(reader.uleb128()?),
+            0x11 => stack.push(reader.sleb128()? as u64),
+            // DW_OP_dup, drop, over
+            0x12 => stack.push(*stack.last().ok_or(Error::Malformed)?),
+            0x13 => {
+                pop(&mut stack)?;
+            }
+            0x14 => {
+                let len = stack.len();
+                stack.push(*stack.get(len.wrapping_sub(2)).ok_or(Error::Malformed)?);
+            }
+            // DW_OP_swap
+            0x16 => {
+                let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                stack.push(a);
+                stack.push(b);
+            }
+            // DW_OP_neg, not
+            0x1f => {
+                let a = pop(&mut stack)?;
+                stack.push((a as i64).wrapping_neg() as u64);
+            }
+            0x20 => {
+                let a = pop(&mut stack)?;
+                stack.push(!a);
+            }
+            // DW_OP_and, minus, mul, or, plus, shl, shr, shra, xor
+            0x1a | 0x1c | 0x1e | 0x21 | 0x22 | 0x24 | 0x25 | 0x26 | 0x27 => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(match op {
+                    0x1a => a & b,
+                    0x1c => a.wrapping_sub(b),
+                    0x1e => a.wrapping_mul(b),
+                    0x21 => a | b,
+                    0x22 => a.wrapping_add(b),
+                    0x24 => a.checked_shl(b as u32).unwrap_or(0),
+                    0x25 => a.checked_shr(b as u32).unwrap_or(0),
+                    0x26 => ((a as i64) >> b.min(63)) as u64,
+                    _ => a ^ b,
+                });
+            }
+            // DW_OP_plus_uconst
+            0x23 => {
+                let a = pop(&mut stack)?;
+                stack.push(a.wrapping_add(reader.uleb128()?));
+            }
+            // DW_OP_lit0 ... DW_OP_lit31
+            0x30..=0x4f => stack.push((op - 0x30) as u64),
+            // DW_OP_reg0 ... DW_OP_reg31, DW_OP_regx
+            0x50..=0x6f => state = State::Register((op - 0x50) as u16),
+            0x90 => state = State::Register(reader.uleb128()? as u16),
+            // DW_OP_breg0 ... DW_OP_breg31, DW_OP_bregx
+            0x70..=0x8f => {
+                let value = context.register((op - 0x70) as u16)?;
+                stack.push(value.wrapping_add(reader.sleb128()? as u64));
+            }
+            0x92 => {
+                let value = context.register(reader.uleb128()? as u16)?;
+                stack.push(value.wrapping_add(reader.sleb128()? as u64));
+            }
+            // DW_OP_fbreg
+            0x91 => {
+                let offset = reader.sleb128()?;
+                stack.push(context.frame_base()?.wrapping_add(offset as u64));
+            }
+            // DW_OP_piece
+            0x93 => {
+                let size = reader.uleb128()? as usize;
+                let location = finish_piece(&mut stack, state)?;
+                pieces.push(Piece { location, size: Some(size) });
+                state = State::Stack;
+            }
+            // DW_OP_nop
+            0x96 => {}
+            // DW_OP_call_frame_cfa
+            0x9c => stack.push(context.call_frame_cfa()?),
+            // DW_OP_implicit_value
+            0x9e => {
+                let len = reader.uleb128()? as usize;
+                state = State::Implicit(reader.bytes(len)?.to_vec());
+            }
+            // DW_OP_stack_value
+            0x9f => state = State::StackValue,
+            // DW_OP_entry_value, DW_OP_GNU_entry_value
+            0xa3 | 0xf3 => {
+                let len = reader.uleb128()? as usize;
+                let sub_expr = reader.bytes(len)?;
+                stack.push(context.entry_value(sub_expr)?);
+            }
+            _ => return Err(Error::Unsupported(op)),
+        }
+    }
+    // an empty expression, or a trailing piece without DW_OP_piece
+    if pieces.is_empty() || !stack.is_empty() || !matches!(state, State::Stack) {
+        let location = finish_piece(&mut stack, state)?;
+        pieces.push(Piece { location, size: None });
+    }
+    Ok(pieces)
+}
+
+fn finish_piece(stack: &mut Vec<u64>, state: State) -> Result<PieceLocation, Error> {
+    Ok(match state {
+        State::Register(reg) => PieceLocation::Register(reg),
+        State::StackValue => PieceLocation::Value(pop(stack)?),
+        State::Implicit(bytes) => PieceLocation::Bytes(bytes),
+        State::Stack => match stack.pop() {
+            Some(addr) => PieceLocation::Memory(addr),
+            None => PieceLocation::OptimizedOut,
+        },
+    })
+}
+
+fn to_array(bytes: &[u8]) -> [u8; 8] {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    buf
+}
+
+/// Runs an expression that computes a value rather than a location, such as `DW_AT_frame_base`
+/// or the value of a parameter at a call site.
+pub fn evaluate_value(expr: &[u8], context: &dyn Context) -> Result<u64, Error> {
+    match evaluate(expr, context)?.first().map(|piece| &piece.location) {
+        Some(PieceLocation::Memory(value)) | Some(PieceLocation::Value(value)) => Ok(*value),
+        Some(PieceLocation::Register(reg)) => context.register(*reg),
+        Some(PieceLocation::Bytes(bytes)) => Ok(u64::from_le_bytes(to_array(bytes))),
+        _ => Err(Error::OptimizedOut),
+    }
+}
+
+/// Collects the `size` bytes of a variable from its pieces. Returns None if any part of it is
+/// optimized out.
+pub fn read_pieces(pieces: &[Piece], size: usize, context: &dyn Context) -> Result<Option<Vec<u8>>, Error> {
+    let mut bytes = Vec::new();
+    for piece in pieces {
+        let len = piece.size.unwrap_or(size);
+        let mut data = match &piece.location {
+            PieceLocation::Memory(addr) => context.read_memory(*addr, len)?,
+            PieceLocation::Register(reg) => context.register_bytes(*reg)?,
+            PieceLocation::Value(value) => value.to_le_bytes().to_vec(),
+            PieceLocation::Bytes(data) => data.clone(),
+            PieceLocation::OptimizedOut => return Ok(None),
+        };
+        data.resize(len, 0);
+        bytes.extend(data);
+    }
+    bytes.resize(size, 0);
+    Ok(Some(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // rbp = 0x1000, rdi = 7, memory holds 0x2a at 0xff0, the CFA is 0x1010
+    struct TestContext;
+
+    impl Context for TestContext {
+        fn register(&self, reg: u16) -> Result<u64, Error> {
+            match reg {
+                RBP => Ok(0x1000),
+                5 => Ok(7),
+                _ => Err(Error::Unavailable),
+            }
+        }
+        fn register_bytes(&self, reg: u16) -> Result<Vec<u8>, Error> {
+            Ok(self.register(reg)?.to_le_bytes().to_vec())
+        }
+        fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, Error> {
+            let mut bytes = vec![0; len];
+            if addr == 0xff0 {
+                bytes[0] = 0x2a;
+            }
+            Ok(bytes)
+        }
+        fn frame_base(&self) -> Result<u64, Error> {
+            self.call_frame_cfa()
+        }
+        fn call_frame_cfa(&self) -> Result<u64, Error> {
+            Ok(0x1010)
+        }
+        fn entry_value(&self, expr: &[u8]) -> Result<u64, Error> {
+            match register_operand(expr) {
+                Some(5) => Ok(3),
+                _ => Err(Error::OptimizedOut),
+            }
+        }
+    }
+
+    fn read(expr: &[u8], size: usize) -> Option<Vec<u8>> {
+        let pieces = evaluate(expr, &TestContext).unwrap();
+        read_pieces(&pieces, size, &TestContext).unwrap()
+    }
+
+    #[test]
+    fn test_locations() {
+        // DW_OP_fbreg -32 (sleb128 0x60)
+        assert_eq!(
+            evaluate(&[0x91, 0x60], &TestContext).unwrap(),
+            vec![Piece { location: PieceLocation::Memory(0xff0), size: None }]
+        );
+        // DW_OP_breg6 -16 and DW_OP_call_frame_cfa, DW_OP_consts -32, DW_OP_plus
+        assert_eq!(read(&[0x76, 0x70], 4), Some(vec![0x2a, 0, 0, 0]));
+        assert_eq!(read(&[0x9c, 0x11, 0x60, 0x22], 4), Some(vec![0x2a, 0, 0, 0]));
+        // DW_OP_reg5
+        assert_eq!(read(&[0x55], 4), Some(vec![7, 0, 0, 0]));
+        // DW_OP_lit5, DW_OP_stack_value
+        assert_eq!(read(&[0x35, 0x9f], 2), Some(vec![5, 0]));
+        // DW_OP_entry_value(DW_OP_reg5), DW_OP_lit1, DW_OP_plus, DW_OP_stack_value
+        assert_eq!(read(&[0xa3, 0x01, 0x55, 0x31, 0x22, 0x9f], 4), Some(vec![4, 0, 0, 0]));
+        // an empty expression, or a piece that is missing
+        assert_eq!(read(&[], 4), None);
+        assert_eq!(read(&[0x55, 0x93, 0x04, 0x93, 0x04], 8), None);
+    }
+
+    #[test]
+    fn test_pieces() {
+        // DW_OP_reg5, DW_OP_piece 4, DW_OP_lit9, DW_OP_stack_value, DW_OP_piece 4
+        let expr = [0x55, 0x93, 0x04, 0x39, 0x9f, 0x93, 0x04];
+        assert_eq!(read(&expr, 8), Some(vec![7, 0, 0, 0, 9, 0, 0, 0]));
+        // DW_OP_reg3 is not known here
+        assert!(matches!(
+            read_pieces(&evaluate(&[0x53], &TestContext).unwrap(), 4, &TestContext),
+            Err(Error::Unavailable)
+        ));
+        assert!(matches!(evaluate(&[0xe0], &TestContext), Err(Error::Unsupported(0xe0))));
+        assert_eq!(register_operand(&[0x55]), Some(5));
+        assert_eq!(register_operand(&[0x55, 0x9f]), None);
+    }
+}
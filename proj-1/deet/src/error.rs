@@ -0,0 +1,36 @@
+//! Errors that end a command early. They are reported to the user, and the debugger carries on
+//! with the next command.
+
+use crate::debugger_command::CommandSpec;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum DebuggerError {
+    /// The first word of a command line is not a command, nor the start of one
+    UnknownCommand(String),
+    /// The first word of a command line is the start of several commands
+    AmbiguousCommand(String, Vec<&'static str>),
+    /// A command was given the wrong arguments
+    Usage(&'static CommandSpec),
+    /// ptrace, or another system call on the inferior, failed
+    Inferior(nix::Error),
+}
+
+impl fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebuggerError::UnknownCommand(word) => write!(f, "Undefined command: \"{}\". Try \"help\".", word),
+            DebuggerError::AmbiguousCommand(word, names) => {
+                write!(f, "Ambiguous command \"{}\": {}.", word, names.join(", "))
+            }
+            DebuggerError::Usage(spec) => write!(f, "{}", spec.usage_text()),
+            DebuggerError::Inferior(err) => write!(f, "Cannot access the program: {}", err),
+        }
+    }
+}
+
+impl From<nix::Error> for DebuggerError {
+    fn from(err: nix::Error) -> DebuggerError {
+        DebuggerError::Inferior(err)
+    }
+}
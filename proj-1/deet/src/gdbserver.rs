@@ -0,0 +1,477 @@
+//! A GDB Remote Serial Protocol server, so that gdb and IDE front-ends can drive an inferior
+//! through deet (`deet --gdbserver [<host>:]<port> <target>`).
+//!
+//! Only the core packets are implemented: `?`, `g`, `G`, `m`, `M`, `c`, `s`, `Z0`, `z0`, plus the
+//! few queries gdb sends while connecting. Anything else gets the empty reply, which tells the
+//! client the packet is not supported. Registers are exchanged in gdb's amd64 `g` packet order;
+//! the floating point and vector registers are left out, which gdb reports as unavailable.
+
+use crate::inferior::{Inferior, Status};
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// A packet received from the client.
+#[derive(Debug, PartialEq)]
+enum Packet {
+    /// `$<data>#<checksum>`
+    Data(String),
+    /// The out-of-band interrupt byte (0x03) sent when the user presses ctrl+c in gdb.
+    Interrupt,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Reads the next packet, acknowledging it. Acks from the client are skipped. Returns None once
+/// the client has disconnected.
+fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Option<Packet>> {
+    let mut byte = [0u8];
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            b'$' => break,
+            0x03 => return Ok(Some(Packet::Interrupt)),
+            // acks ('+', '-') and line noise
+            _ => {}
+        }
+    }
+    let mut data = Vec::new();
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'#' {
+            break;
+        }
+        data.push(byte[0]);
+    }
+    let mut sum = [0u8; 2];
+    stream.read_exact(&mut sum)?;
+    let expected = std::str::from_utf8(&sum)
+        .ok()
+        .and_then(|s| u8::from_str_radix(s, 16).ok());
+    if expected != Some(checksum(&data)) {
+        // ask for a retransmission
+        stream.write_all(b"-")?;
+        return read_packet(stream);
+    }
+    stream.write_all(b"+")?;
+    Ok(Some(Packet::Data(String::from_utf8_lossy(&data).to_string())))
+}
+
+fn write_packet<W: Write>(stream: &mut W, data: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+    stream.flush()
+}
+
+/// The 64-bit registers of the `g` packet, in order; the remaining registers are 32 bits wide.
+fn register_fields(regs: &mut libc::user_regs_struct) -> (Vec<&mut u64>, Vec<&mut u64>) {
+    (
+        vec![
+            &mut regs.rax, &mut regs.rbx, &mut regs.rcx, &mut regs.rdx, &mut regs.rsi,
+            &mut regs.rdi, &mut regs.rbp, &mut regs.rsp, &mut regs.r8, &mut regs.r9,
+            &mut regs.r10, &mut regs.r11, &mut regs.r12, &mut regs.r13, &mut regs.r14,
+            &mut regs.r15, &mut regs.rip,
+        ],
+        vec![
+            &mut regs.eflags, &mut regs.cs, &mut regs.ss, &mut regs.ds, &mut regs.es,
+            &mut regs.fs, &mut regs.gs,
+        ],
+    )
+}
+
+fn encode_registers(mut regs: libc::user_regs_struct) -> String {
+    let (wide, narrow) = register_fields(&mut regs);
+    let mut bytes = Vec::new();
+    for reg in wide {
+        bytes.extend_from_slice(&reg.to_le_bytes());
+    }
+    for reg in narrow {
+        bytes.extend_from_slice(&(*reg as u32).to_le_bytes());
+    }
+    encode_hex(&bytes)
+}
+
+/// Overwrites `regs` with the contents of a `G` packet. A short packet only updates the registers
+/// it covers.
+fn decode_registers(hex: &str, regs: &mut libc::user_regs_struct) -> Option<()> {
+    let bytes = decode_hex(hex)?;
+    let mut chunks = bytes.as_slice();
+    let (wide, narrow) = register_fields(regs);
+    for reg in wide {
+        if chunks.len() < 8 {
+            return Some(());
+        }
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&chunks[..8]);
+        *reg = u64::from_le_bytes(word);
+        chunks = &chunks[8..];
+    }
+    for reg in narrow {
+        if chunks.len() < 4 {
+            return Some(());
+        }
+        let mut word = [0u8; 4];
+        word.copy_from_slice(&chunks[..4]);
+        *reg = u32::from_le_bytes(word) as u64;
+        chunks = &chunks[4..];
+    }
+    Some(())
+}
+
+/// Parses the "addr,length" argument of `m`, `M`, `Z0` and `z0`.
+fn parse_addr_len(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let addr = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((addr, len))
+}
+
+struct GdbServer {
+    inferior: Option<Inferior>,
+    breakpoints: HashMap<usize, u8>,
+    // whether the client lets us report software breakpoint hits with the PC already rewound
+    swbreak: bool,
+    last_status: String,
+}
+
+impl GdbServer {
+    fn stop_reply(&mut self, status: Status) -> String {
+        self.last_status = match status {
+            Status::Exited(exit_code) => {
+                self.inferior = None;
+                format!("W{:02x}", exit_code as u8)
+            }
+            Status::Signaled(signal) => {
+                self.inferior = None;
+                format!("X{:02x}", signal as i32)
+            }
+            // syscall tracing and event stops are never turned on here
+            Status::SyscallEntry(..)
+            | Status::SyscallExit(..)
+            | Status::Forked(..)
+            | Status::Exec(..)
+            | Status::Exiting(..) => "S05".to_string(),
+            Status::Stopped(signal, rip) => {
+                let hit_breakpoint = signal == nix::sys::signal::Signal::SIGTRAP
+                    && self.breakpoints.contains_key(&(rip - 1));
+                if hit_breakpoint && self.swbreak {
+                    let inferior = self.inferior.as_mut().unwrap();
+                    inferior.rewind_breakpoint(&self.breakpoints).ok();
+                    format!("T{:02x}swbreak:;", signal as i32)
+                } else {
+                    format!("S{:02x}", signal as i32)
+                }
+            }
+        };
+        self.last_status.clone()
+    }
+
+    /// Reads memory with our breakpoint instructions replaced by the original bytes.
+    fn read_memory(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let mut bytes = self.inferior.as_ref()?.read_memory(addr, len).ok()?;
+        for (bp, orig) in &self.breakpoints {
+            if *bp >= addr && *bp < addr + len {
+                bytes[*bp - addr] = *orig;
+            }
+        }
+        Some(bytes)
+    }
+
+    fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Option<()> {
+        let inferior = self.inferior.as_mut()?;
+        for (i, byte) in bytes.iter().enumerate() {
+            match self.breakpoints.get_mut(&(addr + i)) {
+                // keep the breakpoint in place, it will put back the new byte when removed
+                Some(orig) => *orig = *byte,
+                None => {
+                    inferior.write_byte(addr + i, *byte).ok()?;
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn resume(&mut self, step: bool) -> Option<String> {
+        let inferior = self.inferior.as_mut()?;
+        let status = if step {
+            inferior.step_instruction(&self.breakpoints).ok()?
+        } else {
+            let rip = inferior.registers().ok()?.rip as usize;
+            let mut status = Status::Stopped(nix::sys::signal::Signal::SIGTRAP, rip);
+            if self.breakpoints.contains_key(&rip) {
+                status = inferior.step_instruction(&self.breakpoints).ok()?;
+            }
+            match status {
+                Status::Stopped(..) => inferior.resume(None).ok()?,
+                other => other,
+            }
+        };
+        Some(self.stop_reply(status))
+    }
+
+    /// Handles one packet and returns the reply, or None if the packet asks us to stop serving.
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "?" => self.last_status.clone(),
+            "g" => match self.inferior.as_ref().and_then(|i| i.registers().ok()) {
+                Some(regs) => encode_registers(regs),
+                None => "E01".to_string(),
+            },
+            "G" => {
+                let result = self.inferior.as_ref().and_then(|inferior| {
+                    let mut regs = inferior.registers().ok()?;
+                    decode_registers(args, &mut regs)?;
+                    inferior.set_registers(regs).ok()
+                });
+                if result.is_some() { "OK" } else { "E01" }.to_string()
+            }
+            "m" => parse_addr_len(args)
+                .and_then(|(addr, len)| self.read_memory(addr, len))
+                .map_or("E01".to_string(), |bytes| encode_hex(&bytes)),
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let result = parse_addr_len(parts.next().unwrap_or("")).and_then(|(addr, len)| {
+                    let bytes = decode_hex(parts.next()?)?;
+                    if bytes.len() != len {
+                        return None;
+                    }
+                    self.write_memory(addr, &bytes)
+                });
+                if result.is_some() { "OK" } else { "E01" }.to_string()
+            }
+            // resuming at a different address is not supported, so the argument is ignored
+            "c" => self.resume(false).unwrap_or("E01".to_string()),
+            "s" => self.resume(true).unwrap_or("E01".to_string()),
+            "Z" | "z" if args.starts_with("0,") => {
+                let insert = command == "Z";
+                let result = parse_addr_len(&args[2..]).and_then(|(addr, _kind)| {
+                    let inferior = self.inferior.as_mut()?;
+                    if insert && !self.breakpoints.contains_key(&addr) {
+                        let orig = inferior.write_byte(addr, 0xcc).ok()?;
+                        self.breakpoints.insert(addr, orig);
+                    } else if !insert {
+                        if let Some(orig) = self.breakpoints.remove(&addr) {
+                            inferior.write_byte(addr, orig).ok()?;
+                        }
+                    }
+                    Some(())
+                });
+                if result.is_some() { "OK" } else { "E01" }.to_string()
+            }
+            "q" if args.starts_with("Supported") => {
+                self.swbreak = args.contains("swbreak+");
+                "PacketSize=4000;swbreak+".to_string()
+            }
+            "q" if args == "Attached" => "0".to_string(),
+            "q" if args == "C" => "QC0".to_string(),
+            "H" => "OK".to_string(),
+            "k" => return None,
+            "D" => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.terminate();
+                }
+                self.inferior = None;
+                "OK".to_string()
+            }
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn run<S: Read + Write>(&mut self, stream: &mut S) -> io::Result<()> {
+        while let Some(packet) = read_packet(stream)? {
+            let reply = match packet {
+                // the inferior only runs while we are blocked waiting for it, so there is never
+                // anything to interrupt
+                Packet::Interrupt => continue,
+                Packet::Data(data) => self.handle(&data),
+            };
+            match reply {
+                Some(reply) => write_packet(stream, &reply)?,
+                None => break,
+            }
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.terminate();
+        }
+        Ok(())
+    }
+}
+
+/// Serves a single client connection on `listener`, debugging a freshly started `target`.
+pub fn serve_on(listener: TcpListener, target: &str, args: &Vec<String>) -> io::Result<()> {
+    let (stream, addr) = listener.accept()?;
+    println!("Remote debugging from host {}", addr.ip());
+    let mut breakpoints = HashMap::new();
+    let inferior = Inferior::new(target, args, &mut breakpoints)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Error starting subprocess"))?;
+    println!("Process {} created; pid = {}", target, inferior.pid());
+    let mut server = GdbServer {
+        inferior: Some(inferior),
+        breakpoints,
+        swbreak: false,
+        last_status: "S05".to_string(),
+    };
+    let mut stream = BufStream(BufReader::new(stream));
+    server.run(&mut stream)
+}
+
+/// Turns a `[host:]port` argument into the address to listen on. Without a host only local
+/// connections are accepted, since anyone who connects can run code as us.
+pub fn listen_address(spec: &str) -> Option<String> {
+    match spec.rfind(':') {
+        Some(colon) if colon > 0 => {
+            spec[colon + 1..].parse::<u16>().ok()?;
+            Some(spec.to_string())
+        }
+        Some(_) => None,
+        None => spec.parse::<u16>().ok().map(|port| format!("127.0.0.1:{}", port)),
+    }
+}
+
+/// Listens on `address` (as returned by `listen_address`) and serves one gdb connection.
+pub fn serve(target: &str, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("Listening on {}", listener.local_addr()?);
+    serve_on(listener, target, &Vec::new())
+}
+
+/// A TCP stream with buffered reads, since packets are parsed one byte at a time.
+struct BufStream(BufReader<TcpStream>);
+
+impl Read for BufStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for BufStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dwarf_data::DwarfData;
+    use std::io::Cursor;
+    use std::thread;
+
+    /// A minimal RSP client for driving the server in tests.
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            write_packet(&mut self.stream, data).unwrap();
+            let mut ack = [0u8];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            match read_packet(&mut self.stream).unwrap() {
+                Some(Packet::Data(reply)) => reply,
+                other => panic!("unexpected reply {:?}", other),
+            }
+        }
+    }
+
+    fn start_server(program: &str) -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let target = program.to_string();
+        // ptrace requests must come from the thread that started the inferior, so the whole
+        // server lives on its own thread
+        thread::spawn(move || serve_on(listener, &target, &Vec::new()).unwrap());
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        Client { stream }
+    }
+
+    #[test]
+    fn test_packet_framing() {
+        let mut out = Vec::new();
+        write_packet(&mut out, "OK").unwrap();
+        assert_eq!(out, b"$OK#9a");
+
+        // a leading ack is skipped, and the packet gets acked
+        let mut stream = Cursor::new(b"+$m4000,2#8f".to_vec());
+        let packet = read_packet(&mut stream).unwrap();
+        assert_eq!(packet, Some(Packet::Data("m4000,2".to_string())));
+        assert!(stream.get_ref().ends_with(b"+"));
+    }
+
+    #[test]
+    fn test_listen_address() {
+        assert_eq!(listen_address("1234").as_deref(), Some("127.0.0.1:1234"));
+        assert_eq!(listen_address("0.0.0.0:1234").as_deref(), Some("0.0.0.0:1234"));
+        assert_eq!(listen_address("[::1]:1234").as_deref(), Some("[::1]:1234"));
+        assert_eq!(listen_address(":1234"), None);
+        assert_eq!(listen_address("localhost:port"), None);
+        assert_eq!(listen_address("99999"), None);
+    }
+
+    #[test]
+    fn test_register_round_trip() {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rax = 0x1122334455667788;
+        regs.rip = 0x401000;
+        regs.eflags = 0x246;
+        let hex = encode_registers(regs);
+        assert_eq!(hex.len(), (17 * 8 + 7 * 4) * 2);
+        assert!(hex.starts_with("8877665544332211"));
+
+        let mut decoded: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        decode_registers(&hex, &mut decoded).unwrap();
+        assert_eq!(decoded.rax, regs.rax);
+        assert_eq!(decoded.rip, regs.rip);
+        assert_eq!(decoded.eflags, regs.eflags);
+    }
+
+    #[test]
+    fn test_breakpoint_and_continue() {
+        let program = "samples/function_calls";
+        let debug_data =
+            DwarfData::from_file(program).expect("Could not load samples. Have you run make?");
+        let func2 = debug_data.get_addr_for_function(None, "func2").unwrap();
+
+        let mut client = start_server(program);
+        assert_eq!(client.request("qSupported:swbreak+"), "PacketSize=4000;swbreak+");
+        assert_eq!(client.request("?"), "S05");
+        let orig = client.request(&format!("m{:x},1", func2));
+        assert_eq!(client.request(&format!("Z0,{:x},1", func2)), "OK");
+        // the breakpoint is invisible to memory reads
+        assert_eq!(client.request(&format!("m{:x},1", func2)), orig);
+
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        let regs = client.request("g");
+        let rip = decode_hex(&regs[16 * 16..17 * 16]).unwrap();
+        assert_eq!(rip, (func2 as u64).to_le_bytes());
+
+        assert_eq!(client.request(&format!("z0,{:x},1", func2)), "OK");
+        assert_eq!(client.request("c"), "W00");
+    }
+}
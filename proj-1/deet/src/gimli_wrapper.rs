@@ -6,17 +6,26 @@
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
 use gimli;
-use gimli::{UnitOffset, UnitSectionOffset};
+use gimli::{Section, UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    CallSite, File, Function, InlinedCall, Line, Location, Member, Scope, Type, TypeId, TypeKind, Variable, Variant,
+    VariantPart,
+};
+use crate::dwarf_expr;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
-use std::{io, path};
+use std::{fs, io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+/// Loads the units and types of a file, along with the split DWARF (.dwo) files it refers to
+/// that could not be read.
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<TypeId, Type>, Vec<String>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         Ok(object
@@ -39,208 +48,841 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
+    // Define a mapping from type ids to type structs
+    let mut types: HashMap<TypeId, Type> = HashMap::new();
+    // numbers the units, main and split, to make type ids unique
+    let mut unit_id = 0;
 
     let mut compilation_units: Vec<File> = Vec::new();
+    let mut missing_dwo = Vec::new();
 
     // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
+    for header in unit_headers(*dwarf.debug_info.reader())? {
         let unit = dwarf.unit(header)?;
+        let first_file = compilation_units.len();
 
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
-        let mut depth = 0;
-        let mut entries = unit.entries();
-        while let Some((delta_depth, entry)) = entries.next_dfs()? {
-            depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
+        // With split DWARF (-gsplit-dwarf), the unit here is a skeleton and the DIEs are in a .dwo
+        // file. Only the line table stays in the executable.
+        let dwo_path = split_dwarf_file(&unit, &dwarf)?;
+        let dwo = match dwo_path.as_ref() {
+            Some((path, addr_base)) => fs::read(path).ok().map(|data| (data, *addr_base)),
+            None => None,
+        };
+        let dwo_object = match dwo.as_ref() {
+            Some((data, addr_base)) => object::File::parse(data).ok().map(|object| (object, *addr_base)),
+            None => None,
+        };
+        if let (Some((path, _)), None) = (dwo_path.as_ref(), dwo_object.as_ref()) {
+            // the skeleton unit is loaded instead, which has no functions or variables
+            missing_dwo.push(path.to_string_lossy().into_owned());
+        }
+        if let Some((dwo_object, addr_base)) = dwo_object {
+            let load_dwo_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
+                let name = format!("{}.dwo", id.name());
+                Ok(dwo_object
+                    .section_data_by_name(&name)
+                    .unwrap_or(borrow::Cow::Borrowed(&[][..])))
+            };
+            let load_dwo_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+            let dwo_cow = gimli::Dwarf::load(&load_dwo_section, &load_dwo_section_sup)?;
+            let mut dwo_dwarf = dwo_cow.borrow(&borrow_section);
+            // addresses are kept in the executable's .debug_addr
+            dwo_dwarf.debug_addr = dwarf.debug_addr.clone();
+            for dwo_header in unit_headers(*dwo_dwarf.debug_info.reader())? {
+                let mut dwo_unit = dwo_dwarf.unit(dwo_header)?;
+                dwo_unit.addr_base = addr_base;
+                dwo_unit.low_pc = unit.low_pc;
+                if dwo_unit.encoding().version >= 5 {
+                    // a split unit has no DW_AT_str_offsets_base: its string offsets start right
+                    // after the header of .debug_str_offsets.dwo
+                    let header_size = match dwo_unit.encoding().format {
+                        gimli::Format::Dwarf32 => 8,
+                        gimli::Format::Dwarf64 => 16,
                     };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
+                    dwo_unit.str_offsets_base = gimli::DebugStrOffsetsBase(header_size);
+                    // and so do its location and range lists
+                    dwo_unit.loclists_base = gimli::DebugLocListsBase(header_size + 4);
+                    dwo_unit.rnglists_base = gimli::DebugRngListsBase(header_size + 4);
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
+                unit_id += 1;
+                load_types(&dwo_unit, &dwo_dwarf, unit_id, &mut types)?;
+                load_entries(&dwo_unit, &dwo_dwarf, unit_id, &types, &mut compilation_units)?;
+            }
+        } else {
+            unit_id += 1;
+            load_types(&unit, &dwarf, unit_id, &mut types)?;
+            load_entries(&unit, &dwarf, unit_id, &types, &mut compilation_units)?;
+        }
+        load_lines(&unit, &dwarf, &mut compilation_units[first_file..])?;
+    }
+    Ok((compilation_units, types, missing_dwo))
+}
+
+/// Parses the unit headers of a .debug_info section. gimli's own iterator rejects the DWARF 5
+/// skeleton and split unit types, whose headers only add a dwo id at the end. Type units are
+/// skipped.
+fn unit_headers<R: Reader>(section: R) -> Result<Vec<gimli::CompilationUnitHeader<R>>, Error> {
+    let mut headers = Vec::new();
+    let mut input = section.clone();
+    while !input.is_empty() {
+        let offset = gimli::DebugInfoOffset(input.offset_from(&section));
+        let (unit_length, format) = input.read_initial_length()?;
+        let mut rest = input.split(unit_length)?;
+        let version = rest.read_u16()?;
+        let (address_size, abbrev_offset);
+        if 2 <= version && version <= 4 {
+            abbrev_offset = rest.read_offset(format)?;
+            address_size = rest.read_u8()?;
+        } else if version == 5 {
+            let unit_type = gimli::DwUt(rest.read_u8()?);
+            address_size = rest.read_u8()?;
+            abbrev_offset = rest.read_offset(format)?;
+            match unit_type {
+                gimli::DW_UT_compile | gimli::DW_UT_partial => {}
+                gimli::DW_UT_skeleton | gimli::DW_UT_split_compile => {
+                    // dwo id
+                    rest.read_u64()?;
+                }
+                _ => continue,
+            }
+        } else {
+            return Err(Error::GimliError(gimli::Error::UnknownVersion(u64::from(version))));
+        }
+        let encoding = gimli::Encoding {
+            format,
+            version,
+            address_size,
+        };
+        let header = gimli::UnitHeader::new(encoding, unit_length, gimli::DebugAbbrevOffset(abbrev_offset), rest);
+        headers.push(gimli::CompilationUnitHeader::new(header, offset));
+    }
+    Ok(headers)
+}
+
+/// For a split DWARF skeleton unit, returns the path of its .dwo file and the base of its
+/// entries in .debug_addr.
+fn split_dwarf_file<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<(path::PathBuf, gimli::DebugAddrBase<usize>)>, Error> {
+    let mut entries = unit.entries();
+    let root = match entries.next_dfs()? {
+        Some((_, root)) => root,
+        None => return Ok(None),
+    };
+    let dwo_name = match root.attr_value(gimli::DW_AT_dwo_name)? {
+        Some(name) => name,
+        None => match root.attr_value(gimli::DW_AT_GNU_dwo_name)? {
+            Some(name) => name,
+            None => return Ok(None),
+        },
+    };
+    let dwo_name = dwarf.attr_string(unit, dwo_name)?.to_string_lossy()?.into_owned();
+    let mut path = path::PathBuf::new();
+    if let Some(comp_dir) = unit.comp_dir.as_ref() {
+        path.push(comp_dir.to_string_lossy()?.as_ref());
+    }
+    path.push(dwo_name);
+    // gimli only knows the DWARF 5 attribute
+    let addr_base = match root.attr_value(gimli::DW_AT_GNU_addr_base)? {
+        Some(gimli::AttributeValue::SecOffset(base)) => gimli::DebugAddrBase(base),
+        Some(gimli::AttributeValue::DebugAddrBase(base)) => base,
+        _ => unit.addr_base,
+    };
+    Ok(Some((path, addr_base)))
+}
+
+/// Makes a path from the line table or a DW_AT_name absolute, so that the two can be compared.
+fn absolute_path(comp_dir: &path::Path, file: &path::Path) -> path::PathBuf {
+    let mut path = comp_dir.to_path_buf();
+    path.push(file);
+    path
+}
+
+/// Adds the functions and variables of a unit. Its types must have been loaded.
+fn load_entries<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    unit_id: usize,
+    types: &HashMap<TypeId, Type>,
+    compilation_units: &mut Vec<File>,
+) -> Result<(), Error> {
+    let language = unit_language(unit)?;
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    // tags of the entry and its ancestors
+    let mut tags: Vec<gimli::DwTag> = Vec::new();
+    // the lexical blocks and inlined calls we are in, with their depth
+    let mut scopes: Vec<(isize, Scope)> = Vec::new();
+    // the depth of the function declaration we are in (e.g. of a C++ method, in its class),
+    // whose parameters have no storage
+    let mut declaration_depth: Option<isize> = None;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        match declaration_depth {
+            Some(declaration) if depth > declaration => continue,
+            _ => declaration_depth = None,
+        }
+        tags.truncate(depth as usize);
+        tags.push(entry.tag());
+        while scopes.last().map_or(false, |(scope_depth, _)| *scope_depth >= depth) {
+            let (_, scope) = scopes.pop().unwrap();
+            close_scope(scope, &mut scopes, compilation_units);
+        }
+        // Update the variable list for formal params/variables
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => {
+                let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
+                    if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
+                        name
                     } else {
                         "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
+                    }
+                } else {
+                    "<unknown>".to_string()
+                };
+                // rustc names units "src/main.rs/@/<codegen unit>"
+                let name = match name.find("/@/") {
+                    Some(end) => name[..end].to_string(),
+                    None => name,
+                };
+                compilation_units.push(File {
+                    name,
+                    global_variables: Vec::new(),
+                    functions: Vec::new(),
+                    lines: Vec::new(),
+                });
+            }
+            gimli::DW_TAG_subprogram if entry.attr_value(gimli::DW_AT_declaration)?.is_some() => {
+                declaration_depth = Some(depth);
+            }
+            gimli::DW_TAG_subprogram => {
+                let mut func: Function = Default::default();
+                let mut linkage_name = None;
+                for attr in attrs_with_origin(&entry, &unit)? {
+                    let val = get_attr_value(&attr, &unit, &dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
+                            }
                         }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
+                        gimli::DW_AT_linkage_name | gimli::DW_AT_MIPS_linkage_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                linkage_name = Some(name);
+                            }
+                        }
+                        gimli::DW_AT_high_pc => {
+                            if let Ok(DebugValue::Uint(high_pc)) = val {
+                                func.text_length = high_pc.try_into().unwrap();
+                            }
+                        }
+                        gimli::DW_AT_low_pc => {
+                            //println!("low pc {:?}", attr.value());
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into().unwrap();
+                            }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into().unwrap();
+                            }
+                        }
+                        gimli::DW_AT_frame_base => {
+                            func.frame_base = get_location(&attr, &unit, &dwarf);
+                        }
+                        gimli::DW_AT_type => {
+                            if let gimli::AttributeValue::UnitRef(offset) = attr.value() {
+                                func.return_type = types.get(&type_id(unit_id, offset)).cloned();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                // DW_AT_name has no path (`new` for `Vec::new`) and no C++ parameters
+                if let Some(linkage_name) = linkage_name {
+                    func.name = demangle(&linkage_name, language);
                 }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
+                compilation_units.last_mut().unwrap().functions.push(func);
+            }
+            // calls that record the values of their arguments, for DW_OP_entry_value in the callee
+            gimli::DW_TAG_call_site | gimli::DW_TAG_GNU_call_site => {
+                let mut site = CallSite::default();
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    if let gimli::DW_AT_call_return_pc | gimli::DW_AT_low_pc = attr.name() {
+                        if let Ok(DebugValue::Uint(addr)) = get_attr_value(&attr, &unit, &dwarf) {
+                            site.return_address = addr.try_into().unwrap();
+                        }
+                    }
+                }
+                if let Some(func) = compilation_units.last_mut().unwrap().functions.last_mut() {
+                    func.call_sites.push(site);
+                }
+            }
+            gimli::DW_TAG_call_site_parameter | gimli::DW_TAG_GNU_call_site_parameter => {
+                let register = match entry.attr_value(gimli::DW_AT_location)? {
+                    Some(gimli::AttributeValue::Exprloc(expr)) => {
+                        dwarf_expr::register_operand(&expr.0.to_slice()?)
+                    }
+                    _ => None,
+                };
+                let value = match entry.attr_value(gimli::DW_AT_call_value)? {
+                    Some(value) => Some(value),
+                    None => entry.attr_value(gimli::DW_AT_GNU_call_site_value)?,
+                };
+                if let (Some(register), Some(gimli::AttributeValue::Exprloc(value))) = (register, value) {
+                    let site = compilation_units
+                        .last_mut()
+                        .unwrap()
+                        .functions
+                        .last_mut()
+                        .and_then(|func| func.call_sites.last_mut());
+                    if let Some(site) = site {
+                        site.parameters.push((register, value.0.to_slice()?.into_owned()));
+                    }
+                }
+            }
+            gimli::DW_TAG_lexical_block | gimli::DW_TAG_inlined_subroutine => {
+                let mut scope = Scope {
+                    ranges: entry_ranges(&entry, &unit, &dwarf),
+                    ..Scope::default()
+                };
+                if entry.tag() == gimli::DW_TAG_inlined_subroutine {
+                    let mut call = InlinedCall::default();
+                    let mut linkage_name = None;
+                    for attr in attrs_with_origin(&entry, &unit)? {
+                        match (attr.name(), attr.value()) {
+                            (gimli::DW_AT_name, _) => {
+                                if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
+                                    call.name = name;
                                 }
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
+                            (gimli::DW_AT_linkage_name, _) | (gimli::DW_AT_MIPS_linkage_name, _) => {
+                                if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
+                                    linkage_name = Some(name);
                                 }
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
-                                }
+                            (gimli::DW_AT_call_file, gimli::AttributeValue::FileIndex(index)) => {
+                                call.call_file = file_path(index, &unit, &dwarf).unwrap_or_default();
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
+                            (gimli::DW_AT_call_line, _) => {
+                                if let Ok(DebugValue::Uint(line)) = get_attr_value(&attr, &unit, &dwarf) {
+                                    call.call_line = line.try_into().unwrap();
                                 }
                             }
                             _ => {}
                         }
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
+                    if let Some(linkage_name) = linkage_name {
+                        call.name = demangle(&linkage_name, language);
+                    }
+                    scope.inlined = Some(call);
                 }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                scopes.push((depth, scope));
+            }
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut entity_type: Option<Type> = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut declaration = false;
+                for attr in attrs_with_origin(&entry, &unit)? {
+                    let val = get_attr_value(&attr, &unit, &dwarf);
+                    //println!("   {}: {:?}", attr.name(), val);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
-                                }
+                        }
+                        gimli::DW_AT_type => {
+                            if let gimli::AttributeValue::UnitRef(offset) = attr.value() {
+                                entity_type = types.get(&type_id(unit_id, offset)).cloned();
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        gimli::DW_AT_location => {
+                            if let Some(loc) = get_location(&attr, &unit, &dwarf) {
+                                location = Some(loc);
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
-                                }
+                        }
+                        gimli::DW_AT_const_value => {
+                            location = const_value(&attr).map(Location::Value);
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
                             }
-                            _ => {}
                         }
+                        gimli::DW_AT_declaration => declaration = true,
+                        _ => {}
+                    }
+                }
+                // parameters of function types have no storage
+                let parent = tags[..tags.len() - 1].last().copied();
+                let in_code = matches!(
+                    parent,
+                    Some(gimli::DW_TAG_subprogram)
+                        | Some(gimli::DW_TAG_lexical_block)
+                        | Some(gimli::DW_TAG_inlined_subroutine)
+                );
+                if depth > 1 && in_code && !declaration && location.is_none() {
+                    location = Some(Location::OptimizedOut);
+                }
+                if let (Some(Location::Value(bytes)), Some(dtype)) = (location.as_mut(), entity_type.as_ref()) {
+                    bytes.resize(dtype.size, 0);
+                }
+                if entity_type.is_some() && location.is_some() && (depth == 1 || in_code) {
+                    let var = Variable {
+                        name,
+                        entity_type: entity_type.unwrap(),
+                        location: location.unwrap(),
+                        line_number: line_number.try_into().unwrap(),
+                    };
+                    if depth == 1 {
+                        compilation_units
+                            .last_mut()
+                            .unwrap()
+                            .global_variables
+                            .push(var);
+                    } else if let Some((_, scope)) = scopes.last_mut() {
+                        scope.variables.push(var);
+                    } else if depth > 1 {
+                        compilation_units
+                            .last_mut()
+                            .unwrap()
+                            .functions
+                            .last_mut()
+                            .unwrap()
+                            .variables
+                            .push(var);
                     }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                }
+            }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
+        }
+    }
+    while let Some((_, scope)) = scopes.pop() {
+        close_scope(scope, &mut scopes, compilation_units);
+    }
+    Ok(())
+}
+
+/// Adds a finished scope to the one containing it, or to its function.
+fn close_scope(scope: Scope, scopes: &mut Vec<(isize, Scope)>, compilation_units: &mut Vec<File>) {
+    if let Some((_, parent)) = scopes.last_mut() {
+        parent.scopes.push(scope);
+    } else if let Some(func) = compilation_units.last_mut().and_then(|file| file.functions.last_mut()) {
+        func.scopes.push(scope);
+    }
+}
+
+/// The id of the type at `offset` in a unit.
+fn type_id(unit_id: usize, offset: UnitOffset<usize>) -> TypeId {
+    (unit_id << 32) | offset.0
+}
+
+/// The `DW_AT_language` of a unit.
+fn unit_language<R: Reader>(unit: &gimli::Unit<R>) -> Result<Option<gimli::DwLang>, Error> {
+    let mut entries = unit.entries();
+    match entries.next_dfs()? {
+        Some((_, root)) => match root.attr_value(gimli::DW_AT_language)? {
+            Some(gimli::AttributeValue::Language(language)) => Ok(Some(language)),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Demangles a Rust (legacy or v0) or C++ symbol name, leaving other names as they are.
+fn demangle(name: &str, language: Option<gimli::DwLang>) -> String {
+    let language = language.map(|language| addr2line::gimli::DwLang(language.0));
+    addr2line::demangle_auto(name.into(), language).into_owned()
+}
+
+fn entry_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// A type whose children (members, enumerators, array bounds...) are being read.
+enum OpenType {
+    Type(TypeId, Type),
+    /// With the offset of the member DW_AT_discr refers to
+    VariantPart(Option<UnitOffset<usize>>, VariantPart),
+    Variant(Option<u64>, Option<Member>),
+    /// Rust types are named with the path of their namespaces
+    Namespace(String),
+}
+
+/// Adds the types of a unit to `types`. This is a pass of its own, before load_entries, because
+/// entries may refer to types defined after them.
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    unit_id: usize,
+    types: &mut HashMap<TypeId, Type>,
+) -> Result<(), Error> {
+    let rust = unit_language(unit)? == Some(gimli::DW_LANG_Rust);
+    // the unnamed const/volatile/... types, named after the type they qualify once it is known
+    let mut qualifiers: HashMap<TypeId, &str> = HashMap::new();
+    let mut depth = 0;
+    let mut open: Vec<(isize, OpenType)> = Vec::new();
+    // the offsets of this unit's entries, some of which turn out to be types
+    let mut ids: Vec<TypeId> = Vec::new();
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while open.last().map_or(false, |(open_depth, _)| *open_depth >= depth) {
+            let (_, item) = open.pop().unwrap();
+            close_type(item, &mut open, types);
+        }
+        let name = entry_name(entry, unit, dwarf);
+        let size = match entry.attr_value(gimli::DW_AT_byte_size)?.and_then(|size| size.udata_value()) {
+            Some(size) => size as usize,
+            None => 0,
+        };
+        let target = match entry.attr_value(gimli::DW_AT_type)? {
+            Some(gimli::AttributeValue::UnitRef(offset)) => Some(type_id(unit_id, offset)),
+            _ => None,
+        };
+        let make = |kind: TypeKind| Type {
+            name: name.clone().unwrap_or_default(),
+            size,
+            kind,
+            rust,
+        };
+        let id = type_id(unit_id, entry.offset());
+        ids.push(id);
+        // the open type this entry belongs to, if it is its parent
+        let parent = match open.last_mut() {
+            Some((open_depth, parent)) if *open_depth == depth - 1 => Some(parent),
+            _ => None,
+        };
+        match entry.tag() {
+            gimli::DW_TAG_namespace => {
+                open.push((depth, OpenType::Namespace(name.unwrap_or_default())));
+            }
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(gimli::AttributeValue::Encoding(encoding)) => encoding.0,
+                    _ => 0,
+                };
+                types.insert(id, make(TypeKind::Base(encoding)));
+            }
+            gimli::DW_TAG_pointer_type | gimli::DW_TAG_reference_type | gimli::DW_TAG_rvalue_reference_type => {
+                types.insert(id, make(TypeKind::Pointer(target)));
+            }
+            gimli::DW_TAG_typedef => {
+                types.insert(id, make(TypeKind::Alias(target)));
+            }
+            gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_atomic_type => {
+                let qualifier = match entry.tag() {
+                    gimli::DW_TAG_const_type => "const",
+                    gimli::DW_TAG_volatile_type => "volatile",
+                    gimli::DW_TAG_restrict_type => "restrict",
+                    _ => "_Atomic",
+                };
+                qualifiers.insert(id, qualifier);
+                types.insert(id, make(TypeKind::Alias(target)));
+            }
+            gimli::DW_TAG_subroutine_type | gimli::DW_TAG_unspecified_type => {
+                types.insert(id, make(TypeKind::Other));
+            }
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+                let mut ty = make(TypeKind::Struct {
+                    members: Vec::new(),
+                    variants: None,
+                    template_params: Vec::new(),
+                });
+                if rust && !ty.name.is_empty() {
+                    // types nested in others (like the variants of an enum) are named after them
+                    let mut path: Vec<&str> = Vec::new();
+                    for (_, item) in &open {
+                        match item {
+                            OpenType::Namespace(name) => path.push(name),
+                            OpenType::Type(_, outer) => path = vec![&outer.name],
+                            _ => {}
                         }
                     }
+                    path.push(&ty.name);
+                    ty.name = path.join("::");
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
-            }
-        }
-
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+                open.push((depth, OpenType::Type(id, ty)));
+            }
+            gimli::DW_TAG_enumeration_type => {
+                open.push((depth, OpenType::Type(id, make(TypeKind::Enum(Vec::new())))));
+            }
+            gimli::DW_TAG_array_type => {
+                if let Some(element) = target {
+                    let kind = TypeKind::Array { element, count: None };
+                    open.push((depth, OpenType::Type(id, make(kind))));
+                }
+            }
+            gimli::DW_TAG_subrange_type => {
+                if let Some(OpenType::Type(_, Type { kind: TypeKind::Array { count, .. }, .. })) = parent {
+                    let length = match entry.attr_value(gimli::DW_AT_count)?.and_then(|count| count.udata_value()) {
+                        Some(length) => Some(length),
+                        // -1 for arrays of unknown size
+                        None => match entry.attr_value(gimli::DW_AT_upper_bound)? {
+                            Some(gimli::AttributeValue::Sdata(bound)) if bound < 0 => None,
+                            Some(bound) => bound.udata_value().map(|bound| bound + 1),
+                            None => None,
+                        },
+                    };
+                    // a multi-dimensional array is read as a flat one
+                    *count = match length {
+                        Some(length) => Some(count.unwrap_or(1) * length as usize),
+                        None => None,
+                    };
+                }
+            }
+            gimli::DW_TAG_enumerator => {
+                if let Some(OpenType::Type(_, Type { kind: TypeKind::Enum(values), .. })) = parent {
+                    let value = match entry.attr_value(gimli::DW_AT_const_value)? {
+                        Some(gimli::AttributeValue::Sdata(value)) => Some(value),
+                        Some(value) => value.udata_value().map(|value| value as i64),
+                        None => None,
+                    };
+                    if let (Some(name), Some(value)) = (name, value) {
+                        values.push((name, value));
+                    }
+                }
+            }
+            gimli::DW_TAG_member => {
+                let offset = match entry.attr_value(gimli::DW_AT_data_member_location)? {
+                    Some(gimli::AttributeValue::Exprloc(expr)) => {
+                        // DWARF 2 compilers use DW_OP_plus_uconst
+                        match gimli::Operation::parse(&mut expr.0.clone(), unit.encoding()) {
+                            Ok(gimli::Operation::PlusConstant { value }) => value,
+                            _ => 0,
                         }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
                     }
+                    Some(location) => location.udata_value().unwrap_or(0),
+                    None => 0,
+                };
+                let member = target.map(|type_id| Member {
+                    name: name.unwrap_or_default(),
+                    offset: offset as usize,
+                    type_id,
+                });
+                match (parent, member) {
+                    (Some(OpenType::Type(_, Type { kind: TypeKind::Struct { members, .. }, .. })), Some(member)) => {
+                        members.push(member);
+                    }
+                    (Some(OpenType::VariantPart(discr, part)), member) if *discr == Some(entry.offset()) => {
+                        part.discriminant = member;
+                    }
+                    (Some(OpenType::Variant(_, variant_member)), member) => *variant_member = member,
+                    _ => {}
+                }
+            }
+            gimli::DW_TAG_template_type_parameter => {
+                if let (Some(OpenType::Type(_, Type { kind: TypeKind::Struct { template_params, .. }, .. })), Some(target)) =
+                    (parent, target)
+                {
+                    template_params.push(target);
+                }
+            }
+            gimli::DW_TAG_variant_part => {
+                let discr = match entry.attr_value(gimli::DW_AT_discr)? {
+                    Some(gimli::AttributeValue::UnitRef(offset)) => Some(offset),
+                    _ => None,
+                };
+                open.push((depth, OpenType::VariantPart(discr, VariantPart::default())));
+            }
+            gimli::DW_TAG_variant => {
+                let discr_value = match entry.attr_value(gimli::DW_AT_discr_value)? {
+                    Some(gimli::AttributeValue::Sdata(value)) => Some(value as u64),
+                    Some(value) => value.udata_value(),
+                    None => None,
+                };
+                open.push((depth, OpenType::Variant(discr_value, None)));
+            }
+            _ => {}
+        }
+    }
+    while let Some((_, item)) = open.pop() {
+        close_type(item, &mut open, types);
+    }
+
+    // name and size the types that are made from others
+    let derived: Vec<(TypeId, String, usize)> = ids
+        .into_iter()
+        .filter(|id| types.contains_key(id))
+        .map(|id| {
+            let (name, size) = derived_name_and_size(types, &qualifiers, id, 0);
+            (id, name, size)
+        })
+        .collect();
+    for (id, name, size) in derived {
+        let ty = types.get_mut(&id).unwrap();
+        ty.name = name;
+        ty.size = size;
+    }
+    Ok(())
+}
+
+/// Adds a type whose children have all been read to `types`, or a variant part or variant to the
+/// type containing it.
+fn close_type(item: OpenType, open: &mut Vec<(isize, OpenType)>, types: &mut HashMap<TypeId, Type>) {
+    let parent = open.last_mut().map(|(_, parent)| parent);
+    match (item, parent) {
+        (OpenType::Type(id, ty), _) => {
+            types.insert(id, ty);
+        }
+        (OpenType::VariantPart(_, part), Some(OpenType::Type(_, ty))) => {
+            if let TypeKind::Struct { variants, .. } = &mut ty.kind {
+                *variants = Some(part);
+            }
+        }
+        (OpenType::Variant(discr_value, Some(member)), Some(OpenType::VariantPart(_, part))) => {
+            part.variants.push(Variant { discr_value, member });
+        }
+        _ => {}
+    }
+}
+
+/// The name and size of a type, deriving them from the types it is made from when it has none
+/// of its own: `int *`, `const char`, `int [4]`.
+fn derived_name_and_size(
+    types: &HashMap<TypeId, Type>,
+    qualifiers: &HashMap<TypeId, &str>,
+    id: TypeId,
+    depth: usize,
+) -> (String, usize) {
+    let ty = match types.get(&id) {
+        Some(ty) => ty,
+        None => return ("<unknown type>".to_string(), 0),
+    };
+    if depth > 8 || (!ty.name.is_empty() && ty.size != 0) {
+        return (ty.name.clone(), ty.size);
+    }
+    let target = |target: Option<TypeId>| match target {
+        Some(target) => derived_name_and_size(types, qualifiers, target, depth + 1),
+        None => ("void".to_string(), 0),
+    };
+    let (name, size) = match &ty.kind {
+        TypeKind::Pointer(pointee) => (format!("{} *", target(*pointee).0), 8),
+        TypeKind::Alias(aliased) => {
+            let (name, size) = target(*aliased);
+            match qualifiers.get(&id) {
+                Some(qualifier) => (format!("{} {}", qualifier, name), size),
+                None => (name, size),
+            }
+        }
+        TypeKind::Array { element, count } => {
+            let (name, size) = target(Some(*element));
+            match count {
+                Some(count) => (format!("{} [{}]", name, count), size * count),
+                None => (format!("{} []", name), 0),
+            }
+        }
+        TypeKind::Struct { .. } => ("<anonymous struct>".to_string(), ty.size),
+        TypeKind::Enum(_) => ("<anonymous enum>".to_string(), ty.size),
+        _ => (ty.name.clone(), ty.size),
+    };
+    let name = if ty.name.is_empty() { name } else { ty.name.clone() };
+    let size = if ty.size == 0 { size } else { ty.size };
+    (name, size)
+}
+
+/// The address ranges of an entry, from DW_AT_low_pc/DW_AT_high_pc or DW_AT_ranges.
+fn entry_ranges<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    if let Ok(mut iter) = dwarf.die_ranges(unit, entry) {
+        while let Ok(Some(range)) = iter.next() {
+            ranges.push((range.begin.try_into().unwrap(), range.end.try_into().unwrap()));
+        }
+    }
+    ranges
+}
+
+/// The absolute path of a file of the unit's line table.
+fn file_path<R: Reader>(index: u64, unit: &gimli::Unit<R>, dwarf: &gimli::Dwarf<R>) -> Option<String> {
+    let header = unit.line_program.as_ref()?.header();
+    let file = header.file(index)?;
+    let mut path = path::PathBuf::new();
+    if let Some(dir) = file.directory(header) {
+        path.push(dwarf.attr_string(unit, dir).ok()?.to_string_lossy().ok()?.as_ref());
+    }
+    path.push(dwarf.attr_string(unit, file.path_name()).ok()?.to_string_lossy().ok()?.as_ref());
+    let comp_dir = match unit.comp_dir.as_ref() {
+        Some(dir) => path::PathBuf::from(dir.to_string_lossy().ok()?.as_ref()),
+        None => path::PathBuf::new(),
+    };
+    Some(absolute_path(&comp_dir, &path).to_string_lossy().into_owned())
+}
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
-
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
-
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
+/// Adds the line table of a unit to the files it describes.
+fn load_lines<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    compilation_units: &mut [File],
+) -> Result<(), Error> {
+    let comp_dir = match unit.comp_dir.as_ref() {
+        Some(dir) => path::PathBuf::from(dir.to_string_lossy()?.as_ref()),
+        None => path::PathBuf::new(),
+    };
+    // Get line numbers
+    if let Some(program) = unit.line_program.clone() {
+        // Iterate over the line program rows.
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.end_sequence() {
+                // Determine the path. Real applications should cache this for performance.
+                let mut path = path::PathBuf::new();
+                if let Some(file) = row.file(header) {
+                    if let Some(dir) = file.directory(header) {
+                        path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy()?.as_ref());
                     }
+                    path.push(
+                        dwarf
+                            .attr_string(&unit, file.path_name())?
+                            .to_string_lossy()?
+                            .as_ref(),
+                    );
+                }
+
+                // Get the File. DWARF 5 line tables name files relative to a different
+                // directory than DW_AT_name does, so compare absolute paths.
+                let path = absolute_path(&comp_dir, &path);
+                let file = compilation_units
+                    .iter_mut()
+                    .find(|f| absolute_path(&comp_dir, path::Path::new(&f.name)) == path);
+
+                // Determine line/column. DWARF line/column is never 0, so we use that
+                // but other applications may want to display this differently.
+                let line = row.line().unwrap_or(0);
+
+                if let Some(file) = file {
+                    file.lines.push(Line {
+                        file: file.name.clone(),
+                        number: line.try_into().unwrap(),
+                        address: row.address().try_into().unwrap(),
+                    });
                 }
             }
         }
     }
-    Ok(compilation_units)
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
@@ -291,25 +933,129 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
-                }
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    match attr.value() {
+        gimli::AttributeValue::Exprloc(ref data) => {
+            if let Some(location) = simple_location(data, unit, dwarf) {
+                return Some(location);
             }
+            Some(Location::Expression(data.0.to_slice().ok()?.into_owned()))
+        }
+        // the location depends on the pc, typically in optimized code
+        gimli::AttributeValue::LocationListsRef(offset) => location_list(offset, unit, dwarf),
+        gimli::AttributeValue::DebugLocListsIndex(index) => {
+            location_list(dwarf.locations_offset(unit, index).ok()?, unit, dwarf)
         }
+        _ => None,
     }
-    None
+}
+
+/// The common expressions made of a single operation that need no evaluation: a static address
+/// or an offset from the frame base.
+fn simple_location<R: Reader>(
+    data: &gimli::Expression<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let encoding = unit.encoding();
+    let mut pc = data.0.clone();
+    // DW_OP_GNU_addr_index, the pre-DWARF 5 split DWARF spelling of DW_OP_addrx, which gimli
+    // does not parse
+    if pc.clone().read_u8().ok() == Some(0xfb) {
+        pc.read_u8().ok()?;
+        let index = gimli::DebugAddrIndex(pc.read_uleb128().ok()? as usize);
+        let address = dwarf.address(unit, index).ok()?;
+        return if pc.len() == 0 { Some(Location::Address(address.try_into().unwrap())) } else { None };
+    }
+    let op = gimli::Operation::parse(&mut pc, encoding).ok()?;
+    if pc.len() > 0 {
+        return None;
+    }
+    match op {
+        gimli::Operation::FrameOffset { offset } => Some(Location::FramePointerOffset(offset.try_into().unwrap())),
+        gimli::Operation::Address { address } => Some(Location::Address(address.try_into().unwrap())),
+        // split DWARF keeps addresses in .debug_addr
+        gimli::Operation::AddressIndex { index } => {
+            let address = dwarf.address(unit, index).ok()?;
+            Some(Location::Address(address.try_into().unwrap()))
+        }
+        _ => None,
+    }
+}
+
+fn location_list<R: Reader>(
+    offset: gimli::LocationListsOffset,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    let mut entries = Vec::new();
+    let mut iter = dwarf.locations(unit, offset).ok()?;
+    while let Some(entry) = iter.next().ok()? {
+        entries.push((
+            entry.range.begin.try_into().unwrap(),
+            entry.range.end.try_into().unwrap(),
+            entry.data.0.to_slice().ok()?.into_owned(),
+        ));
+    }
+    Some(Location::List(entries))
+}
+
+/// The bytes of a `DW_AT_const_value`, least significant first.
+fn const_value<R: Reader>(attr: &gimli::Attribute<R>) -> Option<Vec<u8>> {
+    match attr.value() {
+        gimli::AttributeValue::Data1(data) => Some(vec![data]),
+        gimli::AttributeValue::Data2(data) => Some(data.to_le_bytes().to_vec()),
+        gimli::AttributeValue::Data4(data) => Some(data.to_le_bytes().to_vec()),
+        gimli::AttributeValue::Data8(data) => Some(data.to_le_bytes().to_vec()),
+        gimli::AttributeValue::Sdata(data) => Some(data.to_le_bytes().to_vec()),
+        gimli::AttributeValue::Udata(data) => Some(data.to_le_bytes().to_vec()),
+        gimli::AttributeValue::Block(data) => Some(data.to_slice().ok()?.into_owned()),
+        _ => None,
+    }
+}
+
+/// Returns the attributes of an entry, preceded by those of its `DW_AT_abstract_origin` or
+/// `DW_AT_specification`, and theirs. The out-of-line copy of a function that is also inlined
+/// elsewhere only carries its addresses and locations, and refers to the abstract instance for
+/// names, types and lines; the definition of a C++ method refers to its declaration in the class.
+fn attrs_with_origin<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Vec<gimli::Attribute<R>>, Error> {
+    let origin_of = |entry: &gimli::DebuggingInformationEntry<R>| -> Result<Option<UnitOffset<usize>>, Error> {
+        for name in &[gimli::DW_AT_abstract_origin, gimli::DW_AT_specification] {
+            if let Some(gimli::AttributeValue::UnitRef(offset)) = entry.attr_value(*name)? {
+                return Ok(Some(offset));
+            }
+        }
+        Ok(None)
+    };
+    let mut origins = Vec::new();
+    let mut next = origin_of(entry)?;
+    while let (Some(offset), true) = (next, origins.len() < 4) {
+        let origin = unit.entry(offset)?;
+        next = origin_of(&origin)?;
+        origins.push(origin);
+    }
+    let mut list = Vec::new();
+    for origin in origins.iter().rev() {
+        let mut attrs = origin.attrs();
+        while let Some(attr) = attrs.next()? {
+            // the entry is the definition the declaration is about
+            if attr.name() != gimli::DW_AT_declaration {
+                list.push(attr);
+            }
+        }
+    }
+    let mut attrs = entry.attrs();
+    while let Some(attr) = attrs.next()? {
+        list.push(attr);
+    }
+    Ok(list)
 }
 
 // based on dwarf_dump.rs
@@ -344,6 +1090,12 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        // DWARF 5 string forms
+        gimli::AttributeValue::DebugLineStrRef(_) | gimli::AttributeValue::DebugStrOffsetsIndex(_) => {
+            let s = dwarf.attr_string(unit, value)?;
+            Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+        }
+        gimli::AttributeValue::DebugAddrIndex(index) => Ok(DebugValue::Uint(dwarf.address(unit, index)?)),
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
@@ -1,18 +1,43 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use std::process::Child;
+use std::fs::OpenOptions;
 use std::process::Command;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::process::CommandExt;
-use crate::dwarf_data::DwarfData;
+use crate::dwarf_data::{DwarfData, Location, Variable};
+use crate::dwarf_expr::{self, Context};
+use crate::values;
 use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::thread;
+use std::time::Duration;
 
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
+/// A stack frame, identified by the instruction pointer and frame pointer it runs with.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub rip: usize,
+    pub rbp: usize,
+    pub rsp: usize,
+    /// The canonical frame address: %rsp in the caller before its call instruction
+    pub cfa: usize,
+    /// The code the frame is running, for looking up functions and location lists. Outer frames
+    /// return after a call instruction, so this is the byte before their %rip.
+    pub pc: usize,
+    /// 0 for the innermost frame, the only one whose registers are all known
+    pub level: usize,
+    /// Frames of functions inlined at `pc` share its registers: 0 is the innermost inlined
+    /// function, 1 the one it was inlined into, and so on up to the function that has the code.
+    pub inline_depth: usize,
+}
+
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
     /// current instruction pointer that it is stopped at.
@@ -24,6 +49,67 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates a thread stopped on its way into a system call, while syscall tracing is on.
+    /// Contains the syscall number and the instruction pointer.
+    SyscallEntry(u64, usize),
+
+    /// Indicates a thread stopped as a system call returned, while syscall tracing is on.
+    /// Contains the syscall number and the instruction pointer.
+    SyscallExit(u64, usize),
+
+    /// Indicates the inferior forked, while fork stops are on. Contains the pid of the child,
+    /// which is still attached and stopped (see `detach_child`), and the instruction pointer.
+    Forked(Pid, usize),
+
+    /// Indicates the inferior replaced its program with execve, while exec stops are on. Contains
+    /// the instruction pointer, at the entry point of the new program's loader.
+    Exec(usize),
+
+    /// Indicates the inferior called exit, while exit stops are on. It has not exited yet, so its
+    /// memory can still be inspected. Contains the exit status code and the instruction pointer.
+    Exiting(i32, usize),
+}
+
+/// How a function called in the inferior with `call_function` ended.
+pub enum CallResult {
+    /// It returned. Contains the registers it returned with; the inferior's own have been put
+    /// back.
+    Returned(libc::user_regs_struct, libc::user_fpregs_struct),
+    /// Something stopped it first: a breakpoint, a signal or ctrl+c. The call is abandoned and the
+    /// registers put back, unless the inferior is gone.
+    Interrupted(Status),
+}
+
+// integer and pointer arguments go in these registers, in order (System V AMD64 ABI)
+pub const ARGUMENT_REGISTERS: usize = 6;
+
+/// The floating point and vector registers of thread `tid`.
+fn fp_registers_of(tid: Pid) -> Result<libc::user_fpregs_struct, nix::Error> {
+    let mut fpregs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETFPREGS,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            &mut fpregs as *mut libc::user_fpregs_struct as *mut libc::c_void,
+        )
+    };
+    nix::errno::Errno::result(res)?;
+    Ok(fpregs)
+}
+
+fn set_fp_registers_of(tid: Pid, mut fpregs: libc::user_fpregs_struct) -> Result<(), nix::Error> {
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_SETFPREGS,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            &mut fpregs as *mut libc::user_fpregs_struct as *mut libc::c_void,
+        )
+    };
+    nix::errno::Errno::result(res)?;
+    Ok(())
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
@@ -35,55 +121,634 @@ fn child_traceme() -> Result<(), std::io::Error> {
     )))
 }
 
+/// How to start an inferior, besides its program and arguments: where its standard streams go, and
+/// the environment and working directory it runs with. By default it shares deet's.
+#[derive(Clone, Debug, Default)]
+pub struct LaunchOptions {
+    /// File to read standard input from
+    pub stdin: Option<String>,
+    /// File to write standard output to, and whether to append to it rather than truncate it
+    pub stdout: Option<(String, bool)>,
+    /// Terminal for the standard streams that are not redirected, which also becomes the
+    /// program's controlling terminal
+    pub tty: Option<String>,
+    /// Whether the program starts with an empty environment rather than deet's
+    pub clear_env: bool,
+    /// Environment variables to set, or to remove if None, in order
+    pub env: Vec<(String, Option<String>)>,
+    /// Working directory
+    pub cwd: Option<String>,
+}
+
+impl LaunchOptions {
+    /// Sets `name` in the environment of programs started from now on.
+    pub fn set_env(&mut self, name: &str, value: Option<&str>) {
+        self.env.retain(|(n, _)| n != name);
+        self.env.push((name.to_string(), value.map(|v| v.to_string())));
+    }
+
+    fn apply(&self, cmd: &mut Command) -> Result<(), std::io::Error> {
+        let open = |path: &str, options: &mut OpenOptions| {
+            options.open(path).map_err(|err| std::io::Error::new(err.kind(), format!("{}: {}", path, err)))
+        };
+        if let Some(tty) = &self.tty {
+            let tty = open(tty, OpenOptions::new().read(true).write(true))?;
+            cmd.stdin(tty.try_clone()?).stdout(tty.try_clone()?).stderr(tty);
+        }
+        if let Some(path) = &self.stdin {
+            cmd.stdin(open(path, OpenOptions::new().read(true))?);
+        }
+        if let Some((path, append)) = &self.stdout {
+            let file = open(path, OpenOptions::new().write(true).create(true).append(*append).truncate(!*append))?;
+            cmd.stdout(file);
+        }
+        if self.clear_env {
+            cmd.env_clear();
+        }
+        for (name, value) in &self.env {
+            match value {
+                Some(value) => cmd.env(name, value),
+                None => cmd.env_remove(name),
+            };
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        Ok(())
+    }
+}
+
+/// A thread of the inferior. `id` is the small number shown by `info threads` and accepted by
+/// `thread N`; `tid` is the kernel thread id that ptrace operates on.
+#[derive(Clone, Copy, Debug)]
+pub struct Thread {
+    pub id: usize,
+    pub tid: Pid,
+}
+
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    threads: Vec<Thread>,
+    next_thread_id: usize,
+    // the thread picked with `thread N`, or else the one that reported the last stop
+    current_tid: Pid,
+    // the thread that reported the last stop; it may be sitting just past a breakpoint
+    stopped_tid: Pid,
+    // threads that still have a SIGSTOP from stop_other_threads in flight; it is swallowed when
+    // it arrives instead of being reported to the user
+    pending_stops: HashSet<Pid>,
+    // signals that arrived while stopping the other threads, re-delivered on the next resume
+    pending_signals: HashMap<Pid, signal::Signal>,
+    // whether threads are resumed with PTRACE_SYSCALL, to stop at every system call
+    trace_syscalls: bool,
+    // threads stopped inside a system call; the next syscall stop of these is the return
+    in_syscall: HashSet<Pid>,
+    // options for the fork, exec and exit stops that are on, on top of trace_options()
+    event_options: ptrace::Options,
+    // children made by vfork that are still attached; they share our memory
+    vfork_children: HashSet<Pid>,
+    // addresses we have written an int3 to, for telling a breakpoint trap from a step
+    traps: HashSet<usize>,
+    // set while a single thread runs on its own, when the others are left stopped
+    running_alone: bool,
+}
+
+/// The ptrace options every inferior is traced with: follow threads created with clone(), tell
+/// syscall stops apart from SIGTRAPs, and never leave the inferior running if deet dies.
+fn trace_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACESYSGOOD
+        | ptrace::Options::PTRACE_O_EXITKILL
+}
+
+/// Waits for a child the kernel attached us to when it was forked to stop. It starts out with a
+/// SIGSTOP.
+fn wait_for_new_child(child: Pid) -> Result<(), nix::Error> {
+    loop {
+        match waitpid(child, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::Stopped(_, signal::Signal::SIGSTOP) => return Ok(()),
+            WaitStatus::Stopped(..) | WaitStatus::PtraceEvent(..) => {}
+            _ => return Err(nix::Error::Sys(nix::errno::Errno::ESRCH)),
+        }
+    }
+}
+
+/// Sends `sig` to a single thread of the inferior. nix has no wrapper for tgkill.
+fn tgkill(pid: Pid, tid: Pid, sig: signal::Signal) -> Result<(), nix::Error> {
+    let ret = unsafe {
+        libc::syscall(libc::SYS_tgkill, pid.as_raw(), tid.as_raw(), sig as libc::c_int)
+    };
+    if ret < 0 {
+        Err(nix::Error::last())
+    } else {
+        Ok(())
+    }
+}
+
+// ctrl+c while the inferior runs. The handler can only touch atomics, so it finds the process to
+// stop in WAITING_PID, which wait() sets for as long as it blocks in waitpid.
+static INTERRUPT_REQUESTED: AtomicBool = AtomicBool::new(false);
+static WAITING_PID: AtomicI32 = AtomicI32::new(0);
+// whether the handler sent a SIGSTOP that has not been accounted for yet
+static STOP_SENT: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_interrupt(_: libc::c_int) {
+    INTERRUPT_REQUESTED.store(true, Ordering::SeqCst);
+    let pid = WAITING_PID.swap(0, Ordering::SeqCst);
+    if pid != 0 {
+        STOP_SENT.store(true, Ordering::SeqCst);
+        unsafe { libc::syscall(libc::SYS_tgkill, pid, pid, libc::SIGSTOP) };
+    }
+}
+
+/// Makes ctrl+c interrupt a running inferior instead of killing deet: the inferior is stopped
+/// and the stop is reported as a SIGINT, like gdb does.
+pub fn handle_ctrl_c() -> Result<(), nix::Error> {
+    let action = signal::SigAction::new(
+        signal::SigHandler::Handler(request_interrupt),
+        signal::SaFlags::SA_RESTART,
+        signal::SigSet::empty(),
+    );
+    unsafe { signal::sigaction(signal::Signal::SIGINT, &action) }?;
+    Ok(())
+}
+
+/// Returns true (once) if ctrl+c was pressed since the inferior was last resumed. Loops that run
+/// the inferior one step at a time check this between steps.
+pub fn interrupt_requested() -> bool {
+    INTERRUPT_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Returns true if `tid` has `sig` pending, sent either to it or to its whole process.
+fn signal_pending(tid: Pid, sig: signal::Signal) -> bool {
+    let status = match std::fs::read_to_string(format!("/proc/{}/status", tid)) {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    let bit = 1u64 << (sig as i32 - 1);
+    status
+        .lines()
+        .filter(|line| line.starts_with("SigPnd:") || line.starts_with("ShdPnd:"))
+        .any(|line| u64::from_str_radix(line[7..].trim(), 16).unwrap_or(0) & bit != 0)
 }
 
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
     pub fn new(target: &str, args: &Vec<String>, breakpoints: &mut HashMap<usize, u8>) -> Option<Inferior> {
-        // TODO: implement me!
-        let mut cmd = Command::new(target);
+        let mut inferior = Inferior::launch(target, args, &LaunchOptions::default()).ok()?;
+        inferior.install_breakpoints(breakpoints);
+        Some(inferior)
+    }
+
+    /// Like `new`, with the standard streams, environment and working directory of `options`.
+    pub fn launch(
+        target: &str,
+        args: &Vec<String>,
+        options: &LaunchOptions,
+    ) -> Result<Inferior, std::io::Error> {
+        let mut program = std::path::PathBuf::from(target);
+        if options.cwd.is_some() && target.contains('/') {
+            // a relative path would be looked up from the new working directory
+            program = std::fs::canonicalize(target)?;
+        }
+        let mut cmd = Command::new(program);
         cmd.args(args);
+        options.apply(&mut cmd)?;
+        // standard input may be redirected away from the terminal, so the child gets a
+        // descriptor of its own for it
+        let tty = match &options.tty {
+            Some(path) => Some(OpenOptions::new().read(true).write(true).open(path)?),
+            None => None,
+        };
+        let tty_fd = tty.as_ref().map(|tty| tty.as_raw_fd());
         unsafe {
-            cmd.pre_exec(child_traceme);
+            cmd.pre_exec(move || {
+                if let Some(fd) = tty_fd {
+                    // make the terminal the controlling terminal of a session of its own, so
+                    // that the program gets its job control and ctrl+c rather than deet's
+                    nix::unistd::setsid().map_err(|_| std::io::Error::last_os_error())?;
+                    if libc::ioctl(fd, libc::TIOCSCTTY, 0) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                child_traceme()
+            });
         }
         // When a process that has PTRACE_TRACEME enabled calls exec,
         // the operating system will load the specified program into the process,
         // and then (before the new program starts running) it will
         // pause the process using SIGTRAP. So at the time when inferior is returned,
         // child process is paused.
-        let child = cmd.spawn().ok()?;
-        let mut inferior = Inferior { child: child };
-        // install breakpoints
+        let child = cmd.spawn()?;
+        drop(tty);
+        let pid = Pid::from_raw(child.id() as i32);
+        let not_stopped = || std::io::Error::new(std::io::ErrorKind::Other, "the program did not stop at exec");
+        match waitpid(pid, None).map_err(|_| not_stopped())? {
+            WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {}
+            _ => return Err(not_stopped()),
+        }
+        ptrace::setoptions(pid, trace_options()).map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+        Ok(Inferior::attached(pid))
+    }
+
+    /// Writes every breakpoint into the freshly started program, saving the original bytes.
+    /// Returns the addresses that could not be written.
+    pub fn install_breakpoints(&mut self, breakpoints: &mut HashMap<usize, u8>) -> Vec<usize> {
+        let mut invalid = Vec::new();
         let bps = breakpoints.clone();
         for bp in bps.keys() {
-            match inferior.write_byte(*bp, 0xcc) {
+            match self.write_byte(*bp, 0xcc) {
                 Ok(ori_instr) => {breakpoints.insert(*bp, ori_instr);}
-                Err(_) => println!("Invalid breakpoint address {:#x}", bp),
+                Err(_) => invalid.push(*bp),
             }
         }
-        Some(inferior)
+        invalid
+    }
+
+    /// Wraps a single-threaded process that is already traced by us and stopped.
+    fn attached(pid: Pid) -> Inferior {
+        Inferior {
+            pid: pid,
+            threads: vec![Thread { id: 1, tid: pid }],
+            next_thread_id: 2,
+            current_tid: pid,
+            stopped_tid: pid,
+            pending_stops: HashSet::new(),
+            pending_signals: HashMap::new(),
+            trace_syscalls: false,
+            in_syscall: HashSet::new(),
+            event_options: ptrace::Options::empty(),
+            vfork_children: HashSet::new(),
+            traps: HashSet::new(),
+            running_alone: false,
+        }
+    }
+
+    /// The ptrace options the threads of this inferior are traced with.
+    fn options(&self) -> ptrace::Options {
+        trace_options() | self.event_options
+    }
+
+    /// Turns stopping when the inferior forks, calls execve or exits on or off. Forks and execs
+    /// are not reported at all while off; an exec then shows up as a SIGTRAP.
+    pub fn set_event_stops(&mut self, fork: bool, exec: bool, exit: bool) -> Result<(), nix::Error> {
+        let mut options = ptrace::Options::empty();
+        if fork {
+            options |= ptrace::Options::PTRACE_O_TRACEFORK | ptrace::Options::PTRACE_O_TRACEVFORK;
+        }
+        if exec {
+            options |= ptrace::Options::PTRACE_O_TRACEEXEC;
+        }
+        if exit {
+            options |= ptrace::Options::PTRACE_O_TRACEEXIT;
+        }
+        if options == self.event_options {
+            return Ok(());
+        }
+        self.event_options = options;
+        // new threads inherit the options of the thread that created them
+        for thread in &self.threads {
+            ptrace::setoptions(thread.tid, self.options())?;
+        }
+        Ok(())
+    }
+
+    /// Turns stopping at every system call entry and exit on or off, from the next resume on.
+    pub fn set_syscall_tracing(&mut self, on: bool) {
+        self.trace_syscalls = on;
+    }
+
+    /// Resumes a single thread, stopping at its next system call if syscall tracing is on.
+    fn resume_thread(&self, tid: Pid, signal: Option<signal::Signal>) -> Result<(), nix::Error> {
+        if self.trace_syscalls {
+            ptrace::syscall(tid, signal)
+        } else {
+            ptrace::cont(tid, signal)
+        }
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Returns the kernel thread id of the currently selected thread.
+    pub fn current_tid(&self) -> Pid {
+        self.current_tid
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+    /// Returns all live threads of this inferior, in creation order.
+    pub fn threads(&self) -> &Vec<Thread> {
+        &self.threads
+    }
+
+    /// Makes the thread with the given `info threads` number the current one, so that register
+    /// reads and backtraces refer to it. Returns None if there is no such thread.
+    pub fn select_thread(&mut self, id: usize) -> Option<Thread> {
+        let thread = *self.threads.iter().find(|t| t.id == id)?;
+        self.current_tid = thread.tid;
+        Some(thread)
+    }
+
+    /// Returns the thread record for `tid`, if it is a thread of this inferior.
+    pub fn thread_by_tid(&self, tid: Pid) -> Option<Thread> {
+        self.threads.iter().find(|t| t.tid == tid).copied()
+    }
+
+    fn add_thread(&mut self, tid: Pid) {
+        if self.thread_by_tid(tid).is_none() {
+            self.threads.push(Thread { id: self.next_thread_id, tid: tid });
+            self.next_thread_id += 1;
+        }
+    }
+
+    fn remove_thread(&mut self, tid: Pid) {
+        self.threads.retain(|t| t.tid != tid);
+        self.pending_stops.remove(&tid);
+        self.pending_signals.remove(&tid);
+        self.in_syscall.remove(&tid);
+        if self.current_tid == tid {
+            self.current_tid = self.pid();
+        }
+        if self.stopped_tid == tid {
+            self.stopped_tid = self.pid();
+        }
+    }
+
+    /// Calls waitpid on every thread of this inferior and returns a Status to indicate the state
+    /// of the process after the waitpid call. Thread creation and exit are handled here and never
+    /// reported. When a thread stops, every other thread is stopped as well (all-stop mode) and the
+    /// stopping thread becomes the current thread.
+    pub fn wait(&mut self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
+        if !STOP_SENT.load(Ordering::SeqCst) {
+            WAITING_PID.store(self.pid.as_raw(), Ordering::SeqCst);
+        }
+        let status = self.wait_for_stop(options);
+        WAITING_PID.store(0, Ordering::SeqCst);
+        if STOP_SENT.swap(false, Ordering::SeqCst) && signal_pending(self.pid, signal::Signal::SIGSTOP) {
+            // something else stopped the inferior before ctrl+c did; the SIGSTOP is swallowed
+            // once it arrives
+            self.pending_stops.insert(self.pid);
+        }
+        status
+    }
+
+    /// Waits for the next change of state of one of our threads. Only those are waited for: other
+    /// tracees of this process, like checkpoints or another inferior, are left to their owners.
+    fn wait_for_thread(&mut self, flags: WaitPidFlag) -> Result<WaitStatus, nix::Error> {
+        loop {
+            let tids: Vec<Pid> = self.threads.iter().map(|t| t.tid).collect();
+            if tids.len() == 1 {
+                return waitpid(tids[0], Some(flags));
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
-        })
+            // waitpid can only block on one thread, or on all children
+            for tid in tids {
+                match waitpid(tid, Some(flags | WaitPidFlag::WNOHANG)) {
+                    Ok(WaitStatus::StillAlive) => {}
+                    // the thread was taken down by an exec in another one
+                    Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) if tid != self.pid => self.remove_thread(tid),
+                    status => return status,
+                }
+            }
+            if flags.contains(WaitPidFlag::WNOHANG) {
+                return Ok(WaitStatus::StillAlive);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn wait_for_stop(&mut self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
+        let flags = options.unwrap_or(WaitPidFlag::empty()) | WaitPidFlag::__WALL;
+        loop {
+            match self.wait_for_thread(flags)? {
+                WaitStatus::Exited(pid, exit_code) => {
+                    if pid == self.pid() {
+                        return Ok(Status::Exited(exit_code));
+                    }
+                    self.remove_thread(pid);
+                }
+                WaitStatus::Signaled(pid, signal, _core_dumped) => {
+                    if pid == self.pid() {
+                        return Ok(Status::Signaled(signal));
+                    }
+                    self.remove_thread(pid);
+                }
+                WaitStatus::PtraceEvent(pid, _, event) => {
+                    if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 {
+                        self.track_new_thread(pid)?;
+                    } else if let Some(status) = self.event_status(pid, event)? {
+                        self.add_thread(pid);
+                        self.current_tid = pid;
+                        self.stopped_tid = pid;
+                        self.stop_other_threads()?;
+                        return Ok(status);
+                    }
+                    self.resume_thread(pid, None)?;
+                }
+                WaitStatus::PtraceSyscall(pid) => {
+                    self.add_thread(pid);
+                    self.current_tid = pid;
+                    self.stopped_tid = pid;
+                    let entry = self.toggle_in_syscall(pid);
+                    self.stop_other_threads()?;
+                    let regs = ptrace::getregs(pid)?;
+                    return Ok(if entry {
+                        Status::SyscallEntry(regs.orig_rax, regs.rip as usize)
+                    } else {
+                        Status::SyscallExit(regs.orig_rax, regs.rip as usize)
+                    });
+                }
+                WaitStatus::Stopped(pid, signal::Signal::SIGSTOP)
+                    if pid == self.pid
+                        && !self.pending_stops.contains(&pid)
+                        && STOP_SENT.swap(false, Ordering::SeqCst) =>
+                {
+                    // stopped by ctrl+c
+                    INTERRUPT_REQUESTED.store(false, Ordering::SeqCst);
+                    if signal_pending(pid, signal::Signal::SIGINT) {
+                        // the terminal sent its SIGINT to the inferior too; that one gets
+                        // reported once it arrives
+                        self.resume_thread(pid, None)?;
+                        continue;
+                    }
+                    self.current_tid = pid;
+                    self.stopped_tid = pid;
+                    self.stop_other_threads()?;
+                    let regs = ptrace::getregs(pid)?;
+                    return Ok(Status::Stopped(signal::Signal::SIGINT, regs.rip as usize));
+                }
+                WaitStatus::Stopped(pid, signal::Signal::SIGSTOP)
+                    if self.pending_stops.remove(&pid) || self.thread_by_tid(pid).is_none() =>
+                {
+                    // either our own SIGSTOP arriving late, or a new thread reporting in before
+                    // its parent's clone event did
+                    self.add_thread(pid);
+                    self.resume_thread(pid, None)?;
+                }
+                WaitStatus::Stopped(pid, signal) => {
+                    self.add_thread(pid);
+                    self.current_tid = pid;
+                    self.stopped_tid = pid;
+                    self.stop_other_threads()?;
+                    let regs = ptrace::getregs(pid)?;
+                    return Ok(Status::Stopped(signal, regs.rip as usize));
+                }
+                // nothing else can be reported without further ptrace options
+                _ => {}
+            }
+        }
+    }
+
+    /// The status to report for a fork, exec or exit event stop of thread `tid`, or None if the
+    /// thread should just be resumed.
+    fn event_status(&mut self, tid: Pid, event: i32) -> Result<Option<Status>, nix::Error> {
+        let rip = ptrace::getregs(tid)?.rip as usize;
+        let vfork = event == ptrace::Event::PTRACE_EVENT_VFORK as i32;
+        if vfork || event == ptrace::Event::PTRACE_EVENT_FORK as i32 {
+            let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+            wait_for_new_child(child)?;
+            if vfork {
+                self.vfork_children.insert(child);
+            }
+            Ok(Some(Status::Forked(child, rip)))
+        } else if event == ptrace::Event::PTRACE_EVENT_EXEC as i32 {
+            // execve kills every other thread, and the thread that called it takes over the
+            // thread group id
+            let pid = self.pid;
+            self.threads.retain(|t| t.tid == pid);
+            self.pending_stops.clear();
+            self.pending_signals.clear();
+            self.in_syscall.clear();
+            self.traps.clear();
+            Ok(Some(Status::Exec(rip)))
+        } else if event == ptrace::Event::PTRACE_EVENT_EXIT as i32 && tid == self.pid {
+            let status = ptrace::getevent(tid)? as libc::c_int;
+            // deaths by a signal were already reported as a stop for that signal
+            if libc::WIFEXITED(status) {
+                Ok(Some(Status::Exiting(libc::WEXITSTATUS(status), rip)))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Lets a child reported by `Status::Forked` run on its own. A forked child inherited our
+    /// breakpoints, which are taken out of its memory first, since nobody would catch their traps.
+    /// A vforked child shares our memory, so it keeps them.
+    pub fn detach_child(&mut self, child: Pid, breakpoints: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+        if !self.vfork_children.remove(&child) {
+            let mut child_inferior = Inferior::attached(child);
+            for (addr, orig) in breakpoints {
+                child_inferior.write_byte(*addr, *orig)?;
+            }
+        }
+        ptrace::detach(child, None)
+    }
+
+    /// Records that `tid` made a syscall stop, and returns whether it is entering a system call
+    /// (rather than returning from one). ptrace does not tell the two apart.
+    fn toggle_in_syscall(&mut self, tid: Pid) -> bool {
+        let entry = !self.in_syscall.remove(&tid);
+        if entry {
+            self.in_syscall.insert(tid);
+        }
+        entry
+    }
+
+    /// Registers the thread that `parent` just created. The new thread starts with a SIGSTOP
+    /// pending, which is swallowed when it arrives.
+    fn track_new_thread(&mut self, parent: Pid) -> Result<(), nix::Error> {
+        let tid = Pid::from_raw(ptrace::getevent(parent)? as i32);
+        if self.thread_by_tid(tid).is_none() {
+            self.add_thread(tid);
+            self.pending_stops.insert(tid);
+        }
+        Ok(())
+    }
+
+    /// Stops every thread except the one that just stopped with SIGSTOP and waits until each has stopped.
+    /// A thread that stops for some other reason first keeps its SIGSTOP pending; if it had hit a
+    /// breakpoint, its instruction pointer is rewound so that it hits the breakpoint again once
+    /// resumed, and any other signal is re-delivered on the next resume.
+    fn stop_other_threads(&mut self) -> Result<(), nix::Error> {
+        if self.running_alone {
+            // they were never resumed
+            return Ok(());
+        }
+        let others: Vec<Pid> = self
+            .threads
+            .iter()
+            .map(|t| t.tid)
+            .filter(|tid| *tid != self.stopped_tid)
+            .collect();
+        for tid in others {
+            if tgkill(self.pid(), tid, signal::Signal::SIGSTOP).is_err() {
+                // the thread is already gone; its exit is reaped below
+                self.pending_stops.remove(&tid);
+            }
+            loop {
+                match waitpid(tid, Some(WaitPidFlag::__WALL)) {
+                    Ok(WaitStatus::Stopped(_, signal::Signal::SIGSTOP)) => break,
+                    Ok(WaitStatus::Stopped(_, signal::Signal::SIGTRAP)) => {
+                        let mut regs = ptrace::getregs(tid)?;
+                        if self.traps.contains(&(regs.rip as usize).wrapping_sub(1)) {
+                            regs.rip -= 1;
+                            ptrace::setregs(tid, regs)?;
+                        }
+                        self.pending_stops.insert(tid);
+                        break;
+                    }
+                    Ok(WaitStatus::Stopped(_, signal)) => {
+                        self.pending_signals.insert(tid, signal);
+                        self.pending_stops.insert(tid);
+                        break;
+                    }
+                    Ok(WaitStatus::PtraceEvent(_, _, event)) => {
+                        if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 {
+                            self.track_new_thread(tid)?;
+                        }
+                        self.pending_stops.insert(tid);
+                        break;
+                    }
+                    Ok(WaitStatus::PtraceSyscall(_)) => {
+                        // not reported, but the next syscall stop of the thread is the other kind
+                        self.toggle_in_syscall(tid);
+                        self.pending_stops.insert(tid);
+                        break;
+                    }
+                    Ok(WaitStatus::Exited(..)) | Ok(WaitStatus::Signaled(..)) | Err(_) => {
+                        self.remove_thread(tid);
+                        break;
+                    }
+                    Ok(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resumes every thread of the inferior. The thread that reported the last stop gets `signal`;
+    /// the others, and that thread too if `signal` is None, get any signal that was held back
+    /// while they were being stopped.
+    fn resume_all(&mut self, signal: Option<signal::Signal>) -> Result<(), nix::Error> {
+        INTERRUPT_REQUESTED.store(false, Ordering::SeqCst);
+        if !self.trace_syscalls {
+            // without PTRACE_SYSCALL, system calls run to completion unseen
+            self.in_syscall.clear();
+        }
+        let tids: Vec<Pid> = self.threads.iter().map(|t| t.tid).collect();
+        for tid in tids {
+            let sig = if tid == self.stopped_tid {
+                signal.or_else(|| self.pending_signals.remove(&tid))
+            } else {
+                self.pending_signals.remove(&tid)
+            };
+            self.resume_thread(tid, sig)?;
+        }
+        Ok(())
     }
 
     // wake up the paused inferior process
@@ -91,39 +756,278 @@ impl Inferior {
     // (1) inferior process paused by breakpoints
     // (2) inferior process paused by other signals (e.g. ctrl + c)
     pub fn continue_run(&mut self, signal: Option<signal::Signal>, breakpoints: &HashMap<usize, u8>) -> Result<Status, nix::Error> {
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        // check if inferior stopped at a breakpoint
-        if let Some(ori_instr) = breakpoints.get(&(rip - 1)) {
-            println!("stopped at a breakpoint");
-            // restore the first byte of the instruction we replaced
-            self.write_byte(rip - 1, *ori_instr).unwrap();
-            // set %rip = %rip - 1 to rewind the instruction pointer
-            regs.rip = (rip - 1) as u64;
-            ptrace::setregs(self.pid(), regs).unwrap();
-            // go to the next instruction
-            ptrace::step(self.pid(), None).unwrap();
-            // wait for inferior to stop due to SIGTRAP, just return if the inferior terminates here
-            match self.wait(None).unwrap() {
-                Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)),
-                Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
-                Status::Stopped(_, _) => {
-                    // restore 0xcc in the breakpoint location
-                    self.write_byte(rip - 1, 0xcc).unwrap();
-                }
+        let rip = ptrace::getregs(self.stopped_tid)?.rip as usize;
+        // check if inferior stopped at a breakpoint; %rip is 0 after a call through a null pointer
+        if breakpoints.contains_key(&rip.wrapping_sub(1)) {
+            self.rewind_breakpoint(breakpoints)?;
+            // go to the next instruction; only this thread runs, the others stay stopped.
+            // Just return if the inferior terminates here
+            match self.step_instruction(breakpoints)? {
+                Status::Stopped(_, _) => {}
+                other => return Ok(other),
             }
         }
         // resume normal execution
-        ptrace::cont(self.pid(), signal)?;
+        self.resume_all(signal)?;
         // wait for inferior to stop or terminate
         self.wait(None)
     }
 
-    // kill the inferior, assume that the inferior is still alive
-    pub fn kill(&mut self) {
-        self.child.kill().unwrap();
-        self.wait(None).unwrap();
-        println!("Killing running inferior (pid {})", self.pid())
+    /// Resumes only thread `tid` and waits for it to stop or for the inferior to terminate. The
+    /// other threads stay stopped where they are; one that is past a breakpoint stays that way.
+    fn resume_alone(&mut self, tid: Pid) -> Result<Status, nix::Error> {
+        INTERRUPT_REQUESTED.store(false, Ordering::SeqCst);
+        if !self.trace_syscalls {
+            self.in_syscall.remove(&tid);
+        }
+        self.resume_thread(tid, None)?;
+        self.running_alone = true;
+        let status = self.wait(None);
+        self.running_alone = false;
+        status
+    }
+
+    /// Resumes every thread and waits for the inferior to stop or terminate. Unlike continue_run,
+    /// this does not step over a breakpoint the inferior may be stopped at.
+    pub fn resume(&mut self, signal: Option<signal::Signal>) -> Result<Status, nix::Error> {
+        self.resume_all(signal)?;
+        self.wait(None)
+    }
+
+    /// If the thread that reported the last stop has just executed one of our breakpoints, moves
+    /// its instruction pointer back onto the breakpoint's address so the original instruction can
+    /// be executed.
+    pub fn rewind_breakpoint(&mut self, breakpoints: &HashMap<usize, u8>) -> Result<(), nix::Error> {
+        let mut regs = ptrace::getregs(self.stopped_tid)?;
+        if breakpoints.contains_key(&(regs.rip as usize).wrapping_sub(1)) {
+            regs.rip -= 1;
+            ptrace::setregs(self.stopped_tid, regs)?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single instruction in the thread that reported the last stop. If a breakpoint
+    /// sits on that instruction, the original byte is put back for the duration of the step.
+    pub fn step_instruction(&mut self, breakpoints: &HashMap<usize, u8>) -> Result<Status, nix::Error> {
+        let tid = self.stopped_tid;
+        let rip = ptrace::getregs(tid)?.rip as usize;
+        let ori_instr = breakpoints.get(&rip).copied();
+        if let Some(ori_instr) = ori_instr {
+            // restore the first byte of the instruction we replaced
+            self.write_byte(rip, ori_instr)?;
+        }
+        // a system call the thread is stopped in completes without another syscall stop
+        self.in_syscall.remove(&tid);
+        ptrace::step(tid, None)?;
+        let status = match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::Exited(pid, exit_code) if pid == self.pid() => {
+                return Ok(Status::Exited(exit_code))
+            }
+            WaitStatus::Signaled(pid, signal, _) if pid == self.pid() => {
+                return Ok(Status::Signaled(signal))
+            }
+            WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                // this thread is gone, so there is nobody left to report the step
+                self.remove_thread(tid);
+                return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+            }
+            WaitStatus::PtraceEvent(_, _, event) if event == ptrace::Event::PTRACE_EVENT_EXEC as i32 => {
+                // the old program is gone, and with it the breakpoint we took out
+                let status = self.event_status(tid, event)?;
+                return Ok(status.unwrap_or(Status::Stopped(signal::Signal::SIGTRAP, rip)));
+            }
+            WaitStatus::PtraceEvent(_, _, event) => {
+                if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 {
+                    self.track_new_thread(tid)?;
+                }
+                Status::Stopped(signal::Signal::SIGTRAP, ptrace::getregs(tid)?.rip as usize)
+            }
+            WaitStatus::Stopped(_, signal) => {
+                Status::Stopped(signal, ptrace::getregs(tid)?.rip as usize)
+            }
+            _ => Status::Stopped(signal::Signal::SIGTRAP, ptrace::getregs(tid)?.rip as usize),
+        };
+        if ori_instr.is_some() {
+            // restore 0xcc in the breakpoint location
+            self.write_byte(rip, 0xcc)?;
+        }
+        Ok(status)
+    }
+
+    /// Runs the thread that reported the last stop until it reaches a different source line in the
+    /// same function, or in its caller once it returns. Calls are run through rather than stepped
+    /// into. Stops early at breakpoints, which are reported as if they had just been hit.
+    pub fn step_over_line(&mut self, debug_data: &DwarfData, breakpoints: &HashMap<usize, u8>) -> Result<Status, nix::Error> {
+        self.step_lines(debug_data, breakpoints, false)
+    }
+
+    /// Like step_over_line, but never stops at a line of the same file that is not past the
+    /// starting one, so that the backward jump at the end of a loop runs the loop to completion.
+    /// Still stops once the function returns.
+    pub fn step_until_line(&mut self, debug_data: &DwarfData, breakpoints: &HashMap<usize, u8>) -> Result<Status, nix::Error> {
+        self.step_lines(debug_data, breakpoints, true)
+    }
+
+    fn step_lines(&mut self, debug_data: &DwarfData, breakpoints: &HashMap<usize, u8>, forward_only: bool) -> Result<Status, nix::Error> {
+        self.rewind_breakpoint(breakpoints)?;
+        // forget a ctrl+c pressed before we started
+        interrupt_requested();
+        let tid = self.stopped_tid;
+        let line_of = |addr: usize| debug_data.get_line_from_addr(addr).map(|l| (l.file, l.number));
+        let start_line = line_of(ptrace::getregs(tid)?.rip as usize);
+        // how many calls deep we are relative to the starting function
+        let mut depth: isize = 0;
+        let mut first = true;
+        loop {
+            let mut regs = ptrace::getregs(tid)?;
+            let rip = regs.rip as usize;
+            if !first {
+                if breakpoints.contains_key(&rip) {
+                    regs.rip += 1;
+                    ptrace::setregs(tid, regs)?;
+                    return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip + 1));
+                }
+                let line = line_of(rip);
+                let backwards = match (&line, &start_line) {
+                    (Some((file, number)), Some((start_file, start_number))) => {
+                        forward_only && depth == 0 && file == start_file && number <= start_number
+                    }
+                    _ => false,
+                };
+                if depth <= 0 && line.is_some() && line != start_line && !backwards {
+                    return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+                }
+            }
+            first = false;
+            if interrupt_requested() {
+                return Ok(Status::Stopped(signal::Signal::SIGINT, rip));
+            }
+            match self.step_instruction(breakpoints)? {
+                Status::Stopped(signal::Signal::SIGTRAP, _) => {}
+                other => return Ok(other),
+            }
+            // a call pushes the address of the next instruction and jumps elsewhere; a ret pops
+            // the address it jumps to
+            let after = ptrace::getregs(tid)?;
+            if after.rsp == regs.rsp.wrapping_sub(8) {
+                let ret = ptrace::read(tid, after.rsp as ptrace::AddressType)? as u64;
+                if ret > regs.rip && ret <= regs.rip + 15 && after.rip != ret {
+                    depth += 1;
+                }
+            } else if after.rsp == regs.rsp + 8 {
+                let target = ptrace::read(tid, regs.rsp as ptrace::AddressType)? as u64;
+                if target == after.rip {
+                    depth -= 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the registers of the thread that reported the last stop.
+    pub fn registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.stopped_tid)
+    }
+
+    /// Overwrites the registers of the thread that reported the last stop.
+    pub fn set_registers(&self, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.stopped_tid, regs)
+    }
+
+    /// Reads `len` bytes of inferior memory starting at `addr`. This goes through /proc/<pid>/mem,
+    /// which is much cheaper than one ptrace call per word for larger ranges.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let to_nix = |err: std::io::Error| {
+            nix::Error::Sys(nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(libc::EIO)))
+        };
+        let mem = std::fs::File::open(format!("/proc/{}/mem", self.pid())).map_err(to_nix)?;
+        let mut buf = vec![0; len];
+        mem.read_exact_at(&mut buf, addr as u64).map_err(to_nix)?;
+        Ok(buf)
+    }
+
+    /// Writes a whole (aligned) word of inferior memory.
+    pub fn write_word(&mut self, addr: usize, val: u64) -> Result<(), nix::Error> {
+        ptrace::write(
+            self.current_tid,
+            addr as ptrace::AddressType,
+            val as *mut std::ffi::c_void,
+        )
+    }
+
+    /// Kills the inferior without saying so, and reaps all of its threads. The thread group
+    /// leader is reaped last, since its exit is only reported once every other thread is gone.
+    pub fn terminate(&mut self) {
+        signal::kill(self.pid, signal::Signal::SIGKILL).unwrap();
+        let mut tids: Vec<Pid> = self.threads.iter().map(|t| t.tid).filter(|tid| *tid != self.pid).collect();
+        tids.push(self.pid);
+        for tid in tids {
+            loop {
+                match waitpid(tid, Some(WaitPidFlag::__WALL)) {
+                    Ok(WaitStatus::Exited(..)) | Ok(WaitStatus::Signaled(..)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        }
+        self.threads.clear();
+    }
+
+    /// Makes a copy of the stopped inferior by having it execute a fork syscall, and returns the
+    /// child as a new Inferior, stopped in exactly the same state. The syscall instruction is
+    /// written over the current instruction for the duration of the step and then put back in both
+    /// processes. Only the thread that reported the last stop is copied, as with any fork.
+    pub fn fork(&mut self) -> Result<Inferior, nix::Error> {
+        let tid = self.stopped_tid;
+        let saved_regs = ptrace::getregs(tid)?;
+        let pc = saved_regs.rip as usize;
+        // the `syscall` instruction is 0f 05
+        let orig_bytes = [self.write_byte(pc, 0x0f)?, self.write_byte(pc + 1, 0x05)?];
+        let mut regs = saved_regs;
+        regs.rax = libc::SYS_fork as u64;
+        // keep the kernel from treating this as the restart of an interrupted syscall
+        regs.orig_rax = u64::max_value();
+        ptrace::setregs(tid, regs)?;
+        // have the kernel attach us to the child and stop it before it runs
+        ptrace::setoptions(self.pid, self.options() | ptrace::Options::PTRACE_O_TRACEFORK)?;
+
+        let result = self.step_through_fork(tid);
+
+        ptrace::setoptions(self.pid, self.options())?;
+        self.write_byte(pc + 1, orig_bytes[1])?;
+        self.write_byte(pc, orig_bytes[0])?;
+        ptrace::setregs(tid, saved_regs)?;
+
+        let mut child = Inferior::attached(result?);
+        ptrace::setoptions(child.pid, trace_options())?;
+        child.write_byte(pc + 1, orig_bytes[1])?;
+        child.write_byte(pc, orig_bytes[0])?;
+        ptrace::setregs(child.pid, saved_regs)?;
+        Ok(child)
+    }
+
+    /// Single-steps `tid` over the injected fork syscall and returns the (stopped) child's pid.
+    fn step_through_fork(&mut self, tid: Pid) -> Result<Pid, nix::Error> {
+        let mut child = None;
+        ptrace::step(tid, None)?;
+        loop {
+            match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::PtraceEvent(_, _, event) if event == ptrace::Event::PTRACE_EVENT_FORK as i32 => {
+                    child = Some(Pid::from_raw(ptrace::getevent(tid)? as i32));
+                    // finish the syscall
+                    ptrace::step(tid, None)?;
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => break,
+                WaitStatus::Stopped(_, signal) => {
+                    // a signal that arrived in the meantime; it is delivered when the program
+                    // runs again
+                    self.pending_signals.insert(tid, signal);
+                    ptrace::step(tid, None)?;
+                }
+                _ => return Err(nix::Error::Sys(nix::errno::Errno::ESRCH)),
+            }
+        }
+        let child = child.ok_or(nix::Error::Sys(nix::errno::Errno::EAGAIN))?;
+        wait_for_new_child(child)?;
+        Ok(child)
     }
 
 
@@ -133,45 +1037,386 @@ impl Inferior {
     //     return regs.rip as usize;
     // }
 
+    // collect the stack frames of this inferior process, innermost first
+    // (the currently selected thread, see `thread N`)
+    pub fn backtrace(&self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let regs = ptrace::getregs(self.current_tid)?;
+        let mut frame = self.frame_at(debug_data, 0, regs.rip as usize, regs.rsp as usize, regs.rbp as usize);
+        let mut frames = Vec::new();
+        loop {
+            // one frame per inlined call, innermost first
+            for inline_depth in 0..=debug_data.get_inlined_calls(frame.pc).len() {
+                frames.push(Frame { inline_depth, ..frame });
+            }
+            match debug_data.get_function_containing(frame.pc) {
+                Some(func) if func.name != "main" => {}
+                _ => break,
+            }
+            frame = self.caller_frame(debug_data, &frame)?;
+        }
+        Ok(frames)
+    }
+
+    fn frame_at(&self, debug_data: &DwarfData, level: usize, rip: usize, rsp: usize, rbp: usize) -> Frame {
+        let mut pc = if level == 0 { rip } else { rip.wrapping_sub(1) };
+        // a thread stopped by a breakpoint is one byte past it
+        if level == 0 && self.traps.contains(&pc.wrapping_sub(1)) {
+            pc -= 1;
+        }
+        let cfa = match debug_data.get_frame_rule(pc) {
+            Some(rule) if rule.cfa_register == dwarf_expr::RSP => (rsp as i64 + rule.cfa_offset) as usize,
+            Some(rule) if rule.cfa_register == dwarf_expr::RBP => (rbp as i64 + rule.cfa_offset) as usize,
+            // without call frame information, assume gcc's frame pointer prologue
+            _ => match self.prologue_return_address(debug_data, pc) {
+                Some(offset) if level == 0 => rsp + offset + 8,
+                _ => rbp + 16,
+            },
+        };
+        Frame {
+            rip,
+            rbp,
+            rsp,
+            cfa,
+            pc,
+            level,
+            inline_depth: 0,
+        }
+    }
+
+    /// Returns the frame that called `frame`. The return address is just below the canonical
+    /// frame address.
+    pub fn caller_frame(&self, debug_data: &DwarfData, frame: &Frame) -> Result<Frame, nix::Error> {
+        let read_word = |addr: usize| -> Result<usize, nix::Error> {
+            Ok(ptrace::read(self.current_tid, addr as ptrace::AddressType)? as usize)
+        };
+        let rip = read_word(frame.cfa - 8)?;
+        let rbp = match debug_data.get_frame_rule(frame.pc) {
+            Some(rule) => match rule.rbp_offset {
+                Some(offset) => read_word((frame.cfa as i64 + offset) as usize)?,
+                None => frame.rbp,
+            },
+            // the prologue saves %rbp right below the return address
+            None if frame.cfa == frame.rbp + 16 => read_word(frame.rbp)?,
+            None => frame.rbp,
+        };
+        Ok(self.frame_at(debug_data, frame.level + 1, rip, frame.cfa, rbp))
+    }
+
+    /// If `pc` is in the prologue of its function, before `push %rbp; mov %rsp,%rbp` has
+    /// completed, returns the offset from %rsp of the return address. Assumes gcc's frame pointer
+    /// prologue, optionally preceded by endbr64.
+    fn prologue_return_address(&self, debug_data: &DwarfData, pc: usize) -> Option<usize> {
+        let func = debug_data.get_function_containing(pc)?;
+        let code = self.read_memory(func.address, 4).ok()?;
+        // the first byte may be a breakpoint
+        let push = if code[1..] == [0x0f, 0x1e, 0xfa] { func.address + 4 } else { func.address };
+        if pc == func.address || pc == push {
+            Some(0)
+        } else if pc == push + 1 {
+            Some(8)
+        } else {
+            None
+        }
+    }
+
+    /// The address in the program's own code that the current thread is running, or will
+    /// return to: for a thread stopped in a library function such as `write`, its call site.
+    /// Libraries have no debugging information to unwind with, so this takes the nearest word on
+    /// the stack that points just past a call instruction in code with line information.
+    pub fn source_caller(&self, debug_data: &DwarfData) -> Option<usize> {
+        let regs = ptrace::getregs(self.current_tid).ok()?;
+        if debug_data.get_line_from_addr(regs.rip as usize).is_some() {
+            return Some(regs.rip as usize);
+        }
+        (0..512).find_map(|i| {
+            let word = ptrace::read(self.current_tid, (regs.rsp + i * 8) as ptrace::AddressType).ok()? as usize;
+            debug_data.get_line_from_addr(word.checked_sub(1)?)?;
+            if self.follows_call(word) {
+                Some(word - 1)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether the instruction right before `addr` looks like a call: `e8 rel32`, or one of the
+    /// common encodings of an indirect `ff /2` call.
+    fn follows_call(&self, addr: usize) -> bool {
+        let code = match addr.checked_sub(7).and_then(|start| self.read_memory(start, 7).ok()) {
+            Some(code) => code,
+            None => return false,
+        };
+        code[2] == 0xe8 || [0, 1, 4, 5].iter().any(|&i| code[i] == 0xff && (code[i + 1] >> 3) & 7 == 2)
+    }
+
     // print backtrace of this inferior process
     pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip as usize;
-        let mut rbp = regs.rbp as usize;
-        loop {
-            let _line = debug_data.get_line_from_addr(rip);
-            let _func = debug_data.get_function_from_addr(rip);
+        for frame in self.backtrace(debug_data)? {
+            let _line = debug_data.get_frame_line(frame.pc, frame.inline_depth);
+            let _func = debug_data.get_frame_function(frame.pc, frame.inline_depth);
             match (&_line, &_func) {
                 (None, None) => println!("unknown func (source file not found)"),
                 (Some(line), None) => println!("unknown func ({})", line),
                 (None, Some(func)) => println!("{} (source file not found)", func),
                 (Some(line), Some(func)) => println!("{} ({})", func, line),
             }
-            if let Some(func) = _func {
-                if func == "main" {
-                    break;
-                }
-            } else {
-                break;
+        }
+        Ok(())
+    }
+
+    /// Reads the raw bytes of a variable, or None if it is optimized out at this point of the
+    /// program. Locals are found relative to `frame`; globals do not need one.
+    pub fn read_variable(
+        &self,
+        debug_data: &DwarfData,
+        var: &Variable,
+        frame: Option<&Frame>,
+    ) -> Result<Option<Vec<u8>>, dwarf_expr::Error> {
+        let context = FrameContext {
+            inferior: self,
+            debug_data,
+            frame,
+        };
+        match context.read(var) {
+            Err(dwarf_expr::Error::OptimizedOut) => Ok(None),
+            result => result,
+        }
+    }
+
+    /// Returns the floating point and vector registers of the current thread.
+    pub fn fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        fp_registers_of(self.current_tid)
+    }
+
+    /// Overwrites the floating point and vector registers of the current thread.
+    pub fn set_fp_registers(&self, fpregs: libc::user_fpregs_struct) -> Result<(), nix::Error> {
+        set_fp_registers_of(self.current_tid, fpregs)
+    }
+
+    /// The entry point of the program, from the auxiliary vector the kernel gave it.
+    fn entry_point(&self) -> Result<usize, nix::Error> {
+        let auxv = std::fs::read(format!("/proc/{}/auxv", self.pid()))
+            .map_err(|err| nix::Error::Sys(nix::errno::Errno::from_i32(err.raw_os_error().unwrap_or(libc::EIO))))?;
+        auxv.chunks_exact(16)
+            .map(|pair| (values::read_uint(&pair[..8]), values::read_uint(&pair[8..])))
+            .find(|(key, _)| *key == libc::AT_ENTRY)
+            .map(|(_, value)| value as usize)
+            .ok_or(nix::Error::Sys(nix::errno::Errno::ENOENT))
+    }
+
+    /// Calls the function at `addr` with up to six integer or pointer `args`, the way a call
+    /// instruction in the current thread would. The return address pushed for it is the program's
+    /// entry point, which is long done running by now, with a breakpoint on it to catch the
+    /// return. Only the calling thread runs: another one may be sitting just past a breakpoint,
+    /// and must not run on from the middle of an instruction. However the call ends, the calling
+    /// thread's registers, including the vector registers, are restored afterwards.
+    pub fn call_function(&mut self, addr: usize, args: &[u64], breakpoints: &HashMap<usize, u8>) -> Result<CallResult, nix::Error> {
+        let tid = self.current_tid;
+        let stopped_tid = self.stopped_tid;
+        let saved = ptrace::getregs(tid)?;
+        let saved_fp = fp_registers_of(tid)?;
+        let return_addr = self.entry_point()?;
+        let mut regs = saved;
+        let mut argument_registers = [&mut regs.rdi, &mut regs.rsi, &mut regs.rdx, &mut regs.rcx, &mut regs.r8, &mut regs.r9];
+        for (register, arg) in argument_registers.iter_mut().zip(args) {
+            **register = *arg;
+        }
+        // no vector registers used, for variadic functions
+        regs.rax = 0;
+        // keep a system call the thread was stopped in from being restarted
+        regs.orig_rax = u64::MAX;
+        // below the red zone, aligned the way the callee expects right after a call
+        regs.rsp = ((saved.rsp - 128) & !0xf) - 8;
+        regs.rip = addr as u64;
+        self.write_word(regs.rsp as usize, return_addr as u64)?;
+        let original = if breakpoints.contains_key(&return_addr) {
+            None
+        } else {
+            Some(self.write_byte(return_addr, 0xcc)?)
+        };
+        ptrace::setregs(tid, regs)?;
+        let tracing = self.trace_syscalls;
+        self.trace_syscalls = false;
+        let status = self.resume_alone(tid);
+        self.trace_syscalls = tracing;
+        let status = status?;
+        match status {
+            // nothing left to restore
+            Status::Exited(_) | Status::Signaled(_) => return Ok(CallResult::Interrupted(status)),
+            _ => {}
+        }
+        if let Some(original) = original {
+            self.write_byte(return_addr, original)?;
+        }
+        // every thread is stopped again, whichever one reported
+        let result = match status {
+            Status::Stopped(signal::Signal::SIGTRAP, rip) if self.stopped_tid == tid && rip == return_addr + 1 => {
+                CallResult::Returned(ptrace::getregs(tid)?, fp_registers_of(tid)?)
+            }
+            status => CallResult::Interrupted(status),
+        };
+        ptrace::setregs(tid, saved)?;
+        set_fp_registers_of(tid, saved_fp)?;
+        if self.stopped_tid == tid {
+            // the stop was the call's own, and is over: the thread that stopped before it is
+            // the one to step off its breakpoint on the next continue
+            self.stopped_tid = stopped_tid;
+            self.current_tid = tid;
+        }
+        Ok(result)
+    }
+
+    // print one line per thread, marking the current one with '*'
+    pub fn print_threads(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        for thread in &self.threads {
+            let rip = ptrace::getregs(thread.tid)?.rip as usize;
+            let marker = if thread.tid == self.current_tid { "*" } else { " " };
+            let _line = debug_data.get_line_from_addr(rip);
+            let _func = debug_data.get_function_from_addr(rip);
+            match (&_line, &_func) {
+                (Some(line), Some(func)) => println!(
+                    "{} {} Thread {} {:#x} in {} ({})",
+                    marker, thread.id, thread.tid, rip, func, line
+                ),
+                _ => println!("{} {} Thread {} {:#x}", marker, thread.id, thread.tid, rip),
             }
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
         }
         Ok(())
     }
 
+    fn read_byte(&self, addr: usize) -> Result<u8, nix::Error> {
+        let aligned_addr = align_addr_to_word(addr);
+        let byte_offset = addr - aligned_addr;
+        let word = ptrace::read(self.current_tid, aligned_addr as ptrace::AddressType)? as u64;
+        Ok(((word >> 8 * byte_offset) & 0xff) as u8)
+    }
+
     pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
         let aligned_addr = align_addr_to_word(addr);
         let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
+        let word = ptrace::read(self.current_tid, aligned_addr as ptrace::AddressType)? as u64;
         let orig_byte = (word >> 8 * byte_offset) & 0xff;
         let masked_word = word & !(0xff << 8 * byte_offset);
         let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
         ptrace::write(
-            self.pid(),
+            self.current_tid,
             aligned_addr as ptrace::AddressType,
             updated_word as *mut std::ffi::c_void,
         )?;
+        if val == 0xcc {
+            self.traps.insert(addr);
+        } else {
+            self.traps.remove(&addr);
+        }
         Ok(orig_byte as u8)
     }
-}
\ No newline at end of file
+}
+
+/// The registers and memory of a stack frame, for evaluating DWARF expressions.
+struct FrameContext<'a> {
+    inferior: &'a Inferior,
+    debug_data: &'a DwarfData,
+    // None for global variables
+    frame: Option<&'a Frame>,
+}
+
+impl<'a> FrameContext<'a> {
+    fn read(&self, var: &Variable) -> Result<Option<Vec<u8>>, dwarf_expr::Error> {
+        let size = var.entity_type.size;
+        match &var.location {
+            Location::Address(addr) => Ok(Some(self.inferior.read_memory(*addr, size)?)),
+            Location::FramePointerOffset(offset) => {
+                let addr = (self.frame_base()? as isize + offset) as usize;
+                Ok(Some(self.inferior.read_memory(addr, size)?))
+            }
+            Location::Value(bytes) => Ok(Some(bytes.clone())),
+            Location::OptimizedOut => Ok(None),
+            location => match location.expression_at(self.frame.map_or(0, |frame| frame.pc)) {
+                Some(expr) => dwarf_expr::read_pieces(&dwarf_expr::evaluate(expr, self)?, size, self),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+impl<'a> dwarf_expr::Context for FrameContext<'a> {
+    fn register(&self, reg: u16) -> Result<u64, dwarf_expr::Error> {
+        let frame = self.frame.ok_or(dwarf_expr::Error::Unavailable)?;
+        if frame.level == 0 && reg <= dwarf_expr::RIP {
+            let regs = ptrace::getregs(self.inferior.current_tid)?;
+            let values = [
+                regs.rax, regs.rdx, regs.rcx, regs.rbx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+                regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+            ];
+            return Ok(values[reg as usize]);
+        }
+        // outer frames only know the registers the unwinder recovers
+        match reg {
+            dwarf_expr::RBP => Ok(frame.rbp as u64),
+            dwarf_expr::RSP => Ok(frame.rsp as u64),
+            dwarf_expr::RIP => Ok(frame.rip as u64),
+            _ => Err(dwarf_expr::Error::Unavailable),
+        }
+    }
+
+    fn register_bytes(&self, reg: u16) -> Result<Vec<u8>, dwarf_expr::Error> {
+        if reg < dwarf_expr::XMM0 || reg >= dwarf_expr::XMM0 + 16 {
+            return Ok(self.register(reg)?.to_le_bytes().to_vec());
+        }
+        match self.frame {
+            Some(frame) if frame.level == 0 => {
+                let fpregs = self.inferior.fp_registers()?;
+                let start = (reg - dwarf_expr::XMM0) as usize * 4;
+                Ok(fpregs.xmm_space[start..start + 4].iter().flat_map(|word| word.to_le_bytes().to_vec()).collect())
+            }
+            _ => Err(dwarf_expr::Error::Unavailable),
+        }
+    }
+
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>, dwarf_expr::Error> {
+        Ok(self.inferior.read_memory(addr as usize, len)?)
+    }
+
+    fn frame_base(&self) -> Result<u64, dwarf_expr::Error> {
+        let frame = self.frame.ok_or(dwarf_expr::Error::Unavailable)?;
+        let func = self.debug_data.get_function_containing(frame.pc);
+        match func.and_then(|func| func.frame_base.as_ref()) {
+            Some(base) => {
+                let expr = base.expression_at(frame.pc).ok_or(dwarf_expr::Error::OptimizedOut)?;
+                dwarf_expr::evaluate_value(expr, self)
+            }
+            None => self.call_frame_cfa(),
+        }
+    }
+
+    fn call_frame_cfa(&self) -> Result<u64, dwarf_expr::Error> {
+        Ok(self.frame.ok_or(dwarf_expr::Error::Unavailable)?.cfa as u64)
+    }
+
+    fn entry_value(&self, expr: &[u8]) -> Result<u64, dwarf_expr::Error> {
+        let frame = self.frame.ok_or(dwarf_expr::Error::Unavailable)?;
+        let reg = dwarf_expr::register_operand(expr).ok_or_else(|| match expr.first() {
+            Some(op) => dwarf_expr::Error::Unsupported(*op),
+            None => dwarf_expr::Error::Malformed,
+        })?;
+        let func = self.debug_data.get_function_containing(frame.pc).ok_or(dwarf_expr::Error::OptimizedOut)?;
+        // nothing has touched the argument registers yet
+        if frame.level == 0 && frame.pc == func.address {
+            return self.register(reg);
+        }
+        // otherwise the caller may have recorded what it passed
+        let caller = self.inferior.caller_frame(self.debug_data, frame)?;
+        let value = self
+            .debug_data
+            .get_function_containing(caller.pc)
+            .and_then(|func| func.call_sites.iter().find(|site| site.return_address == caller.rip))
+            .and_then(|site| site.parameters.iter().find(|(param, _)| *param == reg))
+            .map(|(_, value)| value)
+            .ok_or(dwarf_expr::Error::OptimizedOut)?;
+        let context = FrameContext {
+            frame: Some(&caller),
+            ..*self
+        };
+        dwarf_expr::evaluate_value(value, &context).map_err(|_| dwarf_expr::Error::OptimizedOut)
+    }
+}
@@ -1,24 +1,97 @@
+mod completion;
+mod dap;
+mod debug_link;
 mod debugger;
 mod debugger_command;
 mod inferior;
 mod dwarf_data;
+mod dwarf_expr;
+mod error;
+mod gdbserver;
 mod gimli_wrapper;
+mod output;
+mod printers;
+mod record;
+mod session;
+mod strace;
+mod syscalls;
+mod tui;
+mod values;
+mod x86;
 
 use crate::debugger::Debugger;
+use crate::output::Output;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
+    if args.len() >= 2 && args[1] == "--dap" {
+        match args.len() {
+            2 | 3 => {
+                if let Err(err) = dap::serve(args.get(2).map(|t| t.as_str())) {
+                    eprintln!("dap: {}", err);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            _ => usage(&args[0]),
+        }
+    }
+    if args.len() >= 3 && args[1] == "--strace" {
+        match strace::run(&args[2], &args[3..].to_vec()) {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                eprintln!("strace: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    let (target, gdbserver_address, json, tui) = match args.len() {
+        2 => (&args[1], None, false, false),
+        3 if args[1] == "--interpreter=json" => (&args[2], None, true, false),
+        3 if args[1] == "--interpreter=console" => (&args[2], None, false, false),
+        3 if args[1] == "--tui" => (&args[2], None, false, true),
+        4 if args[1] == "--gdbserver" => match gdbserver::listen_address(&args[2]) {
+            Some(address) => (&args[3], Some(address), false, false),
+            None => usage(&args[0]),
+        },
+        _ => usage(&args[0]),
+    };
+
+    if let Some(address) = gdbserver_address {
+        // The server is driven by its client, not from the terminal, so a ctrl+c typed there is
+        // left to the program and must not take the server down with it
+        unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
+        if let Err(err) = gdbserver::serve(target, &address) {
+            println!("gdbserver: {}", err);
+            std::process::exit(1);
+        }
+        return;
     }
-    let target = &args[1];
 
-    // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
-    // processes)
-    unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
+    // In the debugger itself, ctrl+c interrupts the running inferior instead
+    inferior::handle_ctrl_c().expect("Error installing SIGINT handler");
+
+    let out = if json {
+        Output::json().expect("Error setting up JSON output")
+    } else {
+        Output::console()
+    };
+    let mut debugger = Debugger::new(target, out);
+    if tui {
+        if let Err(err) = debugger.start_tui() {
+            println!("tui: {}", err);
+            std::process::exit(1);
+        }
+    }
+    debugger.run();
+}
 
-    Debugger::new(target).run();
+fn usage(program: &str) -> ! {
+    println!(
+        "Usage: {} [--gdbserver [<host>:]<port> | --interpreter=console|json | --tui] <target program>\n       {} --dap [<target program>]\n       {} --strace <target program> [<args>...]",
+        program, program, program
+    );
+    std::process::exit(1);
 }
@@ -0,0 +1,161 @@
+//! Where the debugger's output goes: human-readable text on stdout, or, with
+//! `--interpreter=json`, one JSON record per line in the spirit of GDB/MI.
+//!
+//! In JSON mode every command may be prefixed with a numeric token (`12break main`), and produces
+//! exactly one result record carrying that token:
+//!
+//!     {"type":"result","token":12,"class":"done","breakpoint":{"number":0,"addr":"0x401136"}}
+//!
+//! `class` is "done", "running" (the inferior has been resumed) or "error" (with a "msg").
+//! Things that happen while the inferior runs are reported with "async" records, such as
+//! `{"type":"async","class":"stopped",...}`, and text meant for a human with "console" records.
+//! Records are the only thing written to stdout; the inferior and any other output are moved over
+//! to stderr.
+
+use nix::unistd;
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::FromRawFd;
+
+pub struct Output {
+    // the record stream; None for human-readable output
+    json: Option<File>,
+    // token of the command being executed
+    token: Option<u64>,
+    // fields of the pending "done" record
+    fields: Map<String, Value>,
+    // whether the current command already has its result record
+    result_sent: bool,
+}
+
+/// Splits the numeric token off the front of a command line.
+pub fn split_token(line: &str) -> (Option<u64>, &str) {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    (line[..digits].parse().ok(), &line[digits..])
+}
+
+impl Output {
+    pub fn console() -> Output {
+        Output {
+            json: None,
+            token: None,
+            fields: Map::new(),
+            result_sent: false,
+        }
+    }
+
+    /// Switches stdout over to stderr and keeps the original stdout for the records.
+    pub fn json() -> io::Result<Output> {
+        let to_io_error = |err: nix::Error| io::Error::new(io::ErrorKind::Other, err.to_string());
+        let fd = unistd::dup(1).map_err(to_io_error)?;
+        unistd::dup2(2, 1).map_err(to_io_error)?;
+        Ok(Output {
+            json: Some(unsafe { File::from_raw_fd(fd) }),
+            ..Output::console()
+        })
+    }
+
+    /// Writes the records to `file`, for tests.
+    #[cfg(test)]
+    pub fn json_to(file: File) -> Output {
+        Output {
+            json: Some(file),
+            ..Output::console()
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json.is_some()
+    }
+
+    fn write(&mut self, mut record: Value) {
+        if let Some(out) = self.json.as_mut() {
+            if let Some(token) = self.token {
+                record["token"] = json!(token);
+            }
+            let _ = writeln!(out, "{}", record);
+            let _ = out.flush();
+        }
+    }
+
+    /// Starts a new command.
+    pub fn begin(&mut self, token: Option<u64>) {
+        self.token = token;
+        self.fields.clear();
+        self.result_sent = false;
+    }
+
+    /// Ends the current command, reporting success unless it already has a result.
+    pub fn finish(&mut self) {
+        if !self.result_sent {
+            let mut record = json!({"type": "result", "class": "done"});
+            for (key, value) in std::mem::replace(&mut self.fields, Map::new()) {
+                record[key] = value;
+            }
+            self.write(record);
+        }
+        self.result_sent = true;
+    }
+
+    /// Text for a human, with no structured equivalent.
+    pub fn message(&mut self, text: &str) {
+        if self.is_json() {
+            self.write(json!({"type": "console", "text": text}));
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    /// Reports that the current command failed.
+    pub fn error(&mut self, text: &str) {
+        if self.is_json() {
+            if !self.result_sent {
+                self.write(json!({"type": "result", "class": "error", "msg": text}));
+                self.result_sent = true;
+            }
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    /// Adds `key` to the result of the current command; `text` is what a human sees instead.
+    pub fn result(&mut self, key: &str, value: Value, text: &str) {
+        if self.is_json() {
+            self.fields.insert(key.to_string(), value);
+        } else if !text.is_empty() {
+            println!("{}", text);
+        }
+    }
+
+    /// Reports that the inferior is about to run.
+    pub fn running(&mut self) {
+        if self.is_json() && !self.result_sent {
+            self.write(json!({"type": "result", "class": "running"}));
+            self.result_sent = true;
+        }
+    }
+
+    /// Reports something that happened to the inferior; `text` is what a human sees instead.
+    pub fn event(&mut self, class: &str, mut fields: Value, text: &str) {
+        if self.is_json() {
+            fields["type"] = json!("async");
+            fields["class"] = json!(class);
+            self.write(fields);
+        } else if !text.is_empty() {
+            println!("{}", text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_token() {
+        assert_eq!(split_token("12break main"), (Some(12), "break main"));
+        assert_eq!(split_token("continue"), (None, "continue"));
+        assert_eq!(split_token("7"), (Some(7), ""));
+    }
+}
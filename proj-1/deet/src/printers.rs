@@ -0,0 +1,338 @@
+//! Pretty-printers: custom display for values of types whose raw fields say little, like the
+//! buffer and length of a `Vec` or the links of a list. Printers are registered by type name.
+//! The built-in ones handle C strings, linked lists, and the containers of Rust's standard
+//! library.
+
+use crate::dwarf_data::{DwarfData, Type, TypeId, TypeKind};
+use crate::values::{read_c_string, read_uint, Formatter, ValueTree, MAX_ELEMENTS};
+use std::collections::HashSet;
+
+/// How many nodes of a linked list to show
+const MAX_LIST_NODES: usize = 32;
+
+/// The member names that make a struct a linked list node when they point to the struct itself.
+/// Other self-pointers, like the `left` and `parent` of a tree node, are not links of a list.
+const LINK_NAMES: [&str; 2] = ["next", "link"];
+
+pub trait PrettyPrinter {
+    /// The display tree of a value of type `ty`, or None to show it field by field. `formatter`
+    /// reads the inferior's memory and builds the trees of the values this one contains, which
+    /// are `depth + 1` levels down.
+    fn to_tree(&self, formatter: &Formatter, ty: &Type, bytes: &[u8], depth: usize) -> Option<ValueTree>;
+}
+
+/// The pretty-printers, by the name of the type they print.
+pub struct Printers {
+    printers: Vec<(String, Box<dyn PrettyPrinter>)>,
+}
+
+impl Printers {
+    /// The built-in printers.
+    pub fn new() -> Printers {
+        let mut printers = Printers { printers: Vec::new() };
+        for name in &["char *", "const char *", "unsigned char *", "const unsigned char *", "signed char *"] {
+            printers.register(name, Box::new(CString));
+        }
+        printers.register("alloc::vec::Vec<", Box::new(RustVec));
+        printers.register("alloc::string::String", Box::new(RustString));
+        printers.register("&str", Box::new(RustSlice));
+        printers.register("&mut str", Box::new(RustSlice));
+        printers.register("&[", Box::new(RustSlice));
+        printers.register("&mut [", Box::new(RustSlice));
+        printers
+    }
+
+    /// The built-in printers, and a linked-list printer for the pointers to every C struct that
+    /// has a `next` or `link` member pointing to the same type (like
+    /// `struct node { ...; struct node *next; }`).
+    pub fn for_program(debug_data: &DwarfData) -> Printers {
+        let mut printers = Printers::new();
+        for (id, ty) in debug_data.types() {
+            let node = match ty.kind {
+                TypeKind::Pointer(Some(node)) if !ty.rust => node,
+                _ => continue,
+            };
+            let members = match debug_data.get_type(node).map(|node| strip_aliases(debug_data, node)) {
+                Some(Type {
+                    kind: TypeKind::Struct { members, .. },
+                    ..
+                }) => members,
+                _ => continue,
+            };
+            let link = members.iter().filter(|member| LINK_NAMES.contains(&member.name.as_str())).find(|member| {
+                member.type_id == id || debug_data.get_type(member.type_id).map_or(false, |ty| points_to(debug_data, ty, node))
+            });
+            if let Some(link) = link {
+                if printers.find(&ty.name).is_none() {
+                    printers.register(&ty.name, Box::new(LinkedList::new(&link.name)));
+                }
+            }
+        }
+        printers
+    }
+
+    /// Uses `printer` for the values of the type called `type_name`. A name ending in `<` or `[`
+    /// is a prefix, e.g. `alloc::vec::Vec<` for vectors of any type. Later registrations take
+    /// precedence.
+    pub fn register(&mut self, type_name: &str, printer: Box<dyn PrettyPrinter>) {
+        self.printers.push((type_name.to_string(), printer));
+    }
+
+    /// The printer for the type called `type_name`.
+    pub fn find(&self, type_name: &str) -> Option<&dyn PrettyPrinter> {
+        self.printers
+            .iter()
+            .rev()
+            .find(|(name, _)| {
+                name == type_name || ((name.ends_with('<') || name.ends_with('[')) && type_name.starts_with(name.as_str()))
+            })
+            .map(|(_, printer)| printer.as_ref())
+    }
+}
+
+/// The type a typedef or const/volatile type stands for.
+fn strip_aliases<'a>(debug_data: &'a DwarfData, mut ty: &'a Type) -> &'a Type {
+    for _ in 0..8 {
+        match ty.kind {
+            TypeKind::Alias(Some(aliased)) => match debug_data.get_type(aliased) {
+                Some(aliased) => ty = aliased,
+                None => break,
+            },
+            _ => break,
+        }
+    }
+    ty
+}
+
+/// Whether `ty` is a pointer to the type `target`, or to one of its aliases.
+fn points_to(debug_data: &DwarfData, ty: &Type, target: TypeId) -> bool {
+    match strip_aliases(debug_data, ty).kind {
+        TypeKind::Pointer(Some(pointee)) => {
+            pointee == target
+                || match (debug_data.get_type(pointee), debug_data.get_type(target)) {
+                    (Some(pointee), Some(target)) => {
+                        std::ptr::eq(strip_aliases(debug_data, pointee), strip_aliases(debug_data, target))
+                    }
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+/// `char *`: the address, and the NUL-terminated string there.
+pub struct CString;
+
+impl PrettyPrinter for CString {
+    fn to_tree(&self, formatter: &Formatter, _ty: &Type, bytes: &[u8], _depth: usize) -> Option<ValueTree> {
+        let addr = read_uint(bytes) as usize;
+        if addr == 0 {
+            return None;
+        }
+        let (text, end) = read_c_string(formatter.memory, addr, MAX_ELEMENTS)?;
+        let mut quoted = format!("{:?}", String::from_utf8_lossy(&text));
+        if !end {
+            quoted.push_str("...");
+        }
+        Some(ValueTree::Pointer(addr, Box::new(ValueTree::Scalar(quoted))))
+    }
+}
+
+/// A pointer to the first node of a linked list, shown as the list of its nodes (without the
+/// link to the next one).
+pub struct LinkedList {
+    /// The member pointing to the next node
+    next: String,
+}
+
+impl LinkedList {
+    pub fn new(next: &str) -> LinkedList {
+        LinkedList { next: next.to_string() }
+    }
+}
+
+impl PrettyPrinter for LinkedList {
+    fn to_tree(&self, formatter: &Formatter, ty: &Type, bytes: &[u8], depth: usize) -> Option<ValueTree> {
+        let node_type = match ty.kind {
+            TypeKind::Pointer(Some(node_type)) => strip_aliases(formatter.debug_data, formatter.debug_data.get_type(node_type)?),
+            _ => return None,
+        };
+        let head = read_uint(bytes) as usize;
+        if head == 0 {
+            return None;
+        }
+        let mut nodes = Vec::new();
+        // lists may be circular
+        let mut seen = HashSet::new();
+        let mut addr = head;
+        while addr != 0 && nodes.len() < MAX_LIST_NODES && seen.insert(addr) {
+            let node = match formatter.memory.read(addr, node_type.size) {
+                Some(node) => node,
+                None => {
+                    nodes.push(ValueTree::Scalar(format!("<error reading {:#x}>", addr)));
+                    addr = 0;
+                    break;
+                }
+            };
+            nodes.push(formatter.fields_except("", node_type, &node, depth + 1, &self.next));
+            addr = read_uint(formatter.field(node_type, &node, &self.next)?.1) as usize;
+        }
+        let elements = ValueTree::Elements {
+            elements: nodes,
+            more: addr != 0,
+            square: true,
+        };
+        Some(ValueTree::Pointer(head, Box::new(elements)))
+    }
+}
+
+/// Rust's `Vec<T>`: its elements.
+pub struct RustVec;
+
+/// The buffer and length of a `Vec`.
+fn vec_parts(formatter: &Formatter, ty: &Type, bytes: &[u8]) -> Option<(usize, usize)> {
+    let (_, len) = formatter.field(ty, bytes, "len")?;
+    let (buf_type, buf) = formatter.field(ty, bytes, "buf")?;
+    let (_, ptr) = formatter.find_pointer(buf_type, buf, 0)?;
+    Some((ptr, read_uint(len) as usize))
+}
+
+impl PrettyPrinter for RustVec {
+    fn to_tree(&self, formatter: &Formatter, ty: &Type, bytes: &[u8], depth: usize) -> Option<ValueTree> {
+        // the buffer is untyped in recent versions of Rust
+        let element = match &ty.kind {
+            TypeKind::Struct { template_params, .. } => formatter.debug_data.get_type(*template_params.first()?)?,
+            _ => return None,
+        };
+        let (ptr, len) = vec_parts(formatter, ty, bytes)?;
+        formatter.elements_at(element, ptr, len, depth)
+    }
+}
+
+/// The `len` bytes of UTF-8 text at `ptr`, quoted. The length comes from the inferior, where it
+/// may be garbage, so at most `MAX_ELEMENTS` bytes are read, with "..." for the rest.
+fn quoted_text(formatter: &Formatter, ptr: usize, len: usize) -> Option<ValueTree> {
+    let text = formatter.memory.read(ptr, len.min(MAX_ELEMENTS))?;
+    let mut quoted = format!("{:?}", String::from_utf8_lossy(&text));
+    if len > MAX_ELEMENTS {
+        quoted.push_str("...");
+    }
+    Some(ValueTree::Scalar(quoted))
+}
+
+/// Rust's `String`: the text, quoted.
+pub struct RustString;
+
+impl PrettyPrinter for RustString {
+    fn to_tree(&self, formatter: &Formatter, ty: &Type, bytes: &[u8], _depth: usize) -> Option<ValueTree> {
+        let (vec_type, vec) = formatter.field(ty, bytes, "vec")?;
+        let (ptr, len) = vec_parts(formatter, vec_type, vec)?;
+        quoted_text(formatter, ptr, len)
+    }
+}
+
+/// Rust's `&str` (the text, quoted) and slices (their elements).
+pub struct RustSlice;
+
+impl PrettyPrinter for RustSlice {
+    fn to_tree(&self, formatter: &Formatter, ty: &Type, bytes: &[u8], depth: usize) -> Option<ValueTree> {
+        let (ptr_type, ptr) = formatter.field(ty, bytes, "data_ptr")?;
+        let (_, len) = formatter.field(ty, bytes, "length")?;
+        let (element, ptr) = formatter.find_pointer(ptr_type, ptr, 0)?;
+        let len = read_uint(len) as usize;
+        if ty.name.ends_with("str") {
+            return quoted_text(formatter, ptr, len);
+        }
+        formatter.elements_at(element?, ptr, len, depth)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::values::format_value;
+    use crate::values::test::{make_type, member, structure, FakeMemory};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_c_printers() {
+        const INT: TypeId = 1;
+        const CHAR: TypeId = 2;
+        const NODE: TypeId = 3;
+        const NODE_PTR: TypeId = 4;
+        const CHAR_PTR: TypeId = 5;
+        let mut types = HashMap::new();
+        types.insert(INT, make_type("int", 4, TypeKind::Base(0x05), false));
+        types.insert(CHAR, make_type("char", 1, TypeKind::Base(0x06), false));
+        types.insert(NODE, make_type("node", 16, structure(vec![member("value", 0, INT), member("next", 8, NODE_PTR)]), false));
+        types.insert(NODE_PTR, make_type("node *", 8, TypeKind::Pointer(Some(NODE)), false));
+        types.insert(CHAR_PTR, make_type("char *", 8, TypeKind::Pointer(Some(CHAR)), false));
+        let debug_data = DwarfData::from_types(types);
+        let printers = Printers::for_program(&debug_data);
+
+        // three nodes at 0x1000, 0x1010 and 0x1020, then "hi" at 0x1030
+        let mut heap = Vec::new();
+        for (value, next) in &[(1u64, 0x1010u64), (2, 0x1020), (3, 0)] {
+            heap.extend_from_slice(&value.to_le_bytes());
+            heap.extend_from_slice(&next.to_le_bytes());
+        }
+        heap.extend_from_slice(b"hi\0");
+        // memory is read by pieces of up to 64 bytes
+        heap.resize(0x40, 0);
+        let memory = FakeMemory(0x1000, heap);
+        let format = |id: TypeId, value: u64| {
+            let ty = debug_data.get_type(id).unwrap();
+            format_value(&debug_data, &printers, &memory, ty, &value.to_le_bytes())
+        };
+        assert_eq!(format(NODE_PTR, 0x1000), "0x1000 [{value = 1}, {value = 2}, {value = 3}]");
+        assert_eq!(format(NODE_PTR, 0), "0x0");
+        assert_eq!(format(CHAR_PTR, 0x1030), "0x1030 \"hi\"");
+        assert_eq!(format(CHAR_PTR, 0x1031), "0x1031 \"i\"");
+        assert_eq!(format(CHAR_PTR, 0x2000), "0x2000");
+
+        // a circular list stops where it started
+        let mut circular = memory.1.clone();
+        circular[0x28..0x30].copy_from_slice(&0x1000u64.to_le_bytes());
+        let memory = FakeMemory(0x1000, circular);
+        let ty = debug_data.get_type(NODE_PTR).unwrap();
+        let value = format_value(&debug_data, &printers, &memory, ty, &0x1000u64.to_le_bytes());
+        assert_eq!(value, "0x1000 [{value = 1}, {value = 2}, {value = 3}, ...]");
+    }
+
+    #[test]
+    fn test_only_list_links_make_lists() {
+        const INT: TypeId = 1;
+        const TREE: TypeId = 2;
+        const TREE_PTR: TypeId = 3;
+        let mut types = HashMap::new();
+        types.insert(INT, make_type("int", 4, TypeKind::Base(0x05), false));
+        let tree = structure(vec![member("value", 0, INT), member("left", 8, TREE_PTR), member("right", 16, TREE_PTR)]);
+        types.insert(TREE, make_type("tree", 24, tree, false));
+        types.insert(TREE_PTR, make_type("tree *", 8, TypeKind::Pointer(Some(TREE)), false));
+        let debug_data = DwarfData::from_types(types);
+        let printers = Printers::for_program(&debug_data);
+        assert!(printers.find("tree *").is_none());
+    }
+
+    #[test]
+    fn test_rust_text_is_capped() {
+        const U8: TypeId = 1;
+        const USIZE: TypeId = 2;
+        const PTR_U8: TypeId = 3;
+        let mut types = HashMap::new();
+        types.insert(U8, make_type("u8", 1, TypeKind::Base(0x07), true));
+        types.insert(USIZE, make_type("usize", 8, TypeKind::Base(0x07), true));
+        types.insert(PTR_U8, make_type("*const u8", 8, TypeKind::Pointer(Some(U8)), true));
+        let debug_data = DwarfData::from_types(types);
+        let printers = Printers::new();
+        let str_ref = make_type("&str", 16, structure(vec![member("data_ptr", 0, PTR_U8), member("length", 8, USIZE)]), true);
+        let memory = FakeMemory(0x1000, vec![b'a'; 2 * MAX_ELEMENTS]);
+        let format = |len: u64| {
+            let bytes: Vec<u8> = [0x1000u64, len].iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+            format_value(&debug_data, &printers, &memory, &str_ref, &bytes)
+        };
+        assert_eq!(format(3), "\"aaa\"");
+        // an uninitialised length is not allocated for
+        assert_eq!(format(u64::MAX), format!("\"{}\"...", "a".repeat(MAX_ELEMENTS)));
+    }
+}
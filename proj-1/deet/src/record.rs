@@ -0,0 +1,333 @@
+//! Execution recording for reverse debugging.
+//!
+//! While recording, the inferior is single-stepped one instruction at a time. Before each step the
+//! registers are saved, along with the memory the instruction may write (see `x86`), and after it
+//! that memory is compared against the saved copy so that every overwritten word can be put back.
+//! Going backwards pops entries off the log and undoes them through ptrace; running forwards again
+//! simply re-executes (and re-records) the program from the restored state.
+//!
+//! A system call may write anywhere, so around one all writable memory is compared. Writes made
+//! by the kernel or by other processes at any other time are not undone.
+
+use crate::dwarf_data::{DwarfData, Line};
+use crate::inferior::{self, Inferior, Status};
+use crate::x86;
+use nix::sys::signal::Signal;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+
+/// Upper bound on the number of recorded instructions; the oldest ones are dropped first.
+const RECORD_LIMIT: usize = 100_000;
+
+/// The longest an x86-64 instruction can be.
+const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Everything needed to undo one instruction.
+struct StepRecord {
+    regs: libc::user_regs_struct,
+    // (address, word before the step) for every word the instruction overwrote
+    writes: Vec<(usize, u64)>,
+    // call depth before the step, relative to where recording started
+    depth: isize,
+}
+
+pub struct Recorder {
+    log: VecDeque<StepRecord>,
+    // call depth of the current state, relative to where recording started
+    depth: isize,
+}
+
+fn word_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Source line of an address, ignoring the address itself so that two instructions of the same
+/// line compare equal.
+fn line_key(debug_data: &DwarfData, addr: usize) -> Option<(String, usize)> {
+    debug_data
+        .get_line_from_addr(addr)
+        .map(|Line { file, number, .. }| (file, number))
+}
+
+/// The writable mappings of the inferior, from /proc/<pid>/maps.
+fn writable_memory(inferior: &Inferior) -> Result<Vec<(usize, usize)>, nix::Error> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", inferior.pid()))
+        .or(Err(nix::Error::Sys(nix::errno::Errno::EIO)))?;
+    let mut ranges = Vec::new();
+    for line in maps.lines() {
+        // e.g. "00404000-00405000 rw-p 00003000 08:01 1234   /path/to/exe"
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 2 || !fields[1].starts_with("rw") {
+            continue;
+        }
+        let mut bounds = fields[0].split('-');
+        let lo = usize::from_str_radix(bounds.next().unwrap_or(""), 16);
+        let hi = usize::from_str_radix(bounds.next().unwrap_or(""), 16);
+        if let (Ok(lo), Ok(hi)) = (lo, hi) {
+            ranges.push((lo, hi));
+        }
+    }
+    Ok(ranges)
+}
+
+/// Sorts `ranges`, widens them to whole words and merges the ones that overlap.
+fn merge(mut ranges: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    for range in &mut ranges {
+        *range = (range.0 & !7, range.1.saturating_add(7) & !7);
+    }
+    ranges.retain(|&(start, end)| start < end);
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder {
+            log: VecDeque::new(),
+            depth: 0,
+        }
+    }
+
+    /// Runs the inferior forward one instruction at a time, recording each step, until it hits a
+    /// breakpoint, receives a signal or terminates. Breakpoint hits are reported the same way a
+    /// real trap would leave things: with %rip just past the breakpoint.
+    pub fn continue_run(
+        &mut self,
+        inferior: &mut Inferior,
+        breakpoints: &HashMap<usize, u8>,
+    ) -> Result<Status, nix::Error> {
+        inferior.rewind_breakpoint(breakpoints)?;
+        // forget a ctrl+c pressed before we started
+        inferior::interrupt_requested();
+        let mut first = true;
+        loop {
+            let mut regs = inferior.registers()?;
+            let rip = regs.rip as usize;
+            if !first && breakpoints.contains_key(&rip) {
+                regs.rip += 1;
+                inferior.set_registers(regs)?;
+                return Ok(Status::Stopped(Signal::SIGTRAP, rip + 1));
+            }
+            first = false;
+            if inferior::interrupt_requested() {
+                return Ok(Status::Stopped(Signal::SIGINT, rip));
+            }
+            let before = Recorder::snapshot(inferior, breakpoints, &regs)?;
+            match inferior.step_instruction(breakpoints)? {
+                Status::Stopped(Signal::SIGTRAP, _) => self.commit(inferior, regs, before)?,
+                // a signal stops the instruction from executing, so there is nothing to record
+                other => return Ok(other),
+            }
+        }
+    }
+
+    /// Reads what can be read of `len` bytes at `addr`, a page at a time: the pieces that could
+    /// be read, with their addresses.
+    fn read_pieces(inferior: &Inferior, addr: usize, len: usize) -> Vec<(usize, Vec<u8>)> {
+        let mut pieces = Vec::new();
+        let end = addr.saturating_add(len);
+        let mut start = addr;
+        while start < end {
+            let piece_end = ((start / PAGE_SIZE + 1) * PAGE_SIZE).min(end);
+            if let Ok(data) = inferior.read_memory(start, piece_end - start) {
+                pieces.push((start, data));
+            }
+            start = piece_end;
+        }
+        pieces
+    }
+
+    /// Saves the memory the instruction at `regs.rip` may write.
+    fn snapshot(
+        inferior: &Inferior,
+        breakpoints: &HashMap<usize, u8>,
+        regs: &libc::user_regs_struct,
+    ) -> Result<Vec<(usize, Vec<u8>)>, nix::Error> {
+        let rip = regs.rip as usize;
+        // the instruction may run into a page that can not be read; what was read is decoded
+        let mut code = Vec::new();
+        for (start, data) in Recorder::read_pieces(inferior, rip, MAX_INSTRUCTION_LENGTH) {
+            if start != rip + code.len() {
+                break;
+            }
+            code.extend(data);
+        }
+        for (offset, byte) in code.iter_mut().enumerate() {
+            if let Some(&original) = breakpoints.get(&(rip + offset)) {
+                *byte = original;
+            }
+        }
+        let ranges = match x86::written_ranges(&code, regs) {
+            Some(ranges) => ranges,
+            None => writable_memory(inferior)?,
+        };
+        Ok(merge(ranges)
+            .into_iter()
+            .flat_map(|(start, end)| Recorder::read_pieces(inferior, start, end - start))
+            .collect())
+    }
+
+    /// Appends a log entry for the instruction that was just executed from state `regs`, with
+    /// `before` the memory it may have written as it was before.
+    fn commit(
+        &mut self,
+        inferior: &Inferior,
+        regs: libc::user_regs_struct,
+        before: Vec<(usize, Vec<u8>)>,
+    ) -> Result<(), nix::Error> {
+        let after = inferior.registers()?;
+        let mut writes = Vec::new();
+        for (start, data) in before {
+            // the instruction may have unmapped it
+            let current = match inferior.read_memory(start, data.len()) {
+                Ok(current) => current,
+                Err(_) => continue,
+            };
+            for offset in (0..data.len() - data.len() % 8).step_by(8) {
+                if current[offset..offset + 8] != data[offset..offset + 8] {
+                    writes.push((start + offset, word_at(&data, offset)));
+                }
+            }
+        }
+        self.log.push_back(StepRecord {
+            regs,
+            writes,
+            depth: self.depth,
+        });
+        // a call pushes the address of the next instruction and jumps elsewhere; a ret pops the
+        // address it jumps to
+        let is_call = after.rsp == regs.rsp.wrapping_sub(8)
+            && Recorder::word(inferior, after.rsp as usize).map_or(false, |ret| {
+                ret > regs.rip
+                    && ret <= regs.rip + MAX_INSTRUCTION_LENGTH as u64
+                    && after.rip != ret
+            });
+        let is_ret = after.rsp == regs.rsp + 8
+            && Recorder::word(inferior, regs.rsp as usize) == Some(after.rip);
+        if is_call {
+            self.depth += 1;
+        } else if is_ret {
+            self.depth -= 1;
+        }
+        if self.log.len() > RECORD_LIMIT {
+            self.log.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Reads the word at `addr` of the stack.
+    fn word(inferior: &Inferior, addr: usize) -> Option<u64> {
+        let data = inferior.read_memory(addr, 8).ok()?;
+        Some(word_at(&data, 0))
+    }
+
+    /// Undoes the most recently recorded instruction. Returns the restored registers, or None if
+    /// the log is empty.
+    fn undo(
+        &mut self,
+        inferior: &mut Inferior,
+    ) -> Result<Option<libc::user_regs_struct>, nix::Error> {
+        let entry = match self.log.pop_back() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        for &(addr, val) in entry.writes.iter().rev() {
+            inferior.write_word(addr, val)?;
+        }
+        inferior.set_registers(entry.regs)?;
+        self.depth = entry.depth;
+        Ok(Some(entry.regs))
+    }
+
+    /// Leaves a restored state that sits on a breakpoint looking like a fresh hit of that
+    /// breakpoint, so that `continue` steps over it instead of trapping right away.
+    fn settle(
+        inferior: &mut Inferior,
+        breakpoints: &HashMap<usize, u8>,
+    ) -> Result<usize, nix::Error> {
+        let mut regs = inferior.registers()?;
+        if breakpoints.contains_key(&(regs.rip as usize)) {
+            regs.rip += 1;
+            inferior.set_registers(regs)?;
+        }
+        Ok(regs.rip as usize)
+    }
+
+    /// Runs backwards to the beginning of the previous source line. With `over_calls`, lines
+    /// executed in functions called from the current frame are skipped (`reverse-next`);
+    /// otherwise they count (`reverse-step`). Returns the new %rip and whether there was enough
+    /// history to get there.
+    pub fn reverse_step(
+        &mut self,
+        inferior: &mut Inferior,
+        debug_data: &DwarfData,
+        breakpoints: &HashMap<usize, u8>,
+        over_calls: bool,
+    ) -> Result<(usize, bool), nix::Error> {
+        let start_line = line_key(debug_data, inferior.registers()?.rip as usize);
+        let mut frame_depth = self.depth;
+
+        // Find the entry to go back to before touching the inferior: the earliest entry of the
+        // most recent run of a different line, looking through callees for reverse-next and
+        // through code without line information either way.
+        let mut target: Option<usize> = None;
+        let mut target_line = None;
+        for (i, entry) in self.log.iter().enumerate().rev() {
+            let line = line_key(debug_data, entry.regs.rip as usize);
+            if line.is_none() || (over_calls && entry.depth > frame_depth) {
+                continue;
+            }
+            if target_line.is_none() {
+                if line != start_line {
+                    target = Some(i);
+                    target_line = line;
+                    frame_depth = entry.depth;
+                }
+            } else if line == target_line {
+                target = Some(i);
+            } else {
+                break;
+            }
+        }
+
+        let target = match target {
+            Some(target) => target,
+            None => {
+                while self.undo(inferior)?.is_some() {}
+                return Ok((Recorder::settle(inferior, breakpoints)?, false));
+            }
+        };
+        while self.log.len() > target {
+            self.undo(inferior)?;
+        }
+        Ok((Recorder::settle(inferior, breakpoints)?, true))
+    }
+
+    /// Runs backwards until a breakpoint is reached. Returns the new %rip and whether a breakpoint
+    /// was found before the history ran out.
+    pub fn reverse_continue(
+        &mut self,
+        inferior: &mut Inferior,
+        breakpoints: &HashMap<usize, u8>,
+    ) -> Result<(usize, bool), nix::Error> {
+        let mut found = false;
+        while let Some(regs) = self.undo(inferior)? {
+            if breakpoints.contains_key(&(regs.rip as usize)) {
+                found = true;
+                break;
+            }
+        }
+        Ok((Recorder::settle(inferior, breakpoints)?, found))
+    }
+}
@@ -0,0 +1,95 @@
+//! Breakpoints that outlive a deet session. They are written down as the commands that set them
+//! (`break main`, `tbreak loop.c:12`), by function or source line rather than by address, so that
+//! they still mean the same thing after the program is rebuilt.
+//!
+//! `save breakpoints <file>` writes them anywhere; on quit they also go to a session file for the
+//! target under `~/.deet_sessions`, which is loaded again the next time that program is debugged.
+
+use crate::debugger_command::DebuggerCommand;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A breakpoint as it is saved.
+#[derive(Debug, PartialEq)]
+pub struct SavedBreakpoint {
+    /// Where it is, in any form `break` accepts
+    pub location: String,
+    /// Whether it was set with `tbreak`
+    pub temporary: bool,
+}
+
+/// The session file for `target` in the home directory `home`: the target's absolute path, with
+/// slashes turned into percent signs so that it makes a single file name.
+pub fn session_path(home: &str, target: &str) -> PathBuf {
+    let target = std::fs::canonicalize(target).unwrap_or_else(|_| PathBuf::from(target));
+    let name = target.to_string_lossy().replace('/', "%");
+    Path::new(home).join(".deet_sessions").join(name)
+}
+
+/// The commands that set `breakpoints` again, one per line.
+pub fn format(breakpoints: &[SavedBreakpoint]) -> String {
+    breakpoints
+        .iter()
+        .map(|bp| format!("{} {}\n", if bp.temporary { "tbreak" } else { "break" }, bp.location))
+        .collect()
+}
+
+/// Reads the breakpoints back from the commands in `text`. Blank lines and `#` comments are
+/// skipped, and so is anything that is not a breakpoint command.
+pub fn parse(text: &str) -> Vec<SavedBreakpoint> {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .filter(|tokens| tokens.len() >= 2 && !tokens[0].starts_with('#'))
+        .filter_map(|tokens| match DebuggerCommand::from_tokens(&tokens).ok()? {
+            DebuggerCommand::Breakpoint(location) => Some(SavedBreakpoint { location, temporary: false }),
+            DebuggerCommand::TemporaryBreakpoint(location) => Some(SavedBreakpoint { location, temporary: true }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes `breakpoints` to `path`. An empty list removes the file instead, so that a session
+/// without breakpoints does not bring back old ones.
+pub fn write(path: &Path, breakpoints: &[SavedBreakpoint]) -> io::Result<()> {
+    if breakpoints.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, format(breakpoints))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_and_parse() {
+        let breakpoints = vec![
+            SavedBreakpoint { location: "main".to_string(), temporary: false },
+            SavedBreakpoint { location: "loop.c:12".to_string(), temporary: true },
+            SavedBreakpoint { location: "*0x401136".to_string(), temporary: false },
+        ];
+        let text = format(&breakpoints);
+        assert_eq!(text, "break main\ntbreak loop.c:12\nbreak *0x401136\n");
+        assert_eq!(parse(&text), breakpoints);
+        let text = "# saved by hand\n\nb fact\nrun\ntb 7\n";
+        assert_eq!(
+            parse(text),
+            vec![
+                SavedBreakpoint { location: "fact".to_string(), temporary: false },
+                SavedBreakpoint { location: "7".to_string(), temporary: true },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_session_path() {
+        let path = session_path("/home/me", "/usr/bin/does-not-exist");
+        assert_eq!(path, PathBuf::from("/home/me/.deet_sessions/%usr%bin%does-not-exist"));
+    }
+}
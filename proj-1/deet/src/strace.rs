@@ -0,0 +1,101 @@
+//! `deet --strace <target> [args...]`: runs a program and prints every system call it makes the
+//! way strace does, followed by the line of the program that made it:
+//!
+//! ```text
+//! write(1, "hello\n", 6) = 6    [main at hello.c:4]
+//! ```
+//!
+//! Calls are printed once they return, so that the buffers they fill can be shown; execve and the
+//! exit calls are started when they are made, since their arguments are gone (or their result
+//! never comes) afterwards. Like strace, the trace goes to stderr.
+
+use crate::dwarf_data::DwarfData;
+use crate::inferior::{Inferior, Status};
+use crate::syscalls;
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::io;
+
+/// System calls that are printed when they are made
+const PRINTED_ON_ENTRY: [&str; 4] = ["execve", "execveat", "exit", "exit_group"];
+
+/// `[func at file:line]` for the code that made the system call the inferior is stopped at.
+fn caller_text(inferior: &Inferior, debug_data: Option<&DwarfData>) -> String {
+    let debug_data = match debug_data {
+        Some(debug_data) => debug_data,
+        None => return String::new(),
+    };
+    let addr = match inferior.source_caller(debug_data) {
+        Some(addr) => addr,
+        None => return String::new(),
+    };
+    match (debug_data.get_function_from_addr(addr), debug_data.get_line_from_addr(addr)) {
+        (Some(func), Some(line)) => format!("    [{} at {}]", func, line),
+        (None, Some(line)) => format!("    [{}]", line),
+        _ => String::new(),
+    }
+}
+
+/// Traces `target` until it exits, and returns the exit status deet should exit with.
+pub fn run(target: &str, args: &Vec<String>) -> io::Result<i32> {
+    let debug_data = DwarfData::from_file(target).or_else(|_| DwarfData::from_symbol_table(target)).ok();
+    let mut inferior = Inferior::new(target, args, &mut HashMap::new())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Error starting subprocess"))?;
+    inferior.set_syscall_tracing(true);
+    let mut signal = None;
+    loop {
+        let status = inferior.resume(signal.take()).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let prefix = if inferior.threads().len() > 1 {
+            format!("[tid {}] ", inferior.current_tid())
+        } else {
+            String::new()
+        };
+        match status {
+            Status::SyscallEntry(number, _) | Status::SyscallExit(number, _) => {
+                let entry = matches!(status, Status::SyscallEntry(..));
+                let regs = match inferior.registers() {
+                    Ok(regs) => regs,
+                    // the thread died in the meantime
+                    Err(_) => continue,
+                };
+                let (_, arguments) = syscalls::registers_call(&regs);
+                let name = syscalls::name(number).unwrap_or("");
+                let location = caller_text(&inferior, debug_data.as_ref());
+                match (entry, PRINTED_ON_ENTRY.contains(&name)) {
+                    (true, true) => {
+                        let call = syscalls::format_call(number, &arguments, None, &inferior);
+                        if name.starts_with("exit") {
+                            eprintln!("{}{} = ?{}", prefix, call, location);
+                        } else {
+                            eprint!("{}{}", prefix, call);
+                        }
+                    }
+                    (false, true) => {
+                        eprintln!(" {}{}", syscalls::format_result(number, regs.rax as i64), location);
+                    }
+                    (true, false) => {}
+                    (false, false) => {
+                        let call = syscalls::format_call(number, &arguments, Some(regs.rax as i64), &inferior);
+                        eprintln!("{}{}{}", prefix, call, location);
+                    }
+                }
+            }
+            // the SIGTRAP a successful execve sends is ours, not the program's
+            Status::Stopped(Signal::SIGTRAP, _) => {}
+            // event stops are never turned on here
+            Status::Forked(..) | Status::Exec(..) | Status::Exiting(..) => {}
+            Status::Stopped(sig, _) => {
+                eprintln!("{}--- {} ---", prefix, sig);
+                signal = Some(sig);
+            }
+            Status::Exited(code) => {
+                eprintln!("+++ exited with {} +++", code);
+                return Ok(code);
+            }
+            Status::Signaled(sig) => {
+                eprintln!("+++ killed by {} +++", sig);
+                return Ok(128 + sig as i32);
+            }
+        }
+    }
+}
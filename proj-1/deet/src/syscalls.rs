@@ -0,0 +1,298 @@
+//! System calls of x86-64 Linux: their names, and how to show a call with its arguments and
+//! result the way strace does, for `catch syscall` and `deet --strace`.
+
+use crate::values::{read_c_string, Memory};
+use nix::errno::Errno;
+
+/// Names of the system calls numbered from 0
+const NAMES: [&str; 335] = [
+    "read", "write", "open", "close", "stat", "fstat", "lstat", "poll", "lseek", "mmap", "mprotect",
+    "munmap", "brk", "rt_sigaction", "rt_sigprocmask", "rt_sigreturn", "ioctl", "pread64",
+    "pwrite64", "readv", "writev", "access", "pipe", "select", "sched_yield", "mremap", "msync",
+    "mincore", "madvise", "shmget", "shmat", "shmctl", "dup", "dup2", "pause", "nanosleep",
+    "getitimer", "alarm", "setitimer", "getpid", "sendfile", "socket", "connect", "accept",
+    "sendto", "recvfrom", "sendmsg", "recvmsg", "shutdown", "bind", "listen", "getsockname",
+    "getpeername", "socketpair", "setsockopt", "getsockopt", "clone", "fork", "vfork", "execve",
+    "exit", "wait4", "kill", "uname", "semget", "semop", "semctl", "shmdt", "msgget", "msgsnd",
+    "msgrcv", "msgctl", "fcntl", "flock", "fsync", "fdatasync", "truncate", "ftruncate", "getdents",
+    "getcwd", "chdir", "fchdir", "rename", "mkdir", "rmdir", "creat", "link", "unlink", "symlink",
+    "readlink", "chmod", "fchmod", "chown", "fchown", "lchown", "umask", "gettimeofday",
+    "getrlimit", "getrusage", "sysinfo", "times", "ptrace", "getuid", "syslog", "getgid", "setuid",
+    "setgid", "geteuid", "getegid", "setpgid", "getppid", "getpgrp", "setsid", "setreuid",
+    "setregid", "getgroups", "setgroups", "setresuid", "getresuid", "setresgid", "getresgid",
+    "getpgid", "setfsuid", "setfsgid", "getsid", "capget", "capset", "rt_sigpending",
+    "rt_sigtimedwait", "rt_sigqueueinfo", "rt_sigsuspend", "sigaltstack", "utime", "mknod",
+    "uselib", "personality", "ustat", "statfs", "fstatfs", "sysfs", "getpriority", "setpriority",
+    "sched_setparam", "sched_getparam", "sched_setscheduler", "sched_getscheduler",
+    "sched_get_priority_max", "sched_get_priority_min", "sched_rr_get_interval", "mlock", "munlock",
+    "mlockall", "munlockall", "vhangup", "modify_ldt", "pivot_root", "_sysctl", "prctl",
+    "arch_prctl", "adjtimex", "setrlimit", "chroot", "sync", "acct", "settimeofday", "mount",
+    "umount2", "swapon", "swapoff", "reboot", "sethostname", "setdomainname", "iopl", "ioperm",
+    "create_module", "init_module", "delete_module", "get_kernel_syms", "query_module", "quotactl",
+    "nfsservctl", "getpmsg", "putpmsg", "afs_syscall", "tuxcall", "security", "gettid", "readahead",
+    "setxattr", "lsetxattr", "fsetxattr", "getxattr", "lgetxattr", "fgetxattr", "listxattr",
+    "llistxattr", "flistxattr", "removexattr", "lremovexattr", "fremovexattr", "tkill", "time",
+    "futex", "sched_setaffinity", "sched_getaffinity", "set_thread_area", "io_setup", "io_destroy",
+    "io_getevents", "io_submit", "io_cancel", "get_thread_area", "lookup_dcookie", "epoll_create",
+    "epoll_ctl_old", "epoll_wait_old", "remap_file_pages", "getdents64", "set_tid_address",
+    "restart_syscall", "semtimedop", "fadvise64", "timer_create", "timer_settime", "timer_gettime",
+    "timer_getoverrun", "timer_delete", "clock_settime", "clock_gettime", "clock_getres",
+    "clock_nanosleep", "exit_group", "epoll_wait", "epoll_ctl", "tgkill", "utimes", "vserver",
+    "mbind", "set_mempolicy", "get_mempolicy", "mq_open", "mq_unlink", "mq_timedsend",
+    "mq_timedreceive", "mq_notify", "mq_getsetattr", "kexec_load", "waitid", "add_key",
+    "request_key", "keyctl", "ioprio_set", "ioprio_get", "inotify_init", "inotify_add_watch",
+    "inotify_rm_watch", "migrate_pages", "openat", "mkdirat", "mknodat", "fchownat", "futimesat",
+    "newfstatat", "unlinkat", "renameat", "linkat", "symlinkat", "readlinkat", "fchmodat",
+    "faccessat", "pselect6", "ppoll", "unshare", "set_robust_list", "get_robust_list", "splice",
+    "tee", "sync_file_range", "vmsplice", "move_pages", "utimensat", "epoll_pwait", "signalfd",
+    "timerfd_create", "eventfd", "fallocate", "timerfd_settime", "timerfd_gettime", "accept4",
+    "signalfd4", "eventfd2", "epoll_create1", "dup3", "pipe2", "inotify_init1", "preadv", "pwritev",
+    "rt_tgsigqueueinfo", "perf_event_open", "recvmmsg", "fanotify_init", "fanotify_mark",
+    "prlimit64", "name_to_handle_at", "open_by_handle_at", "clock_adjtime", "syncfs", "sendmmsg",
+    "setns", "getcpu", "process_vm_readv", "process_vm_writev", "kcmp", "finit_module",
+    "sched_setattr", "sched_getattr", "renameat2", "seccomp", "getrandom", "memfd_create",
+    "kexec_file_load", "bpf", "execveat", "userfaultfd", "membarrier", "mlock2", "copy_file_range",
+    "preadv2", "pwritev2", "pkey_mprotect", "pkey_alloc", "pkey_free", "statx", "io_pgetevents",
+    "rseq",
+];
+
+/// Names of the system calls numbered from `NEWER_BASE`; the numbers in between are unused.
+const NEWER_NAMES: [&str; 27] = [
+    "pidfd_send_signal", "io_uring_setup", "io_uring_enter", "io_uring_register", "open_tree",
+    "move_mount", "fsopen", "fsconfig", "fsmount", "fspick", "pidfd_open", "clone3", "close_range",
+    "openat2", "pidfd_getfd", "faccessat2", "process_madvise", "epoll_pwait2", "mount_setattr",
+    "quotactl_fd", "landlock_create_ruleset", "landlock_add_rule", "landlock_restrict_self",
+    "memfd_secret", "process_mrelease", "futex_waitv", "set_mempolicy_home_node",
+];
+const NEWER_BASE: u64 = 424;
+
+/// How many bytes of strings and buffers to show, like strace's default
+const MAX_STRING: usize = 32;
+
+/// How an argument or return value is shown.
+#[derive(Clone, Copy)]
+enum Arg {
+    /// A C `int`: file descriptors, flags that are counted rather than or'ed...
+    Int,
+    /// A 64-bit number: sizes, offsets...
+    Long,
+    /// An address or a set of flags
+    Hex,
+    /// A NUL-terminated string
+    Str,
+    /// A buffer read by the kernel, whose length is the argument at the given index
+    In(usize),
+    /// A buffer filled by the kernel, whose length is the return value. Only shown once the call
+    /// has returned.
+    Out,
+}
+
+use self::Arg::*;
+
+/// The arguments and the kind of return value of the common system calls.
+fn signature(name: &str) -> Option<(&'static [Arg], Arg)> {
+    let arguments: &'static [Arg] = match name {
+        "read" => &[Int, Out, Long],
+        "write" => &[Int, In(2), Long],
+        "pread64" => &[Int, Out, Long, Long],
+        "pwrite64" => &[Int, In(2), Long, Long],
+        "readv" | "writev" => &[Int, Hex, Int],
+        "open" => &[Str, Hex, Hex],
+        "openat" => &[Int, Str, Hex, Hex],
+        "creat" | "mkdir" | "access" | "chmod" | "stat" | "lstat" => &[Str, Hex],
+        "unlink" | "rmdir" | "chdir" | "acct" => &[Str],
+        "rename" | "link" | "symlink" => &[Str, Str],
+        "readlink" => &[Str, Out, Long],
+        "unlinkat" | "mkdirat" | "faccessat" => &[Int, Str, Hex],
+        "newfstatat" => &[Int, Str, Hex, Hex],
+        "execve" => &[Str, Hex, Hex],
+        "close" | "dup" | "fsync" | "exit" | "exit_group" => &[Int],
+        "dup2" | "kill" | "listen" | "shutdown" => &[Int, Int],
+        "dup3" | "socket" | "tgkill" => &[Int, Int, Int],
+        "lseek" => &[Int, Long, Int],
+        "fstat" | "fchmod" | "pipe2" => &[Int, Hex],
+        "ftruncate" => &[Int, Long],
+        "pipe" | "brk" | "set_tid_address" | "uname" | "time" => &[Hex],
+        "ioctl" | "fcntl" => &[Int, Hex, Hex],
+        "getdents64" => &[Int, Hex, Long],
+        "getcwd" => &[Out, Long],
+        "mmap" => &[Hex, Long, Hex, Hex, Int, Hex],
+        "mprotect" | "madvise" => &[Hex, Long, Hex],
+        "munmap" => &[Hex, Long],
+        "rt_sigaction" | "rt_sigprocmask" => &[Int, Hex, Hex, Long],
+        "nanosleep" | "arch_prctl" | "gettimeofday" => &[Hex, Hex],
+        "clock_gettime" => &[Int, Hex],
+        "clock_nanosleep" => &[Int, Hex, Hex, Hex],
+        "wait4" => &[Int, Hex, Hex, Hex],
+        "clone" => &[Hex, Hex, Hex, Hex, Hex],
+        "futex" => &[Hex, Int, Int, Hex, Hex, Int],
+        "set_robust_list" => &[Hex, Long],
+        "prlimit64" => &[Int, Int, Hex, Hex],
+        "getrandom" => &[Out, Long, Hex],
+        "rseq" => &[Hex, Int, Hex, Hex],
+        "connect" | "bind" | "accept" => &[Int, Hex, Int],
+        "sendto" => &[Int, In(2), Long, Hex, Hex, Int],
+        "recvfrom" => &[Int, Out, Long, Hex, Hex, Hex],
+        "poll" => &[Hex, Int, Int],
+        "getpid" | "getppid" | "gettid" | "getuid" | "geteuid" | "getgid" | "getegid" | "fork"
+        | "vfork" | "sched_yield" | "pause" | "setsid" | "rt_sigreturn" => &[],
+        _ => return None,
+    };
+    let result = match name {
+        "mmap" | "brk" => Hex,
+        _ => Int,
+    };
+    Some((arguments, result))
+}
+
+/// The name of a system call.
+pub fn name(number: u64) -> Option<&'static str> {
+    if number >= NEWER_BASE {
+        NEWER_NAMES.get((number - NEWER_BASE) as usize).copied()
+    } else {
+        NAMES.get(number as usize).copied()
+    }
+}
+
+/// The number of a system call, given its name or its number.
+pub fn number(name: &str) -> Option<u64> {
+    if let Ok(number) = name.parse::<u64>() {
+        return self::name(number).map(|_| number);
+    }
+    if let Some(index) = NAMES.iter().position(|n| *n == name) {
+        return Some(index as u64);
+    }
+    NEWER_NAMES.iter().position(|n| *n == name).map(|index| NEWER_BASE + index as u64)
+}
+
+/// The number and arguments of the system call a thread is making, from its registers.
+pub fn registers_call(regs: &libc::user_regs_struct) -> (u64, [u64; 6]) {
+    (regs.orig_rax, [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9])
+}
+
+/// Shows a string or buffer of the inferior as a C string literal, with octal escapes for bytes
+/// that are not printable.
+fn quote(bytes: &[u8], truncated: bool) -> String {
+    let mut quoted = String::from("\"");
+    for (i, &byte) in bytes.iter().enumerate() {
+        match byte {
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(byte as char);
+            }
+            0x20..=0x7e => quoted.push(byte as char),
+            // a digit right after the escape would be taken as part of it
+            _ if bytes.get(i + 1).map_or(false, |next| next.is_ascii_digit()) => {
+                quoted.push_str(&format!("\\{:03o}", byte))
+            }
+            _ => quoted.push_str(&format!("\\{:o}", byte)),
+        }
+    }
+    quoted.push('"');
+    if truncated {
+        quoted.push_str("...");
+    }
+    quoted
+}
+
+fn format_argument(kind: Arg, args: &[u64; 6], value: u64, result: Option<i64>, memory: &dyn Memory) -> String {
+    let buffer = |len: u64| match memory.read(value as usize, (len as usize).min(MAX_STRING)) {
+        Some(bytes) => quote(&bytes, len as usize > MAX_STRING),
+        None => format!("{:#x}", value),
+    };
+    match kind {
+        Int => (value as i32).to_string(),
+        Long => (value as i64).to_string(),
+        Hex => format!("{:#x}", value),
+        Str => match read_c_string(memory, value as usize, MAX_STRING) {
+            Some((text, end)) => quote(&text, !end),
+            None => format!("{:#x}", value),
+        },
+        In(index) => buffer(args[index]),
+        Out => match result {
+            Some(len) if len >= 0 => buffer(len as u64),
+            _ => format!("{:#x}", value),
+        },
+    }
+}
+
+/// Shows a system call like `write(1, "hello\n", 6)`. Once it has returned, `result` holds its
+/// return value, which is appended (` = 6`) and used to show the buffers it filled.
+pub fn format_call(number: u64, args: &[u64; 6], result: Option<i64>, memory: &dyn Memory) -> String {
+    let name = match name(number) {
+        Some(name) => name.to_string(),
+        None => format!("syscall_{}", number),
+    };
+    // when we do not know how many arguments there are, show all that could be
+    let kinds = signature(&name).map_or(&[Hex, Hex, Hex, Hex, Hex, Hex][..], |(kinds, _)| kinds);
+    let shown: Vec<String> = kinds
+        .iter()
+        .zip(args.iter())
+        .map(|(kind, value)| format_argument(*kind, args, *value, result, memory))
+        .collect();
+    let call = format!("{}({})", name, shown.join(", "));
+    match result {
+        Some(value) => format!("{} {}", call, format_result(number, value)),
+        None => call,
+    }
+}
+
+/// Shows the return value of a system call: `= 6`, or `= -1 ENOENT (No such file or directory)`
+/// when it failed.
+pub fn format_result(number: u64, value: i64) -> String {
+    let hex = name(number).and_then(signature).map_or(false, |(_, kind)| matches!(kind, Hex));
+    if value < 0 && value >= -4095 {
+        // the kernel returns -errno on failure
+        let errno = Errno::from_i32(-value as i32);
+        format!("= -1 {:?} ({})", errno, errno.desc())
+    } else if hex {
+        format!("= {:#x}", value)
+    } else {
+        format!("= {}", value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::values::test::FakeMemory;
+
+    #[test]
+    fn test_syscall_names() {
+        assert_eq!(name(1), Some("write"));
+        assert_eq!(name(231), Some("exit_group"));
+        assert_eq!(name(435), Some("clone3"));
+        assert_eq!(name(400), None);
+        assert_eq!(number("write"), Some(1));
+        assert_eq!(number("60"), Some(60));
+        assert_eq!(number("clone3"), Some(435));
+        assert_eq!(number("no_such_call"), None);
+    }
+
+    #[test]
+    fn test_format_call() {
+        // strings are read in 64-byte pieces
+        let mut bytes = b"hi\n\0/etc/passwd\0".to_vec();
+        bytes.resize(0x40, 0);
+        let memory = FakeMemory(0x1000, bytes);
+        let write = [1, 0x1000, 3, 0, 0, 0];
+        assert_eq!(format_call(1, &write, None, &memory), "write(1, \"hi\\n\", 3)");
+        assert_eq!(format_call(1, &write, Some(3), &memory), "write(1, \"hi\\n\", 3) = 3");
+        // the buffer of read is only shown once it has been filled
+        let read = [0, 0x1000, 100, 0, 0, 0];
+        assert_eq!(format_call(0, &read, None, &memory), "read(0, 0x1000, 100)");
+        assert_eq!(format_call(0, &read, Some(2), &memory), "read(0, \"hi\", 100) = 2");
+        let openat = [-100i64 as u64, 0x1004, 0, 0, 0, 0];
+        assert_eq!(
+            format_call(257, &openat, Some(-2), &memory),
+            "openat(-100, \"/etc/passwd\", 0x0, 0x0) = -1 ENOENT (No such file or directory)"
+        );
+        assert_eq!(format_call(12, &[0; 6], Some(0x4000), &memory), "brk(0x0) = 0x4000");
+        let binary = FakeMemory(0x1000, b"\x7fELF\x02\x01\x00\x00".to_vec());
+        let read = [3, 0x1000, 8, 0, 0, 0];
+        assert_eq!(format_call(0, &read, Some(8), &binary), "read(3, \"\\177ELF\\2\\1\\0\\0\", 8) = 8");
+    }
+}
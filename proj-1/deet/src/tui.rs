@@ -0,0 +1,279 @@
+//! `--tui`: a split-screen terminal interface in the spirit of gdb's. The top of the terminal shows
+//! the source around the current line with the breakpoints marked, optionally below the registers
+//! (`layout regs`). The rows underneath are a scrolling region where the usual command line and
+//! output go on as before.
+//!
+//! Everything is drawn with plain ANSI escape sequences, and the cursor is saved and restored
+//! around each redraw, so rustyline does not notice the panes at all.
+
+use crate::dwarf_data::Line;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// rows the command pane keeps at least
+const MIN_COMMAND_ROWS: usize = 6;
+const REGISTER_NAMES: [&str; 18] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags",
+];
+// width of one "name value" column of the register pane
+const REGISTER_WIDTH: usize = 26;
+
+/// What the panes show.
+pub struct View<'a> {
+    /// The line to center the source on
+    pub line: Option<&'a Line>,
+    /// Whether the inferior is stopped at `line`, rather than `line` being just a starting point
+    pub current: bool,
+    /// Lines that have a breakpoint
+    pub breakpoints: &'a [Line],
+    /// Registers of the current thread, if there is an inferior
+    pub registers: Option<libc::user_regs_struct>,
+}
+
+pub struct Tui {
+    rows: usize,
+    cols: usize,
+    // whether the register pane is shown above the source
+    show_registers: bool,
+    // source files read so far, or None if one could not be read
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+/// The size of the terminal on stdout, in rows and columns.
+fn terminal_size() -> io::Result<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } < 0 || size.ws_row == 0 {
+        return Err(io::Error::new(io::ErrorKind::Other, "--tui needs a terminal"));
+    }
+    Ok((size.ws_row as usize, size.ws_col as usize))
+}
+
+/// Fits `text` into `width` columns, expanding tabs.
+fn fit(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    for c in text.chars() {
+        if c == '\t' {
+            let next = (col / 8 + 1) * 8;
+            while col < next && col < width {
+                out.push(' ');
+                col += 1;
+            }
+        } else if !c.is_control() && col < width {
+            out.push(c);
+            col += 1;
+        }
+        if col >= width {
+            break;
+        }
+    }
+    out
+}
+
+/// A title bar across the whole width.
+fn title(text: &str, width: usize) -> String {
+    let text = fit(&format!("-- {} ", text), width);
+    let dashes = width - text.chars().count();
+    format!("{}{}", text, "-".repeat(dashes))
+}
+
+/// The rows of the source pane, `height` of them: `lines` of a file around line `center`, with a
+/// `>` on `current` and a `B` on the lines in `breakpoints`.
+fn source_rows(lines: &[String], center: usize, current: Option<usize>, breakpoints: &[usize], height: usize, width: usize) -> Vec<String> {
+    let first = center.saturating_sub(height / 2).max(1);
+    // show a full pane near the end of the file too
+    let first = first.min((lines.len() + 1).saturating_sub(height).max(1));
+    (first..first + height)
+        .map(|number| match lines.get(number - 1) {
+            Some(text) => {
+                let marker = format!(
+                    "{}{}",
+                    if breakpoints.contains(&number) { "B" } else { " " },
+                    if current == Some(number) { ">" } else { " " },
+                );
+                let row = fit(&format!("{} {:>4} {}", marker, number, text), width);
+                if current == Some(number) {
+                    // reverse video for the current line
+                    format!("\x1b[7m{:<width$}\x1b[0m", row, width = width)
+                } else {
+                    row
+                }
+            }
+            None => String::new(),
+        })
+        .collect()
+}
+
+/// The rows of the register pane, as many registers side by side as fit.
+fn register_rows(regs: &libc::user_regs_struct, width: usize) -> Vec<String> {
+    let values = [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip, regs.eflags,
+    ];
+    let per_row = (width / REGISTER_WIDTH).max(1);
+    let cells: Vec<String> = REGISTER_NAMES
+        .iter()
+        .zip(values.iter())
+        .map(|(name, value)| format!("{:<7}{:<#19x}", name, value))
+        .collect();
+    cells.chunks(per_row).map(|chunk| fit(&chunk.join(""), width)).collect()
+}
+
+impl Tui {
+    /// Splits the terminal, leaving the cursor in the command pane.
+    pub fn new() -> io::Result<Tui> {
+        let (rows, cols) = terminal_size()?;
+        let tui = Tui {
+            rows,
+            cols,
+            show_registers: false,
+            files: HashMap::new(),
+        };
+        // start from a clean screen
+        print!("\x1b[2J");
+        tui.set_scroll_region();
+        io::stdout().flush()?;
+        Ok(tui)
+    }
+
+    /// Shows or hides the register pane. Returns false for an unknown layout.
+    pub fn set_layout(&mut self, layout: &str) -> bool {
+        match layout {
+            "src" => self.show_registers = false,
+            "regs" => self.show_registers = true,
+            _ => return false,
+        }
+        // the panes changed size: clear what was there
+        for row in 1..=self.rows {
+            print!("\x1b[{};1H\x1b[K", row);
+        }
+        self.set_scroll_region();
+        true
+    }
+
+    /// How many rows the register pane takes, title included.
+    fn register_height(&self) -> usize {
+        if self.show_registers {
+            let per_row = (self.cols / REGISTER_WIDTH).max(1);
+            (REGISTER_NAMES.len() + per_row - 1) / per_row + 1
+        } else {
+            0
+        }
+    }
+
+    /// How many rows the panes above the command line take.
+    fn panes_height(&self) -> usize {
+        let registers = self.register_height();
+        let wanted = registers + (self.rows.saturating_sub(registers)) * 3 / 5;
+        wanted.min(self.rows.saturating_sub(MIN_COMMAND_ROWS)).max(registers + 2)
+    }
+
+    /// Confines scrolling to the command pane. This moves the cursor to the top left, so it is
+    /// put back on the bottom row, where the next prompt goes.
+    fn set_scroll_region(&self) {
+        print!("\x1b[{};{}r\x1b[{};1H", self.panes_height() + 1, self.rows, self.rows);
+    }
+
+    fn source(&mut self, path: &str) -> Option<&Vec<String>> {
+        self.files
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let text = std::fs::read_to_string(path).ok()?;
+                Some(text.lines().map(|line| line.to_string()).collect())
+            })
+            .as_ref()
+    }
+
+    /// Redraws the panes.
+    pub fn draw(&mut self, view: &View) -> io::Result<()> {
+        if let Ok((rows, cols)) = terminal_size() {
+            if (rows, cols) != (self.rows, self.cols) {
+                self.rows = rows;
+                self.cols = cols;
+                print!("\x1b[2J");
+                self.set_scroll_region();
+            }
+        }
+        let width = self.cols;
+        let mut panes = Vec::new();
+        if self.show_registers {
+            panes.push(title("Registers", width));
+            match &view.registers {
+                Some(regs) => panes.extend(register_rows(regs, width)),
+                None => panes.push("[ No process ]".to_string()),
+            }
+            panes.resize(self.register_height(), String::new());
+        }
+        let height = self.panes_height() - panes.len() - 1;
+        match view.line {
+            Some(line) => {
+                panes.push(title(&line.file, width));
+                let current = if view.current { Some(line.number) } else { None };
+                let marked: Vec<usize> = view
+                    .breakpoints
+                    .iter()
+                    .filter(|bp| bp.file == line.file)
+                    .map(|bp| bp.number)
+                    .collect();
+                let rows = match self.source(&line.file) {
+                    Some(lines) => source_rows(lines, line.number, current, &marked, height, width),
+                    None => vec![format!("[ Cannot read {} ]", line.file)],
+                };
+                panes.extend(rows);
+            }
+            None => {
+                panes.push(title("Source", width));
+                panes.push("[ No Source Available ]".to_string());
+            }
+        }
+        panes.resize(self.panes_height(), String::new());
+        let mut out = String::from("\x1b7");
+        for (i, row) in panes.iter().enumerate() {
+            out.push_str(&format!("\x1b[{};1H\x1b[K{}", i + 1, row));
+        }
+        out.push_str("\x1b8");
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        // give the whole terminal back
+        print!("\x1b[r\x1b[{};1H", self.rows);
+        let _ = io::stdout().flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source_rows() {
+        let lines: Vec<String> = (1..=20).map(|n| format!("line\t{}", n)).collect();
+        let rows = source_rows(&lines, 10, Some(10), &[8], 5, 20);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], "B     8 line    8");
+        assert_eq!(rows[2], "\x1b[7m >   10 line    10  \x1b[0m");
+        // near the end of the file the pane stays full, and past the end rows are empty
+        let rows = source_rows(&lines, 20, None, &[], 5, 80);
+        assert_eq!(rows[0], "     16 line    16");
+        assert_eq!(rows[4], "     20 line    20");
+        let rows = source_rows(&lines[..2], 1, None, &[], 4, 80);
+        assert_eq!(rows, vec!["      1 line    1", "      2 line    2", "", ""]);
+    }
+
+    #[test]
+    fn test_register_rows() {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401136;
+        let rows = register_rows(&regs, 3 * REGISTER_WIDTH);
+        assert_eq!(rows.len(), 6);
+        assert!(rows[5].starts_with("r15    0x0"));
+        assert!(rows[5].contains("rip    0x401136"));
+        assert!(rows[5].contains("eflags 0x0"));
+    }
+}
@@ -0,0 +1,527 @@
+//! Formats the values of variables from their bytes and their DWARF type. A value is first turned
+//! into a `ValueTree`, by a pretty-printer (see `printers`) when one is registered for its type
+//! and field by field otherwise, and then shown the way gdb prints C values, or the way `{:?}`
+//! would for Rust values.
+
+use crate::dwarf_data::{DwarfData, Member, Type, TypeKind};
+use crate::inferior::Inferior;
+use crate::printers::Printers;
+use std::fmt;
+
+/// How many elements of an array, slice or container to show
+pub const MAX_ELEMENTS: usize = 200;
+/// How deeply nested values are shown before eliding them
+const MAX_DEPTH: usize = 8;
+
+/// The memory values can point into.
+pub trait Memory {
+    fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>>;
+}
+
+impl Memory for Inferior {
+    fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        self.read_memory(addr, len).ok()
+    }
+}
+
+/// A value for display.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueTree {
+    /// A number, a string... already formatted
+    Scalar(String),
+    /// An address followed by what it points to, like gdb's `0x4005f4 "hello"`
+    Pointer(usize, Box<ValueTree>),
+    /// A struct, or a variant of a Rust enum. Rust tuple structs have fields `__0`, `__1`...
+    Fields {
+        name: String,
+        fields: Vec<(String, ValueTree)>,
+        rust: bool,
+    },
+    /// The elements of an array or container; `more` if some were left out. `square` brackets
+    /// are for Rust and lists, braces for C arrays.
+    Elements {
+        elements: Vec<ValueTree>,
+        more: bool,
+        square: bool,
+    },
+}
+
+impl fmt::Display for ValueTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |values: &mut dyn Iterator<Item = String>| values.collect::<Vec<String>>().join(", ");
+        match self {
+            ValueTree::Scalar(text) => write!(f, "{}", text),
+            ValueTree::Pointer(addr, target) => write!(f, "{:#x} {}", addr, target),
+            ValueTree::Fields { fields, rust: false, .. } => {
+                write!(f, "{{{}}}", join(&mut fields.iter().map(|(name, value)| format!("{} = {}", name, value))))
+            }
+            ValueTree::Fields { name, fields, .. } => {
+                let values = || join(&mut fields.iter().map(|(_, value)| value.to_string()));
+                if name.starts_with('(') {
+                    write!(f, "({})", values())
+                } else if fields.is_empty() {
+                    write!(f, "{}", name)
+                } else if fields.iter().all(|(field, _)| field.starts_with("__")) {
+                    write!(f, "{}({})", name, values())
+                } else {
+                    let fields = join(&mut fields.iter().map(|(name, value)| format!("{}: {}", name, value)));
+                    write!(f, "{} {{ {} }}", name, fields)
+                }
+            }
+            ValueTree::Elements {
+                elements,
+                more,
+                square,
+            } => {
+                let mut values: Vec<String> = elements.iter().map(|value| value.to_string()).collect();
+                if *more {
+                    values.push("...".to_string());
+                }
+                if *square {
+                    write!(f, "[{}]", values.join(", "))
+                } else {
+                    write!(f, "{{{}}}", values.join(", "))
+                }
+            }
+        }
+    }
+}
+
+/// Formats a value of type `ty` from its bytes.
+pub fn format_value(
+    debug_data: &DwarfData,
+    printers: &Printers,
+    memory: &dyn Memory,
+    ty: &Type,
+    bytes: &[u8],
+) -> String {
+    Formatter {
+        debug_data,
+        printers,
+        memory,
+    }
+    .tree(ty, bytes, 0)
+    .to_string()
+}
+
+/// Builds value trees; pretty-printers use it to read memory and to build the trees of the
+/// values they contain.
+pub struct Formatter<'a> {
+    pub debug_data: &'a DwarfData,
+    printers: &'a Printers,
+    pub memory: &'a dyn Memory,
+}
+
+/// Reads a little-endian unsigned integer of up to 8 bytes.
+pub fn read_uint(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
+
+/// The bytes of a value of type `ty` that a function returned, from the registers the System V
+/// AMD64 ABI returns it in: each eightbyte of up to two goes in the next of the `integer`
+/// registers (%rax, %rdx), or if it holds only floating point fields, of the `sse` ones (%xmm0,
+/// %xmm1). None if the value is too big for registers, and was returned in memory instead.
+pub fn returned_bytes(debug_data: &DwarfData, ty: &Type, integer: [u64; 2], sse: [u64; 2]) -> Option<Vec<u8>> {
+    if ty.size > 16 {
+        return None;
+    }
+    let mut floating = [true, true];
+    classify(debug_data, ty, 0, &mut floating);
+    let mut integer = integer.iter();
+    let mut sse = sse.iter();
+    let mut bytes = Vec::new();
+    for float in floating.iter().take((ty.size + 7) / 8) {
+        let word = if *float { sse.next() } else { integer.next() };
+        bytes.extend_from_slice(&word.copied().unwrap_or(0).to_le_bytes());
+    }
+    bytes.truncate(ty.size);
+    Some(bytes)
+}
+
+/// Clears the entries of `floating` for the eightbytes of a returned value that a field of type
+/// `ty` at `offset` puts something other than floating point in.
+fn classify(debug_data: &DwarfData, ty: &Type, offset: usize, floating: &mut [bool; 2]) {
+    match &ty.kind {
+        TypeKind::Struct { members, .. } => {
+            for member in members {
+                if let Some(member_type) = debug_data.get_type(member.type_id) {
+                    classify(debug_data, member_type, offset + member.offset, floating);
+                }
+            }
+        }
+        TypeKind::Array { element, count } => {
+            if let Some(element) = debug_data.get_type(*element).filter(|element| element.size > 0) {
+                for i in 0..count.unwrap_or(0).min(16) {
+                    classify(debug_data, element, offset + i * element.size, floating);
+                }
+            }
+        }
+        TypeKind::Alias(Some(aliased)) => {
+            if let Some(aliased) = debug_data.get_type(*aliased) {
+                classify(debug_data, aliased, offset, floating);
+            }
+        }
+        _ if ty.is_float() => {}
+        _ => {
+            let last = (offset + ty.size.max(1) - 1) / 8;
+            for float in floating.iter_mut().take(last + 1).skip(offset / 8) {
+                *float = false;
+            }
+        }
+    }
+}
+
+/// Reads the NUL-terminated string at `addr`, up to `max` bytes of it. Also returns whether the
+/// terminator was found within them. None if nothing can be read there.
+pub fn read_c_string(memory: &dyn Memory, addr: usize, max: usize) -> Option<(Vec<u8>, bool)> {
+    // read in pieces that do not cross a page, which may be the last one mapped
+    let mut text = Vec::new();
+    let mut end = false;
+    while !end && text.len() < max {
+        let start = addr + text.len();
+        let chunk = match memory.read(start, 64 - start % 64) {
+            Some(chunk) => chunk,
+            None if text.is_empty() => return None,
+            None => break,
+        };
+        match chunk.iter().position(|&byte| byte == 0) {
+            Some(nul) => {
+                text.extend_from_slice(&chunk[..nul]);
+                end = true;
+            }
+            None => text.extend_from_slice(&chunk),
+        }
+    }
+    text.truncate(max);
+    Some((text, end))
+}
+
+/// The name of a Rust type the way `Debug` shows it: without its path or generic parameters.
+fn short_name(name: &str) -> &str {
+    let name = match name.find('<') {
+        Some(generics) => &name[..generics],
+        None => name,
+    };
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+impl<'a> Formatter<'a> {
+    /// The tree of a value of type `ty`, `depth` levels down from the variable being shown.
+    pub fn tree(&self, ty: &Type, bytes: &[u8], depth: usize) -> ValueTree {
+        if depth > MAX_DEPTH {
+            return ValueTree::Scalar("...".to_string());
+        }
+        if let Some(printer) = self.printers.find(&ty.name) {
+            if let Some(tree) = printer.to_tree(self, ty, bytes, depth) {
+                return tree;
+            }
+        }
+        let unknown = || ValueTree::Scalar(format!("<{} bytes>", ty.size));
+        match &ty.kind {
+            TypeKind::Base(_) => ValueTree::Scalar(ty.format_value(bytes)),
+            TypeKind::Alias(Some(aliased)) => match self.debug_data.get_type(*aliased) {
+                Some(aliased) => self.tree(aliased, bytes, depth),
+                None => unknown(),
+            },
+            // references and boxes are shown by their target, like `Debug` does
+            TypeKind::Pointer(Some(pointee)) if ty.rust && !ty.name.starts_with('*') => {
+                let addr = read_uint(bytes) as usize;
+                let target = self.debug_data.get_type(*pointee).and_then(|pointee| {
+                    let bytes = self.memory.read(addr, pointee.size)?;
+                    Some(self.tree(pointee, &bytes, depth + 1))
+                });
+                target.unwrap_or_else(|| ValueTree::Scalar(format!("{:#x}", addr)))
+            }
+            TypeKind::Pointer(_) => ValueTree::Scalar(format!("{:#x}", read_uint(bytes))),
+            TypeKind::Array { element, count } => match self.debug_data.get_type(*element) {
+                Some(element) => {
+                    let mut tree = self.elements(element, bytes, count.unwrap_or(0), depth);
+                    if let ValueTree::Elements { square, .. } = &mut tree {
+                        *square = ty.rust;
+                    }
+                    tree
+                }
+                None => unknown(),
+            },
+            TypeKind::Enum(values) => {
+                let shift = 64 - 8 * ty.size.min(8).max(1) as u32;
+                let value = ((read_uint(bytes) << shift) as i64) >> shift;
+                match values.iter().find(|(_, enumerator)| *enumerator == value) {
+                    Some((name, _)) => ValueTree::Scalar(name.clone()),
+                    None => ValueTree::Scalar(format!("{}", value)),
+                }
+            }
+            TypeKind::Struct {
+                variants: Some(part), ..
+            } => {
+                let discriminant = part.discriminant.as_ref().and_then(|member| self.member(member, bytes));
+                let variant = match discriminant {
+                    Some((discr_type, discr_bytes)) => {
+                        let value = read_uint(&discr_bytes[..discr_type.size.min(discr_bytes.len())]);
+                        part.variants
+                            .iter()
+                            .find(|variant| variant.discr_value == Some(value))
+                            .or_else(|| part.variants.iter().find(|variant| variant.discr_value.is_none()))
+                    }
+                    None => part.variants.first(),
+                };
+                match variant.and_then(|variant| Some((variant, self.member(&variant.member, bytes)?))) {
+                    Some((variant, (variant_type, variant_bytes))) => {
+                        self.fields(&variant.member.name, variant_type, variant_bytes, depth)
+                    }
+                    None => ValueTree::Scalar("<unknown variant>".to_string()),
+                }
+            }
+            TypeKind::Struct { .. } => self.fields(short_name(&ty.name), ty, bytes, depth),
+            _ => unknown(),
+        }
+    }
+
+    /// The type and bytes of a member of a value.
+    pub fn member<'b>(&self, member: &Member, bytes: &'b [u8]) -> Option<(&'a Type, &'b [u8])> {
+        let ty = self.debug_data.get_type(member.type_id)?;
+        let end = member.offset + ty.size;
+        if end > bytes.len() {
+            return None;
+        }
+        Some((ty, &bytes[member.offset..end]))
+    }
+
+    /// The member of a struct value called `name`.
+    pub fn field<'b>(&self, ty: &Type, bytes: &'b [u8], name: &str) -> Option<(&'a Type, &'b [u8])> {
+        match &ty.kind {
+            TypeKind::Struct { members, .. } => {
+                let member = members.iter().find(|member| member.name == name)?;
+                self.member(member, bytes)
+            }
+            _ => None,
+        }
+    }
+
+    /// The first pointer in a value, looking into its members, with the type it points to. The
+    /// buffer of a `Vec` is a `*const T` wrapped in a few layers of structs (`RawVec`, `Unique`,
+    /// `NonNull`...) that depend on the Rust version.
+    pub fn find_pointer(&self, ty: &Type, bytes: &[u8], depth: usize) -> Option<(Option<&'a Type>, usize)> {
+        match &ty.kind {
+            TypeKind::Pointer(pointee) => {
+                let pointee = pointee.and_then(|pointee| self.debug_data.get_type(pointee));
+                Some((pointee, read_uint(bytes) as usize))
+            }
+            TypeKind::Struct { members, .. } if depth < MAX_DEPTH => members.iter().find_map(|member| {
+                let (member_type, member_bytes) = self.member(member, bytes)?;
+                self.find_pointer(member_type, member_bytes, depth + 1)
+            }),
+            TypeKind::Alias(Some(aliased)) => {
+                self.find_pointer(self.debug_data.get_type(*aliased)?, bytes, depth + 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// `count` values of type `element`, of which `bytes` may hold only the first
+    /// `MAX_ELEMENTS`.
+    pub fn elements(&self, element: &Type, bytes: &[u8], count: usize, depth: usize) -> ValueTree {
+        let mut elements = Vec::new();
+        for i in 0..count.min(MAX_ELEMENTS) {
+            let start = i * element.size;
+            if start + element.size > bytes.len() {
+                break;
+            }
+            elements.push(self.tree(element, &bytes[start..start + element.size], depth + 1));
+        }
+        ValueTree::Elements {
+            more: count > elements.len(),
+            elements,
+            square: true,
+        }
+    }
+
+    /// `count` values of type `element` read from the inferior at `addr`.
+    pub fn elements_at(&self, element: &Type, addr: usize, count: usize, depth: usize) -> Option<ValueTree> {
+        let bytes = self.memory.read(addr, element.size * count.min(MAX_ELEMENTS))?;
+        Some(self.elements(element, &bytes, count, depth))
+    }
+
+    /// The fields of a struct, or of a variant of a Rust enum, leaving out the one called `skip`.
+    pub fn fields_except(&self, name: &str, ty: &Type, bytes: &[u8], depth: usize, skip: &str) -> ValueTree {
+        let members = match &ty.kind {
+            TypeKind::Struct { members, .. } => &members[..],
+            _ => &[],
+        };
+        let fields = members
+            .iter()
+            .filter(|member| member.name != skip)
+            .map(|member| {
+                let value = match self.member(member, bytes) {
+                    Some((member_type, member_bytes)) => self.tree(member_type, member_bytes, depth + 1),
+                    None => ValueTree::Scalar("<unknown type>".to_string()),
+                };
+                (member.name.clone(), value)
+            })
+            .collect();
+        ValueTree::Fields {
+            name: name.to_string(),
+            fields,
+            rust: ty.rust,
+        }
+    }
+
+    fn fields(&self, name: &str, ty: &Type, bytes: &[u8], depth: usize) -> ValueTree {
+        self.fields_except(name, ty, bytes, depth, "")
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::dwarf_data::{TypeId, Variant, VariantPart};
+    use std::collections::HashMap;
+
+    /// Memory holding `.1` at address `.0`.
+    pub struct FakeMemory(pub usize, pub Vec<u8>);
+
+    impl Memory for FakeMemory {
+        fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+            let start = addr.checked_sub(self.0)?;
+            self.1.get(start..start + len).map(|bytes| bytes.to_vec())
+        }
+    }
+
+    pub fn make_type(name: &str, size: usize, kind: TypeKind, rust: bool) -> Type {
+        Type {
+            name: name.to_string(),
+            size,
+            kind,
+            rust,
+        }
+    }
+
+    pub fn member(name: &str, offset: usize, type_id: TypeId) -> Member {
+        Member {
+            name: name.to_string(),
+            offset,
+            type_id,
+        }
+    }
+
+    pub fn structure(members: Vec<Member>) -> TypeKind {
+        TypeKind::Struct {
+            members,
+            variants: None,
+            template_params: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_rust_values() {
+        const I32: TypeId = 1;
+        const USIZE: TypeId = 2;
+        const U8: TypeId = 3;
+        const PTR_I32: TypeId = 4;
+        const PTR_U8: TypeId = 5;
+        const RAW_VEC: TypeId = 6;
+        const VEC_U8: TypeId = 7;
+        const SOME: TypeId = 8;
+        const NONE: TypeId = 9;
+        let rust_type = |name: &str, size: usize, kind: TypeKind| make_type(name, size, kind, true);
+        let mut types = HashMap::new();
+        types.insert(I32, rust_type("i32", 4, TypeKind::Base(0x05)));
+        types.insert(USIZE, rust_type("usize", 8, TypeKind::Base(0x07)));
+        types.insert(U8, rust_type("u8", 1, TypeKind::Base(0x07)));
+        types.insert(PTR_I32, rust_type("*const i32", 8, TypeKind::Pointer(Some(I32))));
+        types.insert(PTR_U8, rust_type("*const u8", 8, TypeKind::Pointer(Some(U8))));
+        // the buffer pointer is untyped; the element type comes from the template parameter
+        types.insert(
+            RAW_VEC,
+            rust_type("alloc::raw_vec::RawVec<u8>", 16, structure(vec![member("ptr", 0, PTR_U8), member("cap", 8, USIZE)])),
+        );
+        let vec = |element: TypeId| {
+            rust_type(
+                "alloc::vec::Vec<i32, alloc::alloc::Global>",
+                24,
+                TypeKind::Struct {
+                    members: vec![member("buf", 0, RAW_VEC), member("len", 16, USIZE)],
+                    variants: None,
+                    template_params: vec![element],
+                },
+            )
+        };
+        types.insert(VEC_U8, vec(U8));
+        types.insert(SOME, rust_type("core::option::Option<i32>::Some", 8, structure(vec![member("__0", 4, I32)])));
+        types.insert(NONE, rust_type("core::option::Option<i32>::None", 8, structure(Vec::new())));
+        let debug_data = DwarfData::from_types(types);
+        let printers = Printers::new();
+
+        let heap: Vec<u8> = vec![1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, b'h', b'i', b'"'];
+        let memory = FakeMemory(0x1000, heap);
+        let format = |ty: &Type, bytes: &[u8]| format_value(&debug_data, &printers, &memory, ty, bytes);
+        let words = |words: &[u64]| -> Vec<u8> { words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect() };
+
+        assert_eq!(format(&vec(I32), &words(&[0x1000, 4, 3])), "[1, 2, 3]");
+        let string = rust_type("alloc::string::String", 24, structure(vec![member("vec", 0, VEC_U8)]));
+        assert_eq!(format(&string, &words(&[0x100c, 3, 3])), "\"hi\\\"\"");
+        let str_ref = rust_type("&str", 16, structure(vec![member("data_ptr", 0, PTR_U8), member("length", 8, USIZE)]));
+        assert_eq!(format(&str_ref, &words(&[0x100c, 2])), "\"hi\"");
+        let slice = rust_type("&[i32]", 16, structure(vec![member("data_ptr", 0, PTR_I32), member("length", 8, USIZE)]));
+        assert_eq!(format(&slice, &words(&[0x1004, 2])), "[2, 3]");
+
+        let option = rust_type(
+            "core::option::Option<i32>",
+            8,
+            TypeKind::Struct {
+                members: Vec::new(),
+                variants: Some(VariantPart {
+                    discriminant: Some(member("", 0, I32)),
+                    variants: vec![
+                        Variant {
+                            discr_value: Some(0),
+                            member: member("None", 0, NONE),
+                        },
+                        Variant {
+                            discr_value: Some(1),
+                            member: member("Some", 0, SOME),
+                        },
+                    ],
+                }),
+                template_params: vec![I32],
+            },
+        );
+        assert_eq!(format(&option, &[1, 0, 0, 0, 42, 0, 0, 0]), "Some(42)");
+        assert_eq!(format(&option, &[0, 0, 0, 0, 42, 0, 0, 0]), "None");
+        let point = rust_type("demo::Point", 8, structure(vec![member("x", 0, I32), member("y", 4, I32)]));
+        assert_eq!(format(&point, &[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]), "Point { x: 1, y: -1 }");
+        let c_point = Type { rust: false, ..point };
+        assert_eq!(format(&c_point, &[1, 0, 0, 0, 2, 0, 0, 0]), "{x = 1, y = 2}");
+    }
+
+    #[test]
+    fn test_returned_bytes() {
+        const INT: TypeId = 1;
+        const DOUBLE: TypeId = 2;
+        const FLOAT: TypeId = 3;
+        let mut types = HashMap::new();
+        types.insert(INT, make_type("int", 4, TypeKind::Base(0x05), false));
+        types.insert(DOUBLE, make_type("double", 8, TypeKind::Base(0x04), false));
+        types.insert(FLOAT, make_type("float", 4, TypeKind::Base(0x04), false));
+        let debug_data = DwarfData::from_types(types);
+        let returned = |ty: &Type| returned_bytes(&debug_data, ty, [0x11, 0x22], [0x33, 0x44]);
+
+        assert_eq!(returned(debug_data.get_type(INT).unwrap()), Some(vec![0x11, 0, 0, 0]));
+        assert_eq!(returned(debug_data.get_type(DOUBLE).unwrap()), Some(0x33u64.to_le_bytes().to_vec()));
+        // {int, double}: %rax, then %xmm0
+        let mixed = make_type("mixed", 16, structure(vec![member("i", 0, INT), member("d", 8, DOUBLE)]), false);
+        let mut expected = 0x11u64.to_le_bytes().to_vec();
+        expected.extend_from_slice(&0x33u64.to_le_bytes());
+        assert_eq!(returned(&mixed), Some(expected));
+        // two floats share an eightbyte, and an int next to a float makes it an integer one
+        let floats = make_type("floats", 8, structure(vec![member("x", 0, FLOAT), member("y", 4, FLOAT)]), false);
+        assert_eq!(returned(&floats), Some(0x33u64.to_le_bytes().to_vec()));
+        let int_float = make_type("int_float", 8, structure(vec![member("i", 0, INT), member("f", 4, FLOAT)]), false);
+        assert_eq!(returned(&int_float), Some(0x11u64.to_le_bytes().to_vec()));
+        let big = make_type("big", 24, structure(Vec::new()), false);
+        assert_eq!(returned(&big), None);
+    }
+}
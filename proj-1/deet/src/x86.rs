@@ -0,0 +1,366 @@
+//! Just enough x86-64 instruction decoding to tell which memory an instruction may write, for
+//! recording (see `record`). Every memory operand counts as a possible write: telling loads from
+//! stores would take the semantics of each opcode, and reading a little memory that does not
+//! change is cheap.
+
+/// Bytes from the address of a memory operand that are taken as written. The widest ordinary
+/// stores (AVX-512) are 64 bytes.
+const OPERAND_WINDOW: usize = 64;
+
+/// The same for the instructions that save processor state: fxsave, xsave and friends, fnsave.
+const STATE_WINDOW: usize = 16384;
+
+/// Bytes below the stack pointer that are taken as written, for push, call and enter.
+const STACK_WINDOW: usize = 64;
+
+/// The bit of %eflags that makes string instructions go down in memory.
+const DIRECTION_FLAG: u64 = 1 << 10;
+
+/// General purpose register `n`, numbered as in ModRM and SIB bytes.
+fn register(regs: &libc::user_regs_struct, n: u8) -> u64 {
+    match n {
+        0 => regs.rax,
+        1 => regs.rcx,
+        2 => regs.rdx,
+        3 => regs.rbx,
+        4 => regs.rsp,
+        5 => regs.rbp,
+        6 => regs.rsi,
+        7 => regs.rdi,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        _ => regs.r15,
+    }
+}
+
+/// Whether `opcode` of opcode map `map` (0: one byte, 1: 0f, 2: 0f 38, 3: 0f 3a) has a ModRM
+/// byte.
+fn has_modrm(map: u8, opcode: u8) -> bool {
+    match map {
+        0 => match opcode {
+            0x00..=0x3f => opcode & 7 < 4,
+            0x63
+            | 0x69
+            | 0x6b
+            | 0x80..=0x8f
+            | 0xc0
+            | 0xc1
+            | 0xc6
+            | 0xc7
+            | 0xd0..=0xd3
+            | 0xd8..=0xdf => true,
+            0xf6 | 0xf7 | 0xfe | 0xff => true,
+            _ => false,
+        },
+        1 => !matches!(
+            opcode,
+            0x04..=0x09
+                | 0x0b
+                | 0x0e
+                | 0x30..=0x37
+                | 0x77
+                | 0x80..=0x8f
+                | 0xa0..=0xa2
+                | 0xa8..=0xaa
+                | 0xc8..=0xcf
+        ),
+        _ => true,
+    }
+}
+
+/// The size of the immediate operand that follows the ModRM byte and displacement. `reg` is
+/// the reg field of the ModRM byte, which extends the opcode of some instructions.
+fn immediate_size(map: u8, opcode: u8, reg: u8, operand16: bool) -> usize {
+    let full = if operand16 { 2 } else { 4 };
+    match (map, opcode) {
+        (0, 0x80) | (0, 0x83) | (0, 0x6b) | (0, 0xc0) | (0, 0xc1) | (0, 0xc6) => 1,
+        (0, 0x81) | (0, 0x69) | (0, 0xc7) => full,
+        (0, 0xf6) if reg < 2 => 1,
+        (0, 0xf7) if reg < 2 => full,
+        (1, 0x70..=0x73) | (1, 0xa4) | (1, 0xac) | (1, 0xba) | (1, 0xc2) | (1, 0xc4..=0xc6) => 1,
+        (3, _) => 1,
+        _ => 0,
+    }
+}
+
+/// Reads the little-endian signed number of `size` bytes at `code[pos..]`.
+fn signed(code: &[u8], pos: usize, size: usize) -> Option<i64> {
+    let bytes = code.get(pos..pos + size)?;
+    Some(match size {
+        1 => bytes[0] as i8 as i64,
+        4 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+        _ => 0,
+    })
+}
+
+/// The `[start, end)` ranges of memory that the instruction `code`, about to run with `regs`,
+/// may write. None if that can not be told from the instruction, as for system calls, which may
+/// write anywhere.
+pub fn written_ranges(code: &[u8], regs: &libc::user_regs_struct) -> Option<Vec<(usize, usize)>> {
+    let rsp = regs.rsp as usize;
+    let mut ranges = vec![(rsp.wrapping_sub(STACK_WINDOW), rsp)];
+
+    let mut pos = 0;
+    let mut operand16 = false;
+    let mut address32 = false;
+    let mut segment_base = 0;
+    let mut repeat = false;
+    loop {
+        match *code.get(pos)? {
+            0x66 => operand16 = true,
+            0x67 => address32 = true,
+            0x64 => segment_base = regs.fs_base,
+            0x65 => segment_base = regs.gs_base,
+            0xf2 | 0xf3 => repeat = true,
+            0x26 | 0x2e | 0x36 | 0x3e | 0xf0 => {}
+            _ => break,
+        }
+        pos += 1;
+    }
+    let address = |address: u64| {
+        let address = if address32 {
+            address & 0xffff_ffff
+        } else {
+            address
+        };
+        address.wrapping_add(segment_base) as usize
+    };
+
+    // the REX.X and REX.B bits, which extend the index and base registers
+    let mut rex = 0;
+    if *code.get(pos)? & 0xf0 == 0x40 {
+        rex = code[pos] & 3;
+        pos += 1;
+    }
+    let mut evex = false;
+    let map = match *code.get(pos)? {
+        0xc5 => {
+            pos += 2;
+            1
+        }
+        // VEX and EVEX keep X and B inverted, in bits 6 and 5 of their first payload byte
+        0xc4 => {
+            let payload = *code.get(pos + 1)?;
+            rex = (!payload >> 5) & 3;
+            pos += 3;
+            payload & 0x1f
+        }
+        0x62 => {
+            let payload = *code.get(pos + 1)?;
+            rex = (!payload >> 5) & 3;
+            evex = true;
+            pos += 4;
+            payload & 7
+        }
+        0x0f => match *code.get(pos + 1)? {
+            0x38 => {
+                pos += 2;
+                2
+            }
+            0x3a => {
+                pos += 2;
+                3
+            }
+            _ => {
+                pos += 1;
+                1
+            }
+        },
+        _ => 0,
+    };
+    let opcode = *code.get(pos)?;
+    pos += 1;
+
+    match (map, opcode) {
+        // syscall, sysenter, int
+        (1, 0x05) | (1, 0x34) | (0, 0xcd) => return None,
+        // scatter stores, whose addresses come from a vector register
+        (2, 0xa0..=0xa3) if evex => return None,
+        // stos, movs and ins write at %rdi, always in the es segment, which moves up or down
+        // (with the direction flag set) by an element per repetition
+        (0, 0xa4) | (0, 0xa5) | (0, 0xaa) | (0, 0xab) | (0, 0x6c) | (0, 0x6d) => {
+            let mask = if address32 { 0xffff_ffff } else { u64::MAX };
+            let rdi = (regs.rdi & mask) as usize;
+            // word, doubleword or quadword elements are all taken as quadwords
+            let element = if opcode & 1 == 0 { 1 } else { 8 };
+            let count = if repeat {
+                (regs.rcx & mask) as usize
+            } else {
+                1
+            };
+            let length = count.saturating_mul(element);
+            if regs.eflags & DIRECTION_FLAG == 0 {
+                ranges.push((rdi, rdi.saturating_add(length)));
+            } else {
+                ranges.push((
+                    rdi.saturating_sub(length.saturating_sub(element)),
+                    rdi.saturating_add(element),
+                ));
+            }
+            return Some(ranges);
+        }
+        // mov to an absolute address
+        (0, 0xa2) | (0, 0xa3) => {
+            let size = if address32 { 4 } else { 8 };
+            let bytes = code.get(pos..pos + size)?;
+            let mut absolute = [0; 8];
+            absolute[..size].copy_from_slice(bytes);
+            let start = address(u64::from_le_bytes(absolute));
+            ranges.push((start, start.wrapping_add(8)));
+            return Some(ranges);
+        }
+        _ => {}
+    }
+    if !has_modrm(map, opcode) {
+        return Some(ranges);
+    }
+
+    let modrm = *code.get(pos)?;
+    pos += 1;
+    let (mode, reg, rm) = (modrm >> 6, (modrm >> 3) & 7, modrm & 7);
+    if mode == 3 {
+        // a register operand
+        return Some(ranges);
+    }
+    let mut base_address: u64 = 0;
+    let mut rip_relative = false;
+    let mut displacement_size = match mode {
+        1 => 1,
+        2 => 4,
+        _ => 0,
+    };
+    if rm == 4 {
+        let sib = *code.get(pos)?;
+        pos += 1;
+        let index = ((sib >> 3) & 7) | (rex & 2) << 2;
+        let base = (sib & 7) | (rex & 1) << 3;
+        if index != 4 {
+            base_address = register(regs, index).wrapping_shl((sib >> 6) as u32);
+        }
+        if sib & 7 == 5 && mode == 0 {
+            displacement_size = 4;
+        } else {
+            base_address = base_address.wrapping_add(register(regs, base));
+        }
+    } else if rm == 5 && mode == 0 {
+        rip_relative = true;
+        displacement_size = 4;
+    } else {
+        base_address = register(regs, rm | (rex & 1) << 3);
+    }
+    let displacement = signed(code, pos, displacement_size)?;
+    if rip_relative {
+        let length = pos + displacement_size + immediate_size(map, opcode, reg, operand16);
+        base_address = regs.rip.wrapping_add(length as u64);
+    }
+    // EVEX scales a one-byte displacement by the size of the operand, which depends on the
+    // instruction; any size up to 64 bytes is allowed for
+    let (low, high) = if evex && mode == 1 {
+        (displacement.min(0) * 64, displacement.max(0) * 64)
+    } else {
+        (displacement, displacement)
+    };
+    let window = match (map, opcode) {
+        (1, 0xae) | (1, 0xc7) | (0, 0xd9) | (0, 0xdd) => STATE_WINDOW,
+        _ => OPERAND_WINDOW,
+    };
+    let start = address(base_address.wrapping_add(low as u64));
+    let end = address(base_address.wrapping_add(high as u64)).wrapping_add(window);
+    ranges.push((start, end));
+    Some(ranges)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn regs() -> libc::user_regs_struct {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401000;
+        regs.rsp = 0x7ffc0000;
+        regs.rbp = 0x7ffc0100;
+        regs.rax = 0x4052a0;
+        regs.rdi = 0x405300;
+        regs.r12 = 0x10;
+        regs.fs_base = 0x7f0000000000;
+        regs
+    }
+
+    /// The range an instruction writes besides the area below the stack pointer.
+    fn written(code: &[u8]) -> Option<Vec<(usize, usize)>> {
+        let mut ranges = written_ranges(code, &regs())?;
+        assert_eq!(ranges.remove(0), (0x7ffc0000 - STACK_WINDOW, 0x7ffc0000));
+        Some(ranges)
+    }
+
+    #[test]
+    fn test_written_ranges() {
+        // push %rbp, ret: only the stack
+        assert_eq!(written(&[0x55]), Some(vec![]));
+        assert_eq!(written(&[0xc3]), Some(vec![]));
+        // mov %eax,%edx: registers only
+        assert_eq!(written(&[0x89, 0xc2]), Some(vec![]));
+        // movl $0x1,(%rax)
+        assert_eq!(
+            written(&[0xc7, 0x00, 0x01, 0, 0, 0]),
+            Some(vec![(0x4052a0, 0x4052a0 + 64)])
+        );
+        // mov %edi,-0x14(%rbp)
+        assert_eq!(
+            written(&[0x89, 0x7d, 0xec]),
+            Some(vec![(0x7ffc0100 - 0x14, 0x7ffc0100 - 0x14 + 64)])
+        );
+        // mov %eax,0x2ee6(%rip), 6 bytes long
+        assert_eq!(
+            written(&[0x89, 0x05, 0xe6, 0x2e, 0, 0]),
+            Some(vec![(0x401006 + 0x2ee6, 0x401006 + 0x2ee6 + 64)])
+        );
+        // movl $0x5,0x10(%rip): the immediate counts towards the length
+        let code = [0xc7, 0x05, 0x10, 0, 0, 0, 5, 0, 0, 0];
+        assert_eq!(written(&code), Some(vec![(0x40101a, 0x40101a + 64)]));
+        // mov %rax,(%rax,%r12,4), with REX.WX
+        assert_eq!(
+            written(&[0x4a, 0x89, 0x04, 0xa0]),
+            Some(vec![(0x4052e0, 0x4052e0 + 64)])
+        );
+        // mov %rax,%fs:0x28
+        let code = [0x64, 0x48, 0x89, 0x04, 0x25, 0x28, 0, 0, 0];
+        assert_eq!(
+            written(&code),
+            Some(vec![(0x7f0000000028, 0x7f0000000028 + 64)])
+        );
+        // vmovdqu %ymm0,(%rax)
+        assert_eq!(
+            written(&[0xc5, 0xfe, 0x7f, 0x00]),
+            Some(vec![(0x4052a0, 0x4052a0 + 64)])
+        );
+        // vmovdqu64 %zmm0,0x40(%rax), with the displacement compressed to 1
+        let code = [0x62, 0xf1, 0xfe, 0x48, 0x7f, 0x40, 0x01];
+        assert_eq!(written(&code), Some(vec![(0x4052a0, 0x4052a0 + 128)]));
+        // stos %eax,(%rdi), and rep stos %al,(%rdi) with %rcx elements
+        assert_eq!(written(&[0xab]), Some(vec![(0x405300, 0x405308)]));
+        let mut regs = regs();
+        regs.rcx = 0x100000;
+        let ranges = written_ranges(&[0xf3, 0xaa], &regs).unwrap();
+        assert_eq!(ranges[1], (0x405300, 0x505300));
+        // rep movsq downwards, with the direction flag set
+        regs.eflags |= DIRECTION_FLAG;
+        regs.rcx = 4;
+        let ranges = written_ranges(&[0xf3, 0x48, 0xa5], &regs).unwrap();
+        assert_eq!(ranges[1], (0x405300 - 24, 0x405308));
+        // xsave (%rax) saves a lot
+        assert_eq!(
+            written(&[0x0f, 0xae, 0x20]),
+            Some(vec![(0x4052a0, 0x4052a0 + STATE_WINDOW)])
+        );
+        // system calls may write anywhere
+        assert_eq!(written(&[0x0f, 0x05]), None);
+        // a truncated instruction can not be decoded
+        assert_eq!(written(&[0x89]), None);
+    }
+}
@@ -0,0 +1,68 @@
+//! ctrl+c handling, which depends on the signal dispositions of the whole deet process and so is
+//! tested on the real binary. The samples have to be built first (`make`).
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn deet(args: &[&str]) -> Child {
+    let home = std::env::temp_dir().join(format!("deet-interrupt-{}", std::process::id()));
+    std::fs::create_dir_all(&home).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_deet"))
+        .args(args)
+        .env("HOME", &home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+fn wait_for_exit(child: &mut Child, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if child.try_wait().unwrap().is_some() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+#[test]
+fn test_ctrl_c_interrupts_the_inferior() {
+    let mut child = deet(&["samples/sleepy_print"]);
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "run 10").unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    writeln!(stdin, "quit").unwrap();
+
+    assert!(wait_for_exit(&mut child, Duration::from_secs(5)), "deet did not stop the program");
+    let mut output = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut output).unwrap();
+    assert!(output.contains("Child stopped (signal SIGINT)"), "{}", output);
+    assert!(output.contains("Killing running inferior"), "{}", output);
+}
+
+#[test]
+fn test_gdbserver_ignores_ctrl_c() {
+    let mut child = deet(&["--gdbserver", "127.0.0.1:0", "samples/function_calls"]);
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let address = line.trim().trim_start_matches("Listening on ").to_string();
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert!(child.try_wait().unwrap().is_none(), "the server died of SIGINT");
+
+    // a client can still connect; hanging up ends the session
+    let stream = TcpStream::connect(&address).unwrap();
+    drop(stream);
+    assert!(wait_for_exit(&mut child, Duration::from_secs(5)));
+}